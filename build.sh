@@ -9,4 +9,5 @@ as --32 out/code.s -o out/code.o
 # -m elf_i386 means emulate for x86
 # Uncomment this if you wanna define _start yourself
 # ld -m elf_i386 -dynamic-linker /lib/ld-linux.so.2 out/code.o -o a.out -lc
-gcc -m32 out/code.o -o a.out
+# runtime/ has the code used by --sanitize=memory
+gcc -m32 out/code.o runtime/memory_sanitizer.c -o a.out
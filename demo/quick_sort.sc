@@ -1,5 +1,5 @@
 // Quick sort
-int qs(int left, int right, int *arr) {
+unsafe int qs(int left, int right, int *arr) {
     int i = left;
     int j = right;
     int mid = (left + right) / 2;
@@ -26,13 +26,13 @@ int main(int arg) {
     int i = 0;
     int *arr = allocate(4*n);
     while (i < n) {
-        scanf("%d", arr+(i));
+        unsafe { scanf("%d", arr+(i)); }
         i = i + 1; 
     }
-    qs(0, n-1, arr);
+    unsafe { qs(0, n-1, arr); }
     i = 0;
     while (i < n) {
-        printf("%d ", *(arr+i));
+        unsafe { printf("%d ", *(arr+i)); }
         i = i + 1;
     }
     printf("\n");
@@ -0,0 +1,167 @@
+// Runtime for programs compiled with --sanitize=memory.
+//
+// The compiler routes allocate() and free() here, and checks every pointer
+// dereference with __msan_check_access. Each allocation is surrounded by
+// redzones and remembered, along with where it was allocated and freed.
+// Freed memory is never reused, so that we can still tell what a pointer
+// to it pointed to.
+//
+// Before each call the compiler stores the location of the code making it
+// in __msan_line and __msan_function, so that errors can say where they
+// happened.
+
+#include <limits.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+#define REDZONE_SIZE 16
+#define SANITIZER_EXIT_CODE 72
+
+int __msan_line;
+const char* __msan_function;
+
+struct location {
+    int line;
+    const char* function;
+};
+
+struct allocation {
+    char* start;
+    int size;
+    int freed;
+    struct location allocated_at;
+    struct location freed_at;
+    struct allocation* next;
+};
+
+static struct allocation* allocations;
+static int leak_check_registered;
+
+static struct location current_location(void) {
+    struct location loc = { __msan_line, __msan_function };
+    return loc;
+}
+
+static void print_location(const char* what, struct location loc) {
+    if (loc.line == 0) {
+        fprintf(stderr, "  %s in %s (code added by the compiler)\n",
+                what, loc.function);
+    } else {
+        fprintf(stderr, "  %s in %s at line %d\n", what, loc.function,
+                loc.line);
+    }
+}
+
+static void report_and_exit(const char* error, struct allocation* a) {
+    fflush(stdout);
+    fprintf(stderr, "memory sanitizer: %s\n", error);
+    print_location("happened", current_location());
+    if (a != NULL) {
+        print_location("memory was allocated", a->allocated_at);
+        if (a->freed) {
+            print_location("memory was freed", a->freed_at);
+        }
+    }
+    _exit(SANITIZER_EXIT_CODE);
+}
+
+// The allocation addr points into, counting its redzones
+static struct allocation* find_allocation(char* addr) {
+    struct allocation* a;
+    for (a = allocations; a != NULL; a = a->next) {
+        if (addr >= a->start - REDZONE_SIZE &&
+            addr < a->start + a->size + REDZONE_SIZE) {
+            return a;
+        }
+    }
+    return NULL;
+}
+
+static void check_leaks(void) {
+    struct allocation* a;
+    int leaked = 0;
+    fflush(stdout);
+    for (a = allocations; a != NULL; a = a->next) {
+        if (!a->freed) {
+            fprintf(stderr, "memory sanitizer: %d bytes leaked\n", a->size);
+            print_location("memory was allocated", a->allocated_at);
+            leaked = 1;
+        }
+    }
+
+    if (leaked) {
+        _exit(SANITIZER_EXIT_CODE);
+    }
+}
+
+void* __msan_calloc(int count, int size) {
+    struct allocation* a;
+    int total;
+
+    // Like calloc, fail if the size doesn't fit, and let the program check
+    // for NULL
+    if (count < 0 || size < 0 ||
+        (size > 0 && count > (INT_MAX - 2 * REDZONE_SIZE) / size)) {
+        return NULL;
+    }
+
+    a = malloc(sizeof(struct allocation));
+    total = count * size;
+    char* block = calloc(1, total + 2 * REDZONE_SIZE);
+    if (a == NULL || block == NULL) {
+        report_and_exit("out of memory", NULL);
+    }
+
+    if (!leak_check_registered) {
+        atexit(check_leaks);
+        leak_check_registered = 1;
+    }
+
+    a->start = block + REDZONE_SIZE;
+    a->size = total;
+    a->freed = 0;
+    a->allocated_at = current_location();
+    a->next = allocations;
+    allocations = a;
+    return a->start;
+}
+
+void* __msan_malloc(int size) {
+    return __msan_calloc(1, size);
+}
+
+void __msan_free(char* p) {
+    struct allocation* a;
+    if (p == NULL) {
+        return;
+    }
+
+    a = find_allocation(p);
+    if (a == NULL || a->start != p) {
+        report_and_exit("free of memory that wasn't returned by allocate",
+                        a);
+    }
+    if (a->freed) {
+        report_and_exit("double free", a);
+    }
+
+    a->freed = 1;
+    a->freed_at = current_location();
+}
+
+void __msan_check_access(char* addr, int size) {
+    struct allocation* a = find_allocation(addr);
+    if (a == NULL) {
+        // Not on the heap, like the address of a local variable
+        return;
+    }
+
+    if (addr < a->start || addr + size > a->start + a->size) {
+        report_and_exit("access out of the bounds of an allocation", a);
+    }
+    if (a->freed) {
+        report_and_exit("use after free", a);
+    }
+}
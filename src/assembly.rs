@@ -1,7 +1,7 @@
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum RegisterVal {
-    EAX, EBX, ECX,
-    AL, BL, CL,
+    EAX, EBX, ECX, EDX, ESI, EDI,
+    AL, CL,
     ESP, EBP
 }
 
@@ -18,10 +18,9 @@ pub enum Operand {
     Dereference(RegisterVal, i32),
     IntConstant(i32),
     Variable(String),
-    VariableStatic(&'static str),
 }
 
-
+#[derive(PartialEq, Debug)]
 pub enum Instruction {
     Add(Operand, Operand),
     Call(String),
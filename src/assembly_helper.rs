@@ -38,21 +38,10 @@ pub fn free_stack(size: i32) -> Instruction {
         Register(ESP))
 }
 
-/// Return a register beside the given register
-pub fn register_other_than(r: &RegisterVal) -> RegisterVal {
-    match *r {
-        EAX | AL => EBX,
-        EBX | BL => EAX,
-        ECX | CL => EBX,
-        ESP | EBP => panic!("What are you doing with this function?"),
-    }
-}
-
 /// Return the low bit version of the given register
 pub fn get_low_byte(o: &RegisterVal) -> RegisterVal {
     match *o {
         EAX => AL,
-        EBX => BL,
         ECX => CL,
         _ => panic!("Register doesn't have low byte"),
     }
@@ -12,10 +12,12 @@ fn reg_to_str(r: &RegisterVal) -> String {
         EAX => "%eax".to_string(),
         EBX => "%ebx".to_string(),
         ECX => "%ecx".to_string(),
+        EDX => "%edx".to_string(),
+        ESI => "%esi".to_string(),
+        EDI => "%edi".to_string(),
         EBP => "%ebp".to_string(),
         ESP => "%esp".to_string(),
         AL => "%al".to_string(),
-        BL => "%bl".to_string(),
         CL => "%cl".to_string(),
     }
 }
@@ -25,7 +27,6 @@ fn op_to_str(o: &Operand) -> String {
     match *o {
         Register(r) => reg_to_str(&r),
         IntConstant(i) => "$".to_string() + &i.to_string(),
-        VariableStatic(n) => "$".to_string() + &n.to_string(),
         Variable(ref s) => "$".to_string() + &s.clone(),
         Dereference(ref e, offset) => format!("{}({})", offset, reg_to_str(e)),
     }
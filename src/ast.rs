@@ -1,7 +1,7 @@
 use std::collections::VecDeque;
 use std::collections::HashMap;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FunctionCall {
     pub name: String,
     pub args_exprs: Vec<AstExpressionNode>,
@@ -14,6 +14,30 @@ pub enum PointerType {
 
     // Is freed when it goes out of scope, and can be "moved"
     Owned,
+
+    // Borrowed from a local or an owned_pointer. Can't be written through,
+    // stored in a struct, or outlive what it was borrowed from.
+    Ref,
+
+    // Like Ref, but can be written through. The owned_pointer it was
+    // borrowed from can't be used while it's around.
+    MutRef,
+
+    // Reference counted. Points to a header holding the count, followed
+    // by the value itself. The value is destroyed when the last copy goes
+    // out of scope.
+    Shared,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ArrayType {
+    // Made by new_array(T, n). Owns its elements, and is freed and moved
+    // like an owned_pointer.
+    Owned,
+
+    // A borrowed part of an array, made by a[lo..hi]. Can't outlive the
+    // array it was taken from.
+    View,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -22,6 +46,8 @@ pub enum VarType {
     Char,
     Pointer(PointerType, Box<VarType>),
     Struct(String),
+    // Stored as the address of the first element followed by the length
+    Array(ArrayType, Box<VarType>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -29,6 +55,13 @@ pub struct FunctionType {
     pub return_type: VarType,
     pub arg_types: Vec<VarType>,
     pub is_var_args: bool,
+
+    // Can only be called from unsafe code
+    pub is_unsafe: bool,
+
+    // Only called by the code the compiler adds, which may pass it any
+    // pointer
+    pub is_internal: bool,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -45,7 +78,7 @@ pub enum BinaryOp {
     CompareNotEqual,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Expression {
     Value(i32),
     SizeOf(VarType),
@@ -58,22 +91,32 @@ pub enum Expression {
     // For now we'll say this is ok though.
     Dereference(Box<AstExpressionNode>),
     FieldAccess(Box<AstExpressionNode>, String),
+    // a[i], checked against the length of a at runtime
+    Index(Box<AstExpressionNode>, Box<AstExpressionNode>),
+    // a[lo..hi]
+    Slice(Box<AstExpressionNode>, Box<AstExpressionNode>,
+          Box<AstExpressionNode>),
 }
 
 // Part of AST. The "typ" field is set when we go to the type checker/annotator
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AstExpressionNode {
     pub expr: Expression,
     // Before type checking, it's None.
     // If it passes the type checker, it's guaranteed to be Some.
     pub typ: Option<VarType>,
+
+    // The line of the source the expression is on. 0 for expressions
+    // added by the compiler.
+    pub line: usize,
 }
 
 impl AstExpressionNode {
     pub fn new(ex: Expression) -> AstExpressionNode {
         AstExpressionNode {
             expr: ex,
-            typ: None
+            typ: None,
+            line: 0,
         }
     }
 }
@@ -87,6 +130,8 @@ pub enum Statement {
     Let(String, VarType, Option<AstExpressionNode>),
     Assign(AstExpressionNode, AstExpressionNode),
     Call(FunctionCall),
+    // unsafe { ... }, with the line it starts on
+    Unsafe(usize, Block),
 }
 
 #[derive(Debug)]
@@ -102,6 +147,12 @@ pub struct Function {
     pub args: Vec<String>,
 
     pub fn_type: FunctionType,
+
+    // The line of the unsafe qualifier, if the function has one
+    pub unsafe_line: Option<usize>,
+
+    // Marked inline, so it's inlined at -O1 whatever its size
+    pub is_inline: bool,
 }
 
 #[derive(Debug, Clone)]
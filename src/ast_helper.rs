@@ -1,6 +1,13 @@
+use ast::AstExpressionNode;
+use ast::BinaryOp;
+use ast::Block;
+use ast::Expression;
+use ast::Statement;
 use ast::VarType;
 use ast::VarType::*;
 
+use std::collections::HashSet;
+
 
 /// Return true if the type is Pointer
 pub fn is_pointer(typ: &VarType) -> bool {
@@ -10,3 +17,142 @@ pub fn is_pointer(typ: &VarType) -> bool {
         false
     }
 }
+
+/// The line of the source a statement is on, or 0 if it was added by the
+/// compiler. A call has no line of its own, so it's on its first
+/// argument's.
+pub fn get_statement_line(stmt: &Statement) -> usize {
+    match *stmt {
+        Statement::Return(ref expr) | Statement::Print(ref expr) |
+        Statement::Let(_, _, Some(ref expr)) | Statement::Assign(ref expr, _) |
+        Statement::If(ref expr, _, _) | Statement::While(ref expr, _) => expr.line,
+        Statement::Call(ref fn_call) => {
+            fn_call.args_exprs.first().map_or(0, |arg| arg.line)
+        }
+        Statement::Unsafe(line, _) => line,
+        Statement::Let(_, _, None) => 0,
+    }
+}
+
+/// Return the value of an expression made only of int constants, like
+/// 2 * 3 - 1. Return None if it's not constant, or can't be computed
+/// (like 1 / 0).
+pub fn get_constant_value(expr_node: &AstExpressionNode) -> Option<i32> {
+    match expr_node.expr {
+        Expression::Value(v) => Some(v),
+        Expression::BinaryOp(op, ref l, ref r) => {
+            let l = get_constant_value(l)?;
+            let r = get_constant_value(r)?;
+            evaluate_binary_op(op, l, r)
+        }
+        _ => None,
+    }
+}
+
+/// Return l op r the way the generated code computes it, wrapping around
+/// on overflow. Return None for divisions the CPU can't do (by 0, and
+/// -2147483648 / -1).
+pub fn evaluate_binary_op(op: BinaryOp, l: i32, r: i32) -> Option<i32> {
+    match op {
+        BinaryOp::Plus => Some(l.wrapping_add(r)),
+        BinaryOp::Minus => Some(l.wrapping_sub(r)),
+        BinaryOp::Multiply => Some(l.wrapping_mul(r)),
+        BinaryOp::Divide => l.checked_div(r),
+        BinaryOp::CompareEqual => Some((l == r) as i32),
+        BinaryOp::CompareGreater => Some((l > r) as i32),
+        BinaryOp::CompareLess => Some((l < r) as i32),
+        BinaryOp::CompareGreaterOrEqual => Some((l >= r) as i32),
+        BinaryOp::CompareLessOrEqual => Some((l <= r) as i32),
+        BinaryOp::CompareNotEqual => Some((l != r) as i32),
+    }
+}
+
+/// Return true if control can never continue past this statement
+/// (every path through it ends in a return)
+pub fn statement_always_returns(stmt: &Statement) -> bool {
+    match *stmt {
+        Statement::Return(_) => true,
+        Statement::Unsafe(_, ref block) => block_always_returns(block),
+        Statement::If(_, ref then_block, Some(ref else_block)) => {
+            block_always_returns(then_block) && block_always_returns(else_block)
+        }
+        _ => false,
+    }
+}
+
+/// Return true if every path through the block ends in a return
+pub fn block_always_returns(block: &Block) -> bool {
+    block.statements.iter().any(statement_always_returns)
+}
+
+/// Add the variables whose address is taken with & in the expression to
+/// names. They can be read and written through pointers.
+pub fn collect_address_taken(expr_node: &AstExpressionNode,
+                             names: &mut HashSet<String>) {
+    match expr_node.expr {
+        Expression::Reference(ref expr) => {
+            if let Expression::Variable(ref name) = expr.expr {
+                names.insert(name.clone());
+            }
+            collect_address_taken(expr, names);
+        }
+        Expression::BinaryOp(_, ref l, ref r) |
+        Expression::Index(ref l, ref r) => {
+            collect_address_taken(l, names);
+            collect_address_taken(r, names);
+        }
+        Expression::Call(ref fn_call) => {
+            for arg in fn_call.args_exprs.iter() {
+                collect_address_taken(arg, names);
+            }
+        }
+        Expression::Dereference(ref expr) |
+        Expression::FieldAccess(ref expr, _) => {
+            collect_address_taken(expr, names);
+        }
+        Expression::Slice(ref array, ref start, ref end) => {
+            collect_address_taken(array, names);
+            collect_address_taken(start, names);
+            collect_address_taken(end, names);
+        }
+        Expression::Value(_) | Expression::SizeOf(_) |
+        Expression::Variable(_) | Expression::StringValue(_) => {}
+    }
+}
+
+/// Add the variables whose address is taken anywhere in the block to
+/// names
+pub fn collect_address_taken_block(block: &Block, names: &mut HashSet<String>) {
+    for stmt in block.statements.iter() {
+        match *stmt {
+            Statement::Return(ref expr) | Statement::Print(ref expr) |
+            Statement::Let(_, _, Some(ref expr)) => {
+                collect_address_taken(expr, names);
+            }
+            Statement::Let(_, _, None) => {}
+            Statement::Assign(ref left, ref right) => {
+                collect_address_taken(left, names);
+                collect_address_taken(right, names);
+            }
+            Statement::Call(ref fn_call) => {
+                for arg in fn_call.args_exprs.iter() {
+                    collect_address_taken(arg, names);
+                }
+            }
+            Statement::If(ref condition, ref then_block, ref else_block_opt) => {
+                collect_address_taken(condition, names);
+                collect_address_taken_block(then_block, names);
+                if let Some(ref else_block) = *else_block_opt {
+                    collect_address_taken_block(else_block, names);
+                }
+            }
+            Statement::While(ref condition, ref block) => {
+                collect_address_taken(condition, names);
+                collect_address_taken_block(block, names);
+            }
+            Statement::Unsafe(_, ref block) => {
+                collect_address_taken_block(block, names);
+            }
+        }
+    }
+}
@@ -0,0 +1,417 @@
+use ast::AstExpressionNode;
+use ast::BinaryOp;
+use ast::Block;
+use ast::Expression;
+use ast::Function;
+use ast::Statement;
+
+use dataflow::solve;
+use dataflow::DataflowProblem;
+use dataflow::Direction;
+use dataflow::Liveness;
+
+use std::collections::BTreeSet;
+
+// The control flow graph of a function body, for analyses that need to
+// know which statements can run after which. Every statement gets a node,
+// except for if and while, whose node is their condition, and unsafe,
+// whose statements are inlined. Statements after a return get nodes with
+// no predecessors.
+
+pub type NodeId = usize;
+
+pub const ENTRY: NodeId = 0;
+pub const EXIT: NodeId = 1;
+
+/// The edges of a graph, without what's in the nodes, which is all the
+/// dataflow and dominator code needs
+pub struct FlowGraph {
+    pub successors: Vec<Vec<NodeId>>,
+    pub predecessors: Vec<Vec<NodeId>>,
+    pub entry: NodeId,
+    pub exit: NodeId,
+}
+
+impl FlowGraph {
+    pub fn new(entry: NodeId, exit: NodeId) -> FlowGraph {
+        FlowGraph {
+            successors: Vec::new(),
+            predecessors: Vec::new(),
+            entry,
+            exit,
+        }
+    }
+
+    pub fn add_node(&mut self) -> NodeId {
+        self.successors.push(Vec::new());
+        self.predecessors.push(Vec::new());
+        self.successors.len() - 1
+    }
+
+    pub fn add_edge(&mut self, from: NodeId, to: NodeId) {
+        if !self.successors[from].contains(&to) {
+            self.successors[from].push(to);
+            self.predecessors[to].push(from);
+        }
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.successors.len()
+    }
+
+    /// The nodes that can be reached from the entry
+    pub fn get_reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.num_nodes()];
+        let mut stack = vec![self.entry];
+        reachable[self.entry] = true;
+        while let Some(node) = stack.pop() {
+            for &next in self.successors[node].iter() {
+                if !reachable[next] {
+                    reachable[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        reachable
+    }
+}
+
+pub enum CfgNode<'a> {
+    Entry,
+    Exit,
+    Statement(&'a Statement),
+    // The condition of an if or a while
+    Condition(&'a AstExpressionNode),
+}
+
+pub struct Cfg<'a> {
+    pub nodes: Vec<CfgNode<'a>>,
+    pub graph: FlowGraph,
+}
+
+impl<'a> Cfg<'a> {
+    fn add_node(&mut self, node: CfgNode<'a>, preds: &[NodeId]) -> NodeId {
+        let id = self.graph.add_node();
+        self.nodes.push(node);
+        for &pred in preds.iter() {
+            self.graph.add_edge(pred, id);
+        }
+        id
+    }
+
+    // Add the block's statements, starting from preds. Returns the nodes
+    // control leaves the block from.
+    fn add_block(&mut self, block: &'a Block, preds: Vec<NodeId>) -> Vec<NodeId> {
+        let mut preds = preds;
+        for stmt in block.statements.iter() {
+            preds = self.add_statement(stmt, preds);
+        }
+        preds
+    }
+
+    fn add_statement(&mut self, stmt: &'a Statement,
+                     preds: Vec<NodeId>) -> Vec<NodeId> {
+        match *stmt {
+            Statement::If(ref condition, ref then_block, ref else_block_opt) => {
+                let cond = self.add_node(CfgNode::Condition(condition), &preds);
+                let mut after = self.add_block(then_block, vec![cond]);
+                match *else_block_opt {
+                    Some(ref else_block) => {
+                        after.extend(self.add_block(else_block, vec![cond]));
+                    }
+                    None => after.push(cond),
+                }
+                after
+            }
+            Statement::While(ref condition, ref block) => {
+                let cond = self.add_node(CfgNode::Condition(condition), &preds);
+                for end in self.add_block(block, vec![cond]) {
+                    self.graph.add_edge(end, cond);
+                }
+                vec![cond]
+            }
+            Statement::Unsafe(_, ref block) => self.add_block(block, preds),
+            Statement::Return(_) => {
+                let node = self.add_node(CfgNode::Statement(stmt), &preds);
+                self.graph.add_edge(node, EXIT);
+                vec![]
+            }
+            Statement::Print(_) | Statement::Let(_, _, _) |
+            Statement::Assign(_, _) | Statement::Call(_) => {
+                vec![self.add_node(CfgNode::Statement(stmt), &preds)]
+            }
+        }
+    }
+}
+
+/// Build the control flow graph of the function's body
+pub fn build_cfg(function: &Function) -> Cfg<'_> {
+    let mut cfg = Cfg {
+        nodes: Vec::new(),
+        graph: FlowGraph::new(ENTRY, EXIT),
+    };
+    cfg.add_node(CfgNode::Entry, &[]);
+    cfg.add_node(CfgNode::Exit, &[]);
+
+    // Falling off the end of the function goes to the exit too
+    for end in cfg.add_block(&function.statements, vec![ENTRY]) {
+        cfg.graph.add_edge(end, EXIT);
+    }
+    cfg
+}
+
+// Dominators, found as a forward dataflow problem: a node is dominated by
+// itself and by whatever dominates all of its predecessors
+struct Dominators<'a> {
+    graph: &'a FlowGraph,
+}
+
+impl<'a> DataflowProblem for Dominators<'a> {
+    type Fact = BTreeSet<NodeId>;
+
+    fn get_direction(&self) -> Direction {
+        Direction::Forward
+    }
+
+    fn get_boundary_fact(&self) -> BTreeSet<NodeId> {
+        BTreeSet::new()
+    }
+
+    fn get_initial_fact(&self) -> BTreeSet<NodeId> {
+        (0..self.graph.num_nodes()).collect()
+    }
+
+    fn meet(&self, a: &BTreeSet<NodeId>, b: &BTreeSet<NodeId>) -> BTreeSet<NodeId> {
+        a.intersection(b).cloned().collect()
+    }
+
+    fn transfer(&self, node: NodeId, fact: &BTreeSet<NodeId>) -> BTreeSet<NodeId> {
+        let mut fact = fact.clone();
+        fact.insert(node);
+        fact
+    }
+}
+
+/// The immediate dominator of every node: the last node every path from
+/// the entry goes through before getting to it. None for the entry, and
+/// for nodes that can't be reached.
+pub fn compute_dominator_tree(graph: &FlowGraph) -> Vec<Option<NodeId>> {
+    let reachable = graph.get_reachable();
+    let result = solve(&Dominators { graph }, graph);
+
+    (0..graph.num_nodes()).map(|node| {
+        if !reachable[node] {
+            return None;
+        }
+        // The closest strict dominator is the one dominated by all the
+        // others, which is the one with the most dominators
+        let dominators = &result.fact_out[node];
+        dominators.iter()
+            .filter(|&&d| d != node)
+            .max_by_key(|&&d| result.fact_out[d].len())
+            .cloned()
+    }).collect()
+}
+
+/// Whether every path from the entry to b goes through a, given the
+/// dominator tree
+pub fn dominates(dominator_tree: &[Option<NodeId>], a: NodeId, b: NodeId) -> bool {
+    let mut node = Some(b);
+    while let Some(current) = node {
+        if current == a {
+            return true;
+        }
+        node = dominator_tree[current];
+    }
+    false
+}
+
+fn get_operator(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Plus => "+",
+        BinaryOp::Minus => "-",
+        BinaryOp::Multiply => "*",
+        BinaryOp::Divide => "/",
+        BinaryOp::CompareEqual => "==",
+        BinaryOp::CompareGreater => ">",
+        BinaryOp::CompareLess => "<",
+        BinaryOp::CompareGreaterOrEqual => ">=",
+        BinaryOp::CompareLessOrEqual => "<=",
+        BinaryOp::CompareNotEqual => "!=",
+    }
+}
+
+// Write an expression back out as source, for labelling nodes
+fn describe_expr(expr_node: &AstExpressionNode) -> String {
+    match expr_node.expr {
+        Expression::Value(v) => v.to_string(),
+        Expression::SizeOf(ref typ) => format!("sizeof({:?})", typ),
+        Expression::Variable(ref name) => name.clone(),
+        Expression::StringValue(ref s) => s.clone(),
+        Expression::BinaryOp(op, ref l, ref r) => {
+            format!("({} {} {})", describe_expr(l), get_operator(op),
+                    describe_expr(r))
+        }
+        Expression::Call(ref fn_call) => {
+            let args: Vec<String> = fn_call.args_exprs.iter()
+                .map(describe_expr)
+                .collect();
+            format!("{}({})", fn_call.name, args.join(", "))
+        }
+        Expression::Reference(ref expr) => format!("&{}", describe_expr(expr)),
+        Expression::Dereference(ref expr) => format!("*{}", describe_expr(expr)),
+        Expression::FieldAccess(ref expr, ref field) => {
+            format!("{}.{}", describe_expr(expr), field)
+        }
+        Expression::Index(ref array, ref index) => {
+            format!("{}[{}]", describe_expr(array), describe_expr(index))
+        }
+        Expression::Slice(ref array, ref start, ref end) => {
+            format!("{}[{}..{}]", describe_expr(array), describe_expr(start),
+                    describe_expr(end))
+        }
+    }
+}
+
+/// Write the node back out as source
+pub fn describe_node(node: &CfgNode) -> String {
+    match *node {
+        CfgNode::Entry => "entry".to_string(),
+        CfgNode::Exit => "exit".to_string(),
+        CfgNode::Condition(expr) => format!("if {}", describe_expr(expr)),
+        CfgNode::Statement(stmt) => match *stmt {
+            Statement::Return(ref expr) => format!("return {}", describe_expr(expr)),
+            Statement::Print(ref expr) => format!("print {}", describe_expr(expr)),
+            Statement::Let(ref name, ref typ, Some(ref expr)) => {
+                format!("{:?} {} = {}", typ, name, describe_expr(expr))
+            }
+            Statement::Let(ref name, ref typ, None) => format!("{:?} {}", typ, name),
+            Statement::Assign(ref left, ref right) => {
+                format!("{} = {}", describe_expr(left), describe_expr(right))
+            }
+            Statement::Call(ref fn_call) => {
+                let args: Vec<String> = fn_call.args_exprs.iter()
+                    .map(describe_expr)
+                    .collect();
+                format!("{}({})", fn_call.name, args.join(", "))
+            }
+            Statement::If(_, _, _) | Statement::While(_, _) |
+            Statement::Unsafe(_, _) => panic!("Not a node of its own"),
+        }
+    }
+}
+
+fn escape_dot_label(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// The function's control flow graph in Graphviz's format. Each node is
+/// labelled with the variables live before it, and dashed edges go from
+/// every node to its immediate dominator.
+pub fn cfg_to_dot(function: &Function) -> String {
+    let cfg = build_cfg(function);
+    let dominator_tree = compute_dominator_tree(&cfg.graph);
+    let liveness = solve(&Liveness::new(&cfg), &cfg.graph);
+
+    let mut dot = format!("digraph \"{}\" {{\n", function.name);
+    for (i, node) in cfg.nodes.iter().enumerate() {
+        let live: Vec<&str> = liveness.fact_in[i].iter()
+            .map(|name| name.as_str())
+            .collect();
+        let label = format!("{}\\nlive: {}",
+                            escape_dot_label(&describe_node(node)),
+                            live.join(", "));
+        dot.push_str(&format!("  n{} [shape=box, label=\"{}\"];\n", i, label));
+    }
+    for (i, successors) in cfg.graph.successors.iter().enumerate() {
+        for next in successors.iter() {
+            dot.push_str(&format!("  n{} -> n{};\n", i, next));
+        }
+    }
+    for (i, idom) in dominator_tree.iter().enumerate() {
+        if let Some(idom) = *idom {
+            dot.push_str(&format!("  n{} -> n{} [style=dashed, color=gray];\n",
+                                  i, idom));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parser::parse;
+    use scanner::get_tokens;
+
+    const COUNT_SOURCE: &str = "int count (int n)
+{
+    int x = 0;
+    while (x < n) {
+        x = x + 1;
+    }
+    return x;
+}
+";
+
+    #[test]
+    fn dominators_of_a_diamond() {
+        // 0 -> 1 -> 3, 0 -> 2 -> 3, 3 -> 4, and 5 can't be reached
+        let mut graph = FlowGraph::new(0, 4);
+        for _ in 0..6 {
+            graph.add_node();
+        }
+        for &(from, to) in [(0, 1), (0, 2), (1, 3), (2, 3), (3, 4), (5, 4)].iter() {
+            graph.add_edge(from, to);
+        }
+
+        let dominator_tree = compute_dominator_tree(&graph);
+        assert_eq!(dominator_tree, vec![None, Some(0), Some(0), Some(0), Some(3), None]);
+        assert!(dominates(&dominator_tree, 0, 4));
+        assert!(dominates(&dominator_tree, 3, 3));
+        assert!(!dominates(&dominator_tree, 1, 3));
+        assert!(!dominates(&dominator_tree, 5, 4));
+    }
+
+    #[test]
+    fn dominators_of_a_loop() {
+        let prog = parse(&mut get_tokens(COUNT_SOURCE));
+        let cfg = build_cfg(&prog.functions[0]);
+        let labels: Vec<String> = cfg.nodes.iter().map(describe_node).collect();
+        assert_eq!(labels, vec!["entry", "exit", "Int x = 0", "if (x < n)",
+                                "x = (x + 1)", "return x"]);
+
+        // The body and the return both come after the condition
+        let dominator_tree = compute_dominator_tree(&cfg.graph);
+        assert_eq!(dominator_tree,
+                   vec![None, Some(5), Some(0), Some(2), Some(3), Some(3)]);
+    }
+
+    #[test]
+    fn dot_output() {
+        let prog = parse(&mut get_tokens(COUNT_SOURCE));
+        let expected = "digraph \"count\" {
+  n0 [shape=box, label=\"entry\\nlive: n\"];
+  n1 [shape=box, label=\"exit\\nlive: \"];
+  n2 [shape=box, label=\"Int x = 0\\nlive: n\"];
+  n3 [shape=box, label=\"if (x < n)\\nlive: n, x\"];
+  n4 [shape=box, label=\"x = (x + 1)\\nlive: n, x\"];
+  n5 [shape=box, label=\"return x\\nlive: x\"];
+  n0 -> n2;
+  n2 -> n3;
+  n3 -> n4;
+  n3 -> n5;
+  n4 -> n3;
+  n5 -> n1;
+  n1 -> n5 [style=dashed, color=gray];
+  n2 -> n0 [style=dashed, color=gray];
+  n3 -> n2 [style=dashed, color=gray];
+  n4 -> n3 [style=dashed, color=gray];
+  n5 -> n3 [style=dashed, color=gray];
+}
+";
+        assert_eq!(cfg_to_dot(&prog.functions[0]), expected);
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::collections::HashSet;
 
 pub struct CodeBlock {
@@ -11,3 +12,38 @@ impl CodeBlock {
         }
     }
 }
+
+/// The scope that the address of a local variable (&x) is valid in.
+/// depth is the nesting level of the block that declared the variable,
+/// so a larger depth means a shorter lived region.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScopeRegion {
+    pub depth: usize,
+    pub variable: String,
+    // Where the address was taken
+    pub line: usize,
+}
+
+/// Return whichever of the two regions ends first
+pub fn innermost_region(a: Option<ScopeRegion>,
+                        b: Option<ScopeRegion>) -> Option<ScopeRegion> {
+    match (a, b) {
+        (Some(a), Some(b)) => if a.depth >= b.depth { Some(a) } else { Some(b) },
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Combine what two paths know about where variables point: each may
+/// point into whichever region ends first
+pub fn merge_regions(a: &HashMap<String, ScopeRegion>,
+                     b: &HashMap<String, ScopeRegion>) -> HashMap<String, ScopeRegion> {
+    let mut merged = a.clone();
+    for (name, region) in b.iter() {
+        let old = merged.remove(name);
+        if let Some(region) = innermost_region(old, Some(region.clone())) {
+            merged.insert(name.clone(), region);
+        }
+    }
+    merged
+}
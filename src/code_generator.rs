@@ -1,6 +1,6 @@
-use ast::Program;
+use ir::IrProgram;
 
 /// This is an interface
 pub trait GeneratesCode {
-    fn generate_code(&mut self, &Program) -> String;
+    fn generate_code(&mut self, &IrProgram) -> String;
 }
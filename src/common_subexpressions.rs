@@ -0,0 +1,183 @@
+use ir::Instr;
+use ir::IrBinOp;
+use ir::IrFunction;
+use ir::IrProgram;
+use ir::IrType;
+use ir::SlotId;
+use ir::VReg;
+use ir::Value;
+
+use std::collections::HashMap;
+
+// Replaces instructions which compute something already computed earlier
+// in their block with a copy of the register holding it (done at -O1 and
+// above). Only what's in the block is known, so nothing has to be merged
+// where paths join.
+//
+// A computation is forgotten when a register it reads, or the register
+// holding it, is written. Any store or call may write any memory, so loads
+// are forgotten at each of them.
+
+// What an instruction computes, with its registers replaced by the ones
+// they're copies of
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+enum Expression {
+    Trunc(Operand),
+    // Whether the operation is checked for overflow is part of it, as a
+    // checked one can't be replaced by an unchecked one
+    BinOp(IrBinOp, Operand, Operand, bool),
+    Load(IrType, Operand, i32),
+    FrameAddr(SlotId),
+}
+
+// A Value which can be hashed
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+enum Operand {
+    Reg(VReg),
+    Const(i32),
+    Global(String),
+}
+
+impl Expression {
+    fn reads(&self, reg: VReg) -> bool {
+        match *self {
+            Expression::Trunc(ref src) => *src == Operand::Reg(reg),
+            Expression::BinOp(_, ref left, ref right, _) => {
+                *left == Operand::Reg(reg) || *right == Operand::Reg(reg)
+            }
+            Expression::Load(_, ref addr, _) => *addr == Operand::Reg(reg),
+            Expression::FrameAddr(_) => false,
+        }
+    }
+}
+
+// What's known about the registers at a point of a block
+struct Available {
+    // The register holding each expression
+    expressions: HashMap<Expression, VReg>,
+    // The registers which are copies of another value
+    copies: HashMap<VReg, Operand>,
+}
+
+impl Available {
+    fn new() -> Available {
+        Available {
+            expressions: HashMap::new(),
+            copies: HashMap::new(),
+        }
+    }
+
+    fn get_operand(&self, value: &Value) -> Operand {
+        match *value {
+            Value::Reg(reg) => match self.copies.get(&reg) {
+                Some(operand) => operand.clone(),
+                None => Operand::Reg(reg),
+            },
+            Value::Const(c) => Operand::Const(c),
+            Value::Global(ref label) => Operand::Global(label.clone()),
+        }
+    }
+
+    fn get_expression(&self, instr: &Instr) -> Option<Expression> {
+        match *instr {
+            Instr::Trunc { ref src, .. } => Some(Expression::Trunc(self.get_operand(src))),
+            Instr::BinOp { op, ref left, ref right, overflow_check, .. } => {
+                Some(Expression::BinOp(op, self.get_operand(left),
+                                       self.get_operand(right),
+                                       overflow_check.is_some()))
+            }
+            Instr::Load { typ, ref addr, offset, .. } => {
+                Some(Expression::Load(typ, self.get_operand(addr), offset))
+            }
+            Instr::FrameAddr { slot, .. } => Some(Expression::FrameAddr(slot)),
+            Instr::Copy { .. } | Instr::Store { .. } | Instr::Call { .. } => None,
+        }
+    }
+
+    // Forget everything the register being written invalidates
+    fn kill(&mut self, reg: VReg) {
+        self.expressions.retain(|expr, &mut holder| holder != reg && !expr.reads(reg));
+        self.copies.retain(|&copy, operand| copy != reg && *operand != Operand::Reg(reg));
+    }
+
+    fn kill_loads(&mut self) {
+        self.expressions.retain(|expr, _| !matches!(*expr, Expression::Load(_, _, _)));
+    }
+}
+
+// Returns how many instructions were replaced
+fn eliminate_in_function(fun: &mut IrFunction) -> usize {
+    let mut num_replaced = 0;
+    for block in fun.blocks.iter_mut() {
+        let mut available = Available::new();
+        for (instr, line) in block.instrs.iter_mut().zip(block.lines.iter_mut()) {
+            let expr = available.get_expression(instr);
+            let dests = instr.get_dests();
+
+            // A checked operation can't be replaced by an unchecked one, but
+            // an unchecked one can by a checked one, which didn't fail
+            let unchecked = match expr {
+                Some(Expression::BinOp(op, ref left, ref right, true)) => {
+                    Some(Expression::BinOp(op, left.clone(), right.clone(), false))
+                }
+                _ => None,
+            };
+            let holder = expr.as_ref()
+                .and_then(|expr| available.expressions.get(expr))
+                .cloned();
+            if let (Some(holder), &[dest]) = (holder, dests.as_slice()) {
+                if holder != dest && fun.vreg_types[holder] == fun.vreg_types[dest] {
+                    // The copy isn't what the source computes, so it's not
+                    // reported if it's removed
+                    *instr = Instr::Copy { dest, src: Value::Reg(holder) };
+                    *line = 0;
+                    num_replaced += 1;
+                }
+            }
+
+            match *instr {
+                Instr::Store { .. } | Instr::Call { .. } => available.kill_loads(),
+                _ => {}
+            }
+            for &dest in dests.iter() {
+                available.kill(dest);
+            }
+
+            // Remember what the register now holds, unless it was one of
+            // what it was computed from
+            match *instr {
+                Instr::Copy { dest, ref src } => {
+                    let operand = available.get_operand(src);
+                    if operand != Operand::Reg(dest) {
+                        available.copies.insert(dest, operand);
+                    }
+                }
+                _ => {
+                    if let (Some(expr), &[dest]) = (expr, dests.as_slice()) {
+                        if !expr.reads(dest) {
+                            if let Some(unchecked) = unchecked {
+                                available.expressions.entry(unchecked).or_insert(dest);
+                            }
+                            available.expressions.entry(expr).or_insert(dest);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    num_replaced
+}
+
+/// Reuse the values computed earlier in the same block. Returns how many
+/// instructions were replaced in each function.
+pub fn eliminate_common_subexpressions(program: &mut IrProgram) -> Vec<String> {
+    let mut report = Vec::new();
+    for fun in program.functions.iter_mut() {
+        let num_replaced = eliminate_in_function(fun);
+        if num_replaced > 0 {
+            report.push(format!("values reused in {}: {}",
+                                fun.name, num_replaced));
+        }
+    }
+    report
+}
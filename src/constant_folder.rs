@@ -0,0 +1,266 @@
+use ast::AstExpressionNode;
+use ast::BinaryOp;
+use ast::Block;
+use ast::Expression;
+use ast::Function;
+use ast::FunctionCall;
+use ast::Program;
+use ast::Statement;
+use ast::VarType;
+
+use ast_helper::collect_address_taken_block;
+use ast_helper::evaluate_binary_op;
+use ast_helper::is_pointer;
+
+use assembly_helper::get_mtype_size;
+
+use representation_manager::RepresentationManager;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// Computes at compile time whatever can be (done at -O1 and above).
+// Operators on constants, and sizeof, are replaced with their value, and
+// int locals are replaced with the constant they hold, as long as no
+// branch or loop could have changed it since.
+//
+// Overflow wraps around the way it does at run time, unless
+// --overflow-checks is on, in which case it's left for the check to
+// catch. Divisions by zero are never folded either, so the run time check
+// still catches them.
+
+// Add every variable assigned to in the block to names
+fn collect_assigned_block(block: &Block, names: &mut HashSet<String>) {
+    for stmt in block.statements.iter() {
+        match *stmt {
+            Statement::Let(ref name, _, _) => {
+                names.insert(name.clone());
+            }
+            Statement::Assign(ref left, _) => {
+                if let Expression::Variable(ref name) = left.expr {
+                    names.insert(name.clone());
+                }
+            }
+            Statement::If(_, ref then_block, ref else_block_opt) => {
+                collect_assigned_block(then_block, names);
+                if let Some(ref else_block) = *else_block_opt {
+                    collect_assigned_block(else_block, names);
+                }
+            }
+            Statement::While(_, ref block) | Statement::Unsafe(_, ref block) => {
+                collect_assigned_block(block, names);
+            }
+            Statement::Return(_) | Statement::Print(_) | Statement::Call(_) => {}
+        }
+    }
+}
+
+fn is_overflow_checked(op: BinaryOp, l: i32, r: i32) -> bool {
+    match op {
+        BinaryOp::Plus => l.checked_add(r).is_none(),
+        BinaryOp::Minus => l.checked_sub(r).is_none(),
+        BinaryOp::Multiply => l.checked_mul(r).is_none(),
+        _ => false,
+    }
+}
+
+fn get_constant(expr_node: &AstExpressionNode) -> Option<i32> {
+    match expr_node.expr {
+        Expression::Value(v) => Some(v),
+        _ => None,
+    }
+}
+
+struct ConstantFolder {
+    representation_mgr: RepresentationManager,
+    overflow_checks: bool,
+
+    // The int locals known to hold a constant at this point
+    constants: HashMap<String, i32>,
+
+    // Locals of the current function which can be changed through a
+    // pointer, and so are never known to be constant
+    address_taken: HashSet<String>,
+}
+
+impl ConstantFolder {
+    fn new(overflow_checks: bool) -> ConstantFolder {
+        ConstantFolder {
+            representation_mgr: RepresentationManager::new(),
+            overflow_checks,
+            constants: HashMap::new(),
+            address_taken: HashSet::new(),
+        }
+    }
+
+    fn fold_call(&mut self, fn_call: &mut FunctionCall) {
+        // new_array's sizeof(T) is how the type checker knows T
+        let skip = if fn_call.name == "new_array" { 1 } else { 0 };
+        for arg in fn_call.args_exprs.iter_mut().skip(skip) {
+            self.fold_expr(arg);
+        }
+    }
+
+    fn fold_expr(&mut self, expr_node: &mut AstExpressionNode) {
+        let value = match expr_node.expr {
+            Expression::Value(_) | Expression::StringValue(_) => None,
+            Expression::SizeOf(ref typ) => {
+                Some(get_mtype_size(self.representation_mgr.get_machine_type(typ)))
+            }
+            Expression::Variable(ref name) => self.constants.get(name).cloned(),
+            Expression::BinaryOp(op, ref mut l, ref mut r) => {
+                self.fold_expr(l);
+
+                // A divisor which only turns out to be 0 here has to stay
+                // as it was, so the run time check still catches it
+                let divisor = if op == BinaryOp::Divide {
+                    Some((**r).clone())
+                } else {
+                    None
+                };
+                self.fold_expr(r);
+                if let Some(divisor) = divisor {
+                    if get_constant(r) == Some(0) {
+                        **r = divisor;
+                    }
+                }
+
+                match (get_constant(l), get_constant(r)) {
+                    (Some(l), Some(r)) => {
+                        if self.overflow_checks && is_overflow_checked(op, l, r) {
+                            None
+                        } else {
+                            evaluate_binary_op(op, l, r)
+                        }
+                    }
+                    _ => None,
+                }
+            }
+            Expression::Call(ref mut fn_call) => {
+                self.fold_call(fn_call);
+                None
+            }
+            Expression::Reference(ref mut expr) |
+            Expression::Dereference(ref mut expr) |
+            Expression::FieldAccess(ref mut expr, _) => {
+                self.fold_expr(expr);
+                None
+            }
+            Expression::Index(ref mut array, ref mut index) => {
+                self.fold_expr(array);
+                self.fold_expr(index);
+                None
+            }
+            Expression::Slice(ref mut array, ref mut start, ref mut end) => {
+                self.fold_expr(array);
+                self.fold_expr(start);
+                self.fold_expr(end);
+                None
+            }
+        };
+
+        // Constants only stand in for ints, never for pointers
+        if let Some(value) = value {
+            if !expr_node.typ.as_ref().is_some_and(is_pointer) {
+                expr_node.expr = Expression::Value(value);
+            }
+        }
+    }
+
+    // Record what's known about a local after it's given a value
+    fn set_variable(&mut self, name: &str, typ: &VarType,
+                    value: Option<&AstExpressionNode>) {
+        let constant = value.and_then(get_constant);
+        match constant {
+            Some(c) if *typ == VarType::Int &&
+                !self.address_taken.contains(name) => {
+                self.constants.insert(name.to_string(), c);
+            }
+            _ => {
+                self.constants.remove(name);
+            }
+        }
+    }
+
+    fn fold_stmt(&mut self, stmt: &mut Statement) {
+        match *stmt {
+            Statement::Return(ref mut expr) | Statement::Print(ref mut expr) => {
+                self.fold_expr(expr);
+            }
+            Statement::Call(ref mut fn_call) => self.fold_call(fn_call),
+            Statement::Let(ref name, ref typ, ref mut value) => {
+                if let Some(ref mut expr) = *value {
+                    self.fold_expr(expr);
+                }
+                self.set_variable(name, typ, value.as_ref());
+            }
+            Statement::Assign(ref mut left, ref mut right) => {
+                self.fold_expr(right);
+                let name = match left.expr {
+                    Expression::Variable(ref name) => name.clone(),
+                    _ => {
+                        // Writing through a pointer or to a field can't
+                        // change an int local whose address isn't taken
+                        self.fold_expr(left);
+                        return;
+                    }
+                };
+                let typ = left.typ.clone().unwrap();
+                self.set_variable(&name, &typ, Some(right));
+            }
+            Statement::If(ref mut condition, ref mut then_block,
+                          ref mut else_block_opt) => {
+                self.fold_expr(condition);
+
+                let before = self.constants.clone();
+                self.fold_block(then_block);
+                let after_then = std::mem::replace(&mut self.constants, before);
+                if let Some(ref mut else_block) = *else_block_opt {
+                    self.fold_block(else_block);
+                }
+
+                // Only what's known at the end of both branches is known
+                // after the if
+                self.constants.retain(|name, value| {
+                    after_then.get(name) == Some(value)
+                });
+            }
+            Statement::While(ref mut condition, ref mut block) => {
+                // Anything assigned in the loop can have a different value
+                // each time the condition is checked
+                let mut assigned = HashSet::new();
+                collect_assigned_block(block, &mut assigned);
+                self.constants.retain(|name, _| !assigned.contains(name));
+
+                self.fold_expr(condition);
+                let before = self.constants.clone();
+                self.fold_block(block);
+                self.constants = before;
+            }
+            Statement::Unsafe(_, ref mut block) => self.fold_block(block),
+        }
+    }
+
+    fn fold_block(&mut self, block: &mut Block) {
+        for stmt in block.statements.iter_mut() {
+            self.fold_stmt(stmt);
+        }
+    }
+
+    fn fold_function(&mut self, function: &mut Function) {
+        self.constants.clear();
+        self.address_taken.clear();
+        collect_address_taken_block(&function.statements, &mut self.address_taken);
+
+        self.fold_block(&mut function.statements);
+    }
+}
+
+/// Replace whatever can be computed at compile time with its value
+pub fn fold_constants(program: &mut Program, overflow_checks: bool) {
+    let mut folder = ConstantFolder::new(overflow_checks);
+    folder.representation_mgr.init(&program.structs);
+    for function in program.functions.iter_mut() {
+        folder.fold_function(function);
+    }
+}
@@ -0,0 +1,420 @@
+use ast::AstExpressionNode;
+use ast::Expression;
+use ast::Statement;
+
+use ast_helper::collect_address_taken;
+
+use cfg::Cfg;
+use cfg::CfgNode;
+use cfg::FlowGraph;
+use cfg::NodeId;
+
+use ir::IrFunction;
+use ir::VReg;
+use ir::Value;
+
+use std::collections::BTreeSet;
+use std::collections::HashSet;
+
+// A framework for dataflow analyses over a FlowGraph. An analysis says
+// what it knows at the start (or end) of the function, how to combine what
+// it knows from several predecessors, and how each node changes it; solve
+// then finds what it knows before and after every node.
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Direction {
+    // Facts flow from the entry along the edges, like "x is initialised"
+    Forward,
+    // Facts flow from the exit against the edges, like "x is read later"
+    Backward,
+}
+
+pub trait DataflowProblem {
+    type Fact: Clone + PartialEq;
+
+    fn get_direction(&self) -> Direction;
+
+    // What's known at the entry (for forward problems) or the exit (for
+    // backward ones)
+    fn get_boundary_fact(&self) -> Self::Fact;
+
+    // What every other node starts with. Has to be the identity of meet.
+    fn get_initial_fact(&self) -> Self::Fact;
+
+    // Combine the facts coming from two edges
+    fn meet(&self, a: &Self::Fact, b: &Self::Fact) -> Self::Fact;
+
+    // The fact after the node, given the fact before it (in the direction
+    // of the analysis)
+    fn transfer(&self, node: NodeId, fact: &Self::Fact) -> Self::Fact;
+}
+
+/// The facts right before and right after every node, in program order
+/// (for backward problems, fact_out is what flows into the node)
+pub struct DataflowResult<F> {
+    pub fact_in: Vec<F>,
+    pub fact_out: Vec<F>,
+}
+
+/// Find the fixed point of the problem on the graph
+pub fn solve<P: DataflowProblem>(problem: &P,
+                                 graph: &FlowGraph) -> DataflowResult<P::Fact> {
+    let num_nodes = graph.num_nodes();
+    let forward = problem.get_direction() == Direction::Forward;
+    let (boundary, sources) = if forward {
+        (graph.entry, &graph.predecessors)
+    } else {
+        (graph.exit, &graph.successors)
+    };
+
+    // Before and after each node, in the direction of the analysis
+    let mut before = vec![problem.get_initial_fact(); num_nodes];
+    let mut after = vec![problem.get_initial_fact(); num_nodes];
+
+    // Nodes are mostly added in program order, so visiting them in (or
+    // against) that order converges in few passes
+    let order: Vec<NodeId> = if forward {
+        (0..num_nodes).collect()
+    } else {
+        (0..num_nodes).rev().collect()
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &node in order.iter() {
+            let input = if node == boundary {
+                problem.get_boundary_fact()
+            } else {
+                sources[node].iter()
+                    .fold(None, |acc: Option<P::Fact>, &source| {
+                        Some(match acc {
+                            Some(fact) => problem.meet(&fact, &after[source]),
+                            None => after[source].clone(),
+                        })
+                    })
+                    .unwrap_or_else(|| problem.get_initial_fact())
+            };
+            let output = problem.transfer(node, &input);
+
+            if output != after[node] {
+                changed = true;
+                after[node] = output;
+            }
+            before[node] = input;
+        }
+    }
+
+    if forward {
+        DataflowResult { fact_in: before, fact_out: after }
+    } else {
+        DataflowResult { fact_in: after, fact_out: before }
+    }
+}
+
+fn collect_variables(expr_node: &AstExpressionNode, names: &mut BTreeSet<String>) {
+    match expr_node.expr {
+        Expression::Variable(ref name) => {
+            names.insert(name.clone());
+        }
+        Expression::BinaryOp(_, ref l, ref r) |
+        Expression::Index(ref l, ref r) => {
+            collect_variables(l, names);
+            collect_variables(r, names);
+        }
+        Expression::Call(ref fn_call) => {
+            for arg in fn_call.args_exprs.iter() {
+                collect_variables(arg, names);
+            }
+        }
+        Expression::Reference(ref expr) | Expression::Dereference(ref expr) |
+        Expression::FieldAccess(ref expr, _) => collect_variables(expr, names),
+        Expression::Slice(ref array, ref start, ref end) => {
+            collect_variables(array, names);
+            collect_variables(start, names);
+            collect_variables(end, names);
+        }
+        Expression::Value(_) | Expression::SizeOf(_) |
+        Expression::StringValue(_) => {}
+    }
+}
+
+// The expressions a node evaluates
+fn get_node_exprs<'a>(node: &CfgNode<'a>) -> Vec<&'a AstExpressionNode> {
+    match *node {
+        CfgNode::Entry | CfgNode::Exit => vec![],
+        CfgNode::Condition(expr) => vec![expr],
+        CfgNode::Statement(stmt) => match *stmt {
+            Statement::Return(ref expr) | Statement::Print(ref expr) |
+            Statement::Let(_, _, Some(ref expr)) => vec![expr],
+            Statement::Let(_, _, None) => vec![],
+            Statement::Assign(ref left, ref right) => vec![left, right],
+            Statement::Call(ref fn_call) => fn_call.args_exprs.iter().collect(),
+            Statement::If(_, _, _) | Statement::While(_, _) |
+            Statement::Unsafe(_, _) => panic!("Not a node of its own"),
+        }
+    }
+}
+
+/// Live variables: the ones whose current value may still be read. A
+/// backward problem, where a node kills the variables it assigns to and
+/// makes the ones it reads live.
+pub struct Liveness {
+    uses: Vec<BTreeSet<String>>,
+    defs: Vec<BTreeSet<String>>,
+}
+
+impl Liveness {
+    pub fn new(cfg: &Cfg) -> Liveness {
+        // Variables whose address is taken can be read through a pointer
+        // anywhere, so they're always live
+        let mut address_taken = HashSet::new();
+        for node in cfg.nodes.iter() {
+            for expr in get_node_exprs(node) {
+                collect_address_taken(expr, &mut address_taken);
+            }
+        }
+
+        let mut uses = Vec::new();
+        let mut defs = Vec::new();
+        for node in cfg.nodes.iter() {
+            let mut node_uses: BTreeSet<String> = address_taken.iter()
+                .cloned()
+                .collect();
+            let mut node_defs = BTreeSet::new();
+
+            match *node {
+                CfgNode::Statement(Statement::Let(name, _, value)) => {
+                    node_defs.insert(name.clone());
+                    if let Some(expr) = value {
+                        collect_variables(expr, &mut node_uses);
+                    }
+                }
+                CfgNode::Statement(Statement::Assign(left, right)) => {
+                    // Assigning to a whole variable overwrites it, but
+                    // assigning to a field or through a pointer doesn't
+                    match left.expr {
+                        Expression::Variable(ref name) => {
+                            node_defs.insert(name.clone());
+                        }
+                        _ => collect_variables(left, &mut node_uses),
+                    }
+                    collect_variables(right, &mut node_uses);
+                }
+                _ => {
+                    for expr in get_node_exprs(node) {
+                        collect_variables(expr, &mut node_uses);
+                    }
+                }
+            }
+
+            for name in address_taken.iter() {
+                node_defs.remove(name);
+            }
+            uses.push(node_uses);
+            defs.push(node_defs);
+        }
+
+        Liveness { uses, defs }
+    }
+}
+
+impl DataflowProblem for Liveness {
+    type Fact = BTreeSet<String>;
+
+    fn get_direction(&self) -> Direction {
+        Direction::Backward
+    }
+
+    fn get_boundary_fact(&self) -> BTreeSet<String> {
+        BTreeSet::new()
+    }
+
+    fn get_initial_fact(&self) -> BTreeSet<String> {
+        BTreeSet::new()
+    }
+
+    fn meet(&self, a: &BTreeSet<String>, b: &BTreeSet<String>) -> BTreeSet<String> {
+        a.union(b).cloned().collect()
+    }
+
+    fn transfer(&self, node: NodeId, fact: &BTreeSet<String>) -> BTreeSet<String> {
+        let mut live: BTreeSet<String> = fact.difference(&self.defs[node])
+            .cloned()
+            .collect();
+        live.extend(self.uses[node].iter().cloned());
+        live
+    }
+}
+
+/// Live virtual registers, for each block of an IR function and the exit
+/// node after them (see IrFunction::get_flow_graph)
+pub struct IrLiveness {
+    // The registers each block reads before writing them
+    uses: Vec<BTreeSet<VReg>>,
+    defs: Vec<BTreeSet<VReg>>,
+}
+
+impl IrLiveness {
+    pub fn new(function: &IrFunction) -> IrLiveness {
+        let mut uses = Vec::new();
+        let mut defs = Vec::new();
+        for block in function.blocks.iter() {
+            let mut block_uses = BTreeSet::new();
+            let mut block_defs = BTreeSet::new();
+            let values = block.instrs.iter()
+                .map(|instr| (instr.get_uses(), instr.get_dests()))
+                .chain(Some((block.terminator.get_uses(), vec![])));
+            for (used, written) in values {
+                for value in used {
+                    if let Value::Reg(reg) = *value {
+                        if !block_defs.contains(&reg) {
+                            block_uses.insert(reg);
+                        }
+                    }
+                }
+                block_defs.extend(written);
+            }
+            uses.push(block_uses);
+            defs.push(block_defs);
+        }
+
+        // The exit node
+        uses.push(BTreeSet::new());
+        defs.push(BTreeSet::new());
+
+        IrLiveness { uses, defs }
+    }
+}
+
+impl DataflowProblem for IrLiveness {
+    type Fact = BTreeSet<VReg>;
+
+    fn get_direction(&self) -> Direction {
+        Direction::Backward
+    }
+
+    fn get_boundary_fact(&self) -> BTreeSet<VReg> {
+        BTreeSet::new()
+    }
+
+    fn get_initial_fact(&self) -> BTreeSet<VReg> {
+        BTreeSet::new()
+    }
+
+    fn meet(&self, a: &BTreeSet<VReg>, b: &BTreeSet<VReg>) -> BTreeSet<VReg> {
+        a.union(b).cloned().collect()
+    }
+
+    fn transfer(&self, node: NodeId, fact: &BTreeSet<VReg>) -> BTreeSet<VReg> {
+        let mut live: BTreeSet<VReg> = fact.difference(&self.defs[node])
+            .cloned()
+            .collect();
+        live.extend(self.uses[node].iter().cloned());
+        live
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cfg::build_cfg;
+
+    use ir::BasicBlock;
+    use ir::Instr;
+    use ir::IrBinOp;
+    use ir::IrType;
+    use ir::Terminator;
+
+    use parser::parse;
+    use scanner::get_tokens;
+
+    fn names(set: &BTreeSet<String>) -> Vec<&str> {
+        set.iter().map(|name| name.as_str()).collect()
+    }
+
+    #[test]
+    fn variables_are_live_until_their_last_read() {
+        let source = "int f (int a)
+{
+    int b = a + 1;
+    a = 2;
+    print b;
+    return a;
+}
+";
+        let prog = parse(&mut get_tokens(source));
+        let cfg = build_cfg(&prog.functions[0]);
+        let liveness = solve(&Liveness::new(&cfg), &cfg.graph);
+
+        // entry, exit, then the statements in order
+        let live: Vec<Vec<&str>> = liveness.fact_in.iter().map(names).collect();
+        assert_eq!(live, vec![vec!["a"], vec![], vec!["a"], vec!["b"],
+                              vec!["a", "b"], vec!["a"]]);
+    }
+
+    #[test]
+    fn address_taken_variables_are_always_live() {
+        let source = "int f (int a)
+{
+    int x = 1;
+    int* p = &x;
+    x = 2;
+    return a;
+}
+";
+        let prog = parse(&mut get_tokens(source));
+        let cfg = build_cfg(&prog.functions[0]);
+        let liveness = solve(&Liveness::new(&cfg), &cfg.graph);
+
+        assert_eq!(names(&liveness.fact_in[3]), vec!["a", "x"]);
+        assert_eq!(names(&liveness.fact_in[5]), vec!["a", "x"]);
+    }
+
+    #[test]
+    fn live_registers() {
+        // bb0: %1 = 0; jump bb1
+        // bb1: %2 = lt %1, %0; branch %2, bb2, bb3
+        // bb2: %1 = add %1, 1; jump bb1
+        // bb3: return %1
+        let mut fun = IrFunction::new("count", 1);
+        fun.params = vec![0];
+        fun.vreg_types = vec![IrType::I32; 3];
+        fun.blocks = vec![
+            BasicBlock {
+                instrs: vec![Instr::Copy { dest: 1, src: Value::Const(0) }],
+                lines: vec![1],
+                terminator: Terminator::Jump(1),
+            },
+            BasicBlock {
+                instrs: vec![Instr::BinOp { dest: 2, op: IrBinOp::Lt,
+                                            left: Value::Reg(1),
+                                            right: Value::Reg(0),
+                                            overflow_check: None }],
+                lines: vec![2],
+                terminator: Terminator::Branch { cond: Value::Reg(2),
+                                                 then_block: 2, else_block: 3 },
+            },
+            BasicBlock {
+                instrs: vec![Instr::BinOp { dest: 1, op: IrBinOp::Add,
+                                            left: Value::Reg(1),
+                                            right: Value::Const(1),
+                                            overflow_check: None }],
+                lines: vec![3],
+                terminator: Terminator::Jump(1),
+            },
+            BasicBlock {
+                instrs: vec![],
+                lines: vec![],
+                terminator: Terminator::Return(vec![Value::Reg(1)]),
+            },
+        ];
+
+        let liveness = solve(&IrLiveness::new(&fun), &fun.get_flow_graph());
+        let live: Vec<Vec<VReg>> = liveness.fact_in.iter()
+            .map(|regs| regs.iter().cloned().collect())
+            .collect();
+        assert_eq!(live, vec![vec![0], vec![0, 1], vec![0, 1], vec![1], vec![]]);
+    }
+}
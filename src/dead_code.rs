@@ -0,0 +1,214 @@
+use dataflow::solve;
+use dataflow::IrLiveness;
+
+use ir::Instr;
+use ir::IrFunction;
+use ir::IrProgram;
+use ir::Terminator;
+use ir::Value;
+
+use std::collections::BTreeSet;
+
+// Removes code that can't run, and instructions that compute values
+// nothing reads (done at -O1 and above). This is done on the IR, where
+// an if with a constant condition becomes a jump, and the blocks nothing
+// jumps to anymore can be dropped. Calls and stores are always kept, as
+// are the operations that check for overflow.
+//
+// What's reported in verbose mode is the lines of the source the removed
+// instructions came from. Blocks with no instructions, like one that
+// only returns a constant, aren't reported.
+
+// The lines code was removed from, and why
+#[derive(Default)]
+struct Removed {
+    unreachable: BTreeSet<usize>,
+    unused: BTreeSet<usize>,
+}
+
+// Whether removing the instruction only loses the value it computes
+fn is_pure(instr: &Instr) -> bool {
+    match *instr {
+        Instr::Copy { .. } | Instr::Trunc { .. } | Instr::Load { .. } |
+        Instr::FrameAddr { .. } => true,
+        Instr::BinOp { overflow_check, .. } => overflow_check.is_none(),
+        Instr::Store { .. } | Instr::Call { .. } => false,
+    }
+}
+
+// Branches on a constant always go the same way
+fn fold_constant_branches(fun: &mut IrFunction) {
+    for block in fun.blocks.iter_mut() {
+        let target = match block.terminator {
+            Terminator::Branch { cond: Value::Const(c), then_block, else_block } => {
+                if c != 0 { then_block } else { else_block }
+            }
+            _ => continue,
+        };
+        block.terminator = Terminator::Jump(target);
+    }
+}
+
+fn remove_unreachable_blocks(fun: &mut IrFunction, removed: &mut Removed) {
+    let reachable = fun.get_flow_graph().get_reachable();
+
+    // Where each block that's kept ends up
+    let mut new_ids = vec![None; fun.blocks.len()];
+    let mut num_kept = 0;
+    for (i, new_id) in new_ids.iter_mut().enumerate() {
+        if reachable[i] {
+            *new_id = Some(num_kept);
+            num_kept += 1;
+        }
+    }
+
+    let blocks = std::mem::take(&mut fun.blocks);
+    for (i, mut block) in blocks.into_iter().enumerate() {
+        if !reachable[i] {
+            removed.unreachable.extend(block.lines.iter().filter(|&&line| line != 0));
+            continue;
+        }
+        let renumber = |b: usize| {
+            new_ids[b].expect("A reachable block jumps to an unreachable one")
+        };
+        block.terminator = match block.terminator {
+            Terminator::Jump(b) => Terminator::Jump(renumber(b)),
+            Terminator::Branch { cond, then_block, else_block } => {
+                Terminator::Branch {
+                    cond,
+                    then_block: renumber(then_block),
+                    else_block: renumber(else_block),
+                }
+            }
+            terminator => terminator,
+        };
+        fun.blocks.push(block);
+    }
+}
+
+// Remove the pure instructions whose result is never read. Returns whether
+// anything was removed.
+fn remove_dead_instructions(fun: &mut IrFunction, removed: &mut Removed) -> bool {
+    let liveness = solve(&IrLiveness::new(fun), &fun.get_flow_graph());
+
+    let mut changed = false;
+    for (i, block) in fun.blocks.iter_mut().enumerate() {
+        // Go backwards from the end of the block, keeping track of what's
+        // live after each instruction
+        let mut live: BTreeSet<usize> = liveness.fact_out[i].clone();
+        for value in block.terminator.get_uses() {
+            if let Value::Reg(reg) = *value {
+                live.insert(reg);
+            }
+        }
+
+        let mut kept = Vec::new();
+        let mut kept_lines = Vec::new();
+        let lines = block.lines.drain(..).rev();
+        for (instr, line) in block.instrs.drain(..).rev().zip(lines) {
+            let dests = instr.get_dests();
+            if is_pure(&instr) && dests.iter().all(|dest| !live.contains(dest)) {
+                if line != 0 {
+                    removed.unused.insert(line);
+                }
+                changed = true;
+                continue;
+            }
+            for dest in dests {
+                live.remove(&dest);
+            }
+            for value in instr.get_uses() {
+                if let Value::Reg(reg) = *value {
+                    live.insert(reg);
+                }
+            }
+            kept.push(instr);
+            kept_lines.push(line);
+        }
+        kept.reverse();
+        kept_lines.reverse();
+        block.instrs = kept;
+        block.lines = kept_lines;
+    }
+    changed
+}
+
+/// Remove the code of the program that can't run or doesn't do anything.
+/// Returns the lines of each function code was removed from.
+pub fn eliminate_dead_code(program: &mut IrProgram) -> Vec<String> {
+    let mut report = Vec::new();
+    for fun in program.functions.iter_mut() {
+        let mut removed = Removed::default();
+        fold_constant_branches(fun);
+        remove_unreachable_blocks(fun, &mut removed);
+
+        // Removing an instruction can make what it read dead too
+        while remove_dead_instructions(fun, &mut removed) {}
+
+        let mut messages: Vec<(usize, &str)> = removed.unreachable.iter()
+            .map(|&line| (line, "code that can never run was removed"))
+            .chain(removed.unused.difference(&removed.unreachable)
+                   .map(|&line| (line, "a value that's never used was removed")))
+            .collect();
+        messages.sort();
+        for (line, message) in messages {
+            report.push(format!("in {} at line {}: {}", fun.name, line, message));
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ir::BasicBlock;
+    use ir::IrType;
+
+    #[test]
+    fn removed_lines_are_reported() {
+        // bb0: %1 = 1 (line 3, never read); %1 = 2 (line 4)
+        //      branch 0, bb1, bb2
+        // bb1: call printf(%1) (line 6)
+        // bb2: %2 = 3 (added by the compiler, never read); return %1
+        let mut fun = IrFunction::new("f", 1);
+        fun.params = vec![0];
+        fun.vreg_types = vec![IrType::I32; 3];
+        fun.blocks = vec![
+            BasicBlock {
+                instrs: vec![Instr::Copy { dest: 1, src: Value::Const(1) },
+                             Instr::Copy { dest: 1, src: Value::Const(2) }],
+                lines: vec![3, 4],
+                terminator: Terminator::Branch { cond: Value::Const(0),
+                                                 then_block: 1, else_block: 2 },
+            },
+            BasicBlock {
+                instrs: vec![Instr::Call { dests: vec![],
+                                           name: "printf".to_string(),
+                                           args: vec![Value::Reg(1)],
+                                           line: 6 }],
+                lines: vec![6],
+                terminator: Terminator::Jump(2),
+            },
+            BasicBlock {
+                instrs: vec![Instr::Copy { dest: 2, src: Value::Const(3) }],
+                lines: vec![0],
+                terminator: Terminator::Return(vec![Value::Reg(1)]),
+            },
+        ];
+        let mut program = IrProgram { functions: vec![fun], strings: vec![] };
+
+        assert_eq!(eliminate_dead_code(&mut program), vec![
+            "in f at line 3: a value that's never used was removed",
+            "in f at line 6: code that can never run was removed",
+        ]);
+
+        let fun = &program.functions[0];
+        assert_eq!(fun.blocks.len(), 2);
+        assert_eq!(fun.blocks[0].instrs, vec![Instr::Copy { dest: 1, src: Value::Const(2) }]);
+        assert_eq!(fun.blocks[0].lines, vec![4]);
+        assert_eq!(fun.blocks[0].terminator, Terminator::Jump(1));
+        assert!(fun.blocks[1].instrs.is_empty());
+        assert!(fun.blocks[1].lines.is_empty());
+    }
+}
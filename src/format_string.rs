@@ -0,0 +1,94 @@
+use ast::PointerType;
+use ast::VarType;
+use ast::VarType::*;
+
+// Parsing of printf/scanf format strings, so the type checker can match
+// each conversion against the argument given for it.
+
+/// Return the conversion character of every argument the format string
+/// uses, like ['d', 's'] for "%5d: %s\n". Flags, a width and a precision
+/// may come before the conversion.
+pub fn get_conversions(format: &str) -> Result<Vec<char>, String> {
+    let mut conversions = Vec::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+
+        // Flags
+        while let Some(&('-' | '+' | ' ' | '#' | '0')) = chars.peek() {
+            chars.next();
+        }
+
+        // Width, then precision
+        while chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+            chars.next();
+        }
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            while chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+                chars.next();
+            }
+        }
+
+        match chars.next() {
+            Some('%') => {}
+            Some(conversion @ ('d' | 'c' | 's' | 'p' | 'x')) => {
+                conversions.push(conversion);
+            }
+            Some(other) => {
+                return Err(format!("Unsupported conversion %{} in format \
+                                    string {}", other, format));
+            }
+            None => {
+                return Err(format!("Format string {} ends in the middle \
+                                    of a conversion", format));
+            }
+        }
+    }
+
+    Ok(conversions)
+}
+
+// A shared_pointer points past the reference count, which printf and
+// scanf don't know about, so it has to be passed as &*p
+fn is_pointer_to<F: Fn(&VarType) -> bool>(typ: &VarType, pointed: F) -> bool {
+    match *typ {
+        Pointer(PointerType::Shared, _) => false,
+        Pointer(_, ref t) => pointed(t),
+        _ => false,
+    }
+}
+
+fn is_integer(typ: &VarType) -> bool {
+    *typ == Int || *typ == Char
+}
+
+/// Return true if printf can print a value of type typ with the conversion
+pub fn printf_accepts(conversion: char, typ: &VarType) -> bool {
+    match conversion {
+        // chars are passed as ints, so these work for both
+        'd' | 'x' | 'c' => is_integer(typ),
+        's' => is_pointer_to(typ, |t| *t == Char),
+        'p' => is_pointer_to(typ, |_| true),
+        _ => false,
+    }
+}
+
+/// Return true if scanf can read into an argument of type typ with the
+/// conversion
+pub fn scanf_accepts(conversion: char, typ: &VarType) -> bool {
+    // scanf writes through the pointer, which a ref can't do
+    if let Pointer(PointerType::Ref, _) = *typ {
+        return false;
+    }
+
+    match conversion {
+        'd' | 'x' => is_pointer_to(typ, |t| *t == Int),
+        'c' | 's' => is_pointer_to(typ, |t| *t == Char),
+        // Only a plain pointer can be given any address
+        'p' => is_pointer_to(typ, |t| matches!(*t, Pointer(PointerType::Raw, _))),
+        _ => false,
+    }
+}
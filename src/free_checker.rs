@@ -0,0 +1,354 @@
+use ast::AstExpressionNode;
+use ast::BinaryOp;
+use ast::Block;
+use ast::Expression;
+use ast::FunctionCall;
+use ast::PointerType;
+use ast::Program;
+use ast::Statement;
+use ast::VarType;
+
+use ast_helper::block_always_returns;
+
+use code_block::CodeBlock;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// Looks for raw pointers which are used after being passed to free().
+// This is done one function at a time, and has to run after the type
+// checker.
+//
+// We keep track of which pointer variables may hold the address of freed
+// memory. Copying a pointer (q = p) makes q an alias of p, so freeing
+// either one frees both. Assigning a new value to a pointer makes it valid
+// again.
+//
+// Dereferencing (or freeing again) a pointer which is freed on every path
+// is an error. If it's only freed on some paths we just warn about it.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FreeStatus {
+    Freed,
+
+    // Freed on some paths but not others
+    MaybeFreed,
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+struct FreeState {
+    // Pointers whose memory may have been freed. Pointers which aren't in
+    // here are valid.
+    pointer_to_status: HashMap<String, FreeStatus>,
+
+    // Pointer -> every other pointer holding a copy of the same address
+    aliases: HashMap<String, HashSet<String>>,
+}
+
+fn merge_status(a: Option<FreeStatus>,
+                b: Option<FreeStatus>) -> Option<FreeStatus> {
+    match (a, b) {
+        (None, None) => None,
+        (Some(FreeStatus::Freed), Some(FreeStatus::Freed)) => {
+            Some(FreeStatus::Freed)
+        }
+        _ => Some(FreeStatus::MaybeFreed),
+    }
+}
+
+// The state after either a or b was executed
+fn merge_states(a: &FreeState, b: &FreeState) -> FreeState {
+    let mut res = FreeState::default();
+
+    let names: HashSet<&String> = a.pointer_to_status.keys()
+        .chain(b.pointer_to_status.keys())
+        .collect();
+    for name in names {
+        let status = merge_status(a.pointer_to_status.get(name).cloned(),
+                                  b.pointer_to_status.get(name).cloned());
+        res.pointer_to_status.insert(name.clone(), status.unwrap());
+    }
+
+    // A pointer may be an alias of anything it was an alias of on either
+    // path
+    for (name, aliases) in a.aliases.iter().chain(b.aliases.iter()) {
+        res.aliases.entry(name.clone())
+            .or_default()
+            .extend(aliases.iter().cloned());
+    }
+    res
+}
+
+fn is_raw_pointer(expr_node: &AstExpressionNode) -> bool {
+    matches!(expr_node.typ, Some(VarType::Pointer(PointerType::Raw, _)))
+}
+
+// The pointer variable that an address is computed from, like p in p or
+// p + 1
+fn get_pointer_variable(expr_node: &AstExpressionNode) -> Option<&String> {
+    match expr_node.expr {
+        Expression::Variable(ref name) if is_raw_pointer(expr_node) => Some(name),
+        Expression::BinaryOp(BinaryOp::Plus, ref l, ref r) |
+        Expression::BinaryOp(BinaryOp::Minus, ref l, ref r)
+            if is_raw_pointer(expr_node) => {
+            get_pointer_variable(l).or_else(|| get_pointer_variable(r))
+        }
+        _ => None,
+    }
+}
+
+pub struct FreeChecker {
+    state: FreeState,
+    blocks: Vec<CodeBlock>,
+    current_fn: String,
+
+    // Turned off while we look for the fixed point of a loop, so that we
+    // don't report the same problem once per iteration
+    report: bool,
+
+    errors_found: Vec<String>,
+    warnings_found: Vec<String>,
+}
+
+impl FreeChecker {
+    pub fn new() -> FreeChecker {
+        FreeChecker {
+            state: FreeState::default(),
+            blocks: Vec::new(),
+            current_fn: String::new(),
+            report: true,
+            errors_found: Vec::new(),
+            warnings_found: Vec::new(),
+        }
+    }
+
+    // Report a problem with a pointer, depending on how sure we are that
+    // it was freed
+    fn report_freed_use(&mut self, name: &str, what: &str) {
+        if !self.report {
+            return;
+        }
+
+        match self.state.pointer_to_status.get(name) {
+            Some(&FreeStatus::Freed) => {
+                self.errors_found.push(format!(
+                    "In {}: {} {} after it was freed", self.current_fn,
+                    name, what));
+            }
+            Some(&FreeStatus::MaybeFreed) => {
+                self.warnings_found.push(format!(
+                    "In {}: {} {} after it may have been freed",
+                    self.current_fn, name, what));
+            }
+            None => {}
+        }
+    }
+
+    // Stop treating name as a copy of any other pointer
+    fn remove_aliases(&mut self, name: &str) {
+        if let Some(aliases) = self.state.aliases.remove(name) {
+            for alias in aliases {
+                if let Some(others) = self.state.aliases.get_mut(&alias) {
+                    others.remove(name);
+                }
+            }
+        }
+    }
+
+    fn forget_pointer(&mut self, name: &str) {
+        self.remove_aliases(name);
+        self.state.pointer_to_status.remove(name);
+    }
+
+    // name now holds the value of value_opt
+    fn assign_pointer(&mut self, name: &str,
+                      value_opt: Option<&AstExpressionNode>) {
+        self.forget_pointer(name);
+
+        let source = match value_opt.map(|value| &value.expr) {
+            Some(Expression::Variable(ref source))
+                if is_raw_pointer(value_opt.unwrap()) && source != name => {
+                source.clone()
+            }
+            _ => return,
+        };
+
+        // name is a copy of source, so it's freed whenever source is
+        if let Some(status) = self.state.pointer_to_status.get(&source).cloned() {
+            self.state.pointer_to_status.insert(name.to_string(), status);
+        }
+
+        let mut group = self.state.aliases.get(&source)
+            .cloned()
+            .unwrap_or_default();
+        group.insert(source);
+        for alias in group.iter() {
+            self.state.aliases.entry(alias.clone())
+                .or_default()
+                .insert(name.to_string());
+        }
+        self.state.aliases.insert(name.to_string(), group);
+    }
+
+    fn mark_freed(&mut self, name: &str) {
+        let mut freed = self.state.aliases.get(name)
+            .cloned()
+            .unwrap_or_default();
+        freed.insert(name.to_string());
+        for pointer in freed {
+            self.state.pointer_to_status.insert(pointer, FreeStatus::Freed);
+        }
+    }
+
+    fn check_call(&mut self, fn_call: &FunctionCall) {
+        for arg in fn_call.args_exprs.iter() {
+            self.check_expr(arg);
+        }
+
+        if fn_call.name == "free" {
+            let arg = &fn_call.args_exprs[0];
+            if let Expression::Variable(ref name) = arg.expr {
+                if is_raw_pointer(arg) {
+                    self.report_freed_use(name, "is freed again");
+                    self.mark_freed(name);
+                }
+            }
+        }
+    }
+
+    fn check_expr(&mut self, expr_node: &AstExpressionNode) {
+        match expr_node.expr {
+            Expression::Dereference(ref expr) => {
+                self.check_expr(expr);
+                if let Some(name) = get_pointer_variable(expr) {
+                    self.report_freed_use(name, "is dereferenced");
+                }
+            }
+            Expression::Call(ref fn_call) => self.check_call(fn_call),
+            Expression::BinaryOp(_, ref l, ref r) => {
+                self.check_expr(l);
+                self.check_expr(r);
+            }
+            Expression::Reference(ref expr) |
+            Expression::FieldAccess(ref expr, _) => self.check_expr(expr),
+            Expression::Index(ref array, ref index) => {
+                self.check_expr(array);
+                self.check_expr(index);
+            }
+            Expression::Slice(ref array, ref start, ref end) => {
+                self.check_expr(array);
+                self.check_expr(start);
+                self.check_expr(end);
+            }
+            Expression::Value(_) | Expression::SizeOf(_) |
+            Expression::Variable(_) | Expression::StringValue(_) => {}
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &Statement) {
+        match *stmt {
+            Statement::Return(ref expr) | Statement::Print(ref expr) => {
+                self.check_expr(expr);
+            }
+            Statement::Call(ref fn_call) => self.check_call(fn_call),
+            Statement::Let(ref name, _, ref value_opt) => {
+                self.blocks.last_mut()
+                    .expect("No current block!")
+                    .declared_variables.insert(name.clone());
+                if let Some(ref value) = *value_opt {
+                    self.check_expr(value);
+                }
+                self.assign_pointer(name, value_opt.as_ref());
+            }
+            Statement::Assign(ref left, ref right) => {
+                self.check_expr(right);
+                match left.expr {
+                    Expression::Variable(ref name) => {
+                        self.assign_pointer(name, Some(right));
+                    }
+                    // Writing through a pointer uses it
+                    _ => self.check_expr(left),
+                }
+            }
+            Statement::If(ref condition, ref then_block, ref else_block_opt) => {
+                self.check_expr(condition);
+                let before = self.state.clone();
+
+                self.check_block(then_block);
+                let after_then = std::mem::replace(&mut self.state, before);
+
+                if let Some(ref else_block) = *else_block_opt {
+                    self.check_block(else_block);
+                }
+
+                // A branch that returns doesn't reach the code after the if
+                let then_returns = block_always_returns(then_block);
+                let else_returns = else_block_opt.as_ref()
+                    .is_some_and(block_always_returns);
+                if then_returns {
+                    // self.state is already the state after the else block
+                } else if else_returns {
+                    self.state = after_then;
+                } else {
+                    self.state = merge_states(&after_then, &self.state);
+                }
+            }
+            Statement::Unsafe(_, ref block) => self.check_block(block),
+            Statement::While(ref condition, ref block) => {
+                // Find what may be freed at the start of an iteration
+                let report = self.report;
+                self.report = false;
+                let mut loop_start = self.state.clone();
+                loop {
+                    self.state = loop_start.clone();
+                    self.check_expr(condition);
+                    self.check_block(block);
+
+                    let next_start = merge_states(&loop_start, &self.state);
+                    if next_start == loop_start {
+                        break;
+                    }
+                    loop_start = next_start;
+                }
+
+                // Now go through it once more to report problems
+                self.report = report;
+                self.state = loop_start.clone();
+                self.check_expr(condition);
+                self.check_block(block);
+                self.state = loop_start;
+            }
+        }
+    }
+
+    fn check_block(&mut self, block: &Block) {
+        self.blocks.push(CodeBlock::new());
+        for stmt in block.statements.iter() {
+            self.check_stmt(stmt);
+        }
+
+        let b = self.blocks.pop().expect("No current block!");
+        for variable in b.declared_variables {
+            self.forget_pointer(&variable);
+        }
+    }
+
+    // Return false if a pointer is definitely used after being freed
+    pub fn check_program(&mut self, program: &Program) -> bool {
+        for fun in program.functions.iter() {
+            self.current_fn = fun.name.clone();
+            self.state = FreeState::default();
+            self.check_block(&fun.statements);
+        }
+
+        self.errors_found.is_empty()
+    }
+
+    pub fn get_errors(&self) -> &Vec<String> {
+        &self.errors_found
+    }
+
+    pub fn get_warnings(&self) -> &Vec<String> {
+        &self.warnings_found
+    }
+}
@@ -0,0 +1,264 @@
+use ir::BasicBlock;
+use ir::BlockId;
+use ir::Instr;
+use ir::IrFunction;
+use ir::IrProgram;
+use ir::Terminator;
+use ir::Value;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// Replaces calls to small functions with a copy of their body (done at -O1
+// and above). The callee's registers, slots and blocks are renumbered to
+// come after the caller's, so its parameters become fresh registers which
+// the arguments are copied into. Each return becomes a copy of the result
+// into the call's registers, and a jump to a block holding whatever came
+// after the call.
+//
+// Functions are inlined if they aren't recursive, and are either marked
+// inline or have at most INLINE_THRESHOLD instructions. Functions with
+// runtime checks that report which function failed are never inlined, as
+// the error would name the caller instead.
+
+const INLINE_THRESHOLD: usize = 12;
+
+// The number of instructions in the function, counting terminators
+fn get_size(fun: &IrFunction) -> usize {
+    fun.blocks.iter().map(|block| block.instrs.len() + 1).sum()
+}
+
+// Whether the function has overflow checks or traps (division by zero and
+// bounds errors), whose errors say which function they're in
+fn has_named_checks(fun: &IrFunction) -> bool {
+    fun.blocks.iter().any(|block| {
+        block.instrs.iter().any(|instr| match *instr {
+            Instr::BinOp { overflow_check, .. } => overflow_check.is_some(),
+            _ => false,
+        }) || matches!(block.terminator, Terminator::Trap(..))
+    })
+}
+
+// The functions of the program each function calls
+fn get_call_graph(functions: &[IrFunction]) -> HashMap<&str, HashSet<&str>> {
+    let mut graph = HashMap::new();
+    for fun in functions.iter() {
+        let callees = fun.blocks.iter()
+            .flat_map(|block| block.instrs.iter())
+            .filter_map(|instr| match *instr {
+                Instr::Call { ref name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        graph.insert(fun.name.as_str(), callees);
+    }
+    graph
+}
+
+// Whether the function can end up calling itself
+fn is_recursive(graph: &HashMap<&str, HashSet<&str>>, name: &str) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack = vec![name];
+    while let Some(current) = stack.pop() {
+        for &callee in graph.get(current).into_iter().flatten() {
+            if callee == name {
+                return true;
+            }
+            if seen.insert(callee) {
+                stack.push(callee);
+            }
+        }
+    }
+    false
+}
+
+fn rename_value(value: &Value, reg_base: usize) -> Value {
+    match *value {
+        Value::Reg(reg) => Value::Reg(reg + reg_base),
+        _ => value.clone(),
+    }
+}
+
+// The instruction with the callee's registers and slots moved up to where
+// they are in the caller
+fn rename_instr(instr: &Instr, reg_base: usize, slot_base: usize) -> Instr {
+    let rename = |value: &Value| rename_value(value, reg_base);
+    match *instr {
+        Instr::Copy { dest, ref src } => {
+            Instr::Copy { dest: dest + reg_base, src: rename(src) }
+        }
+        Instr::Trunc { dest, ref src } => {
+            Instr::Trunc { dest: dest + reg_base, src: rename(src) }
+        }
+        Instr::BinOp { dest, op, ref left, ref right, overflow_check } => {
+            Instr::BinOp {
+                dest: dest + reg_base,
+                op,
+                left: rename(left),
+                right: rename(right),
+                overflow_check,
+            }
+        }
+        Instr::Load { dest, typ, ref addr, offset } => {
+            Instr::Load { dest: dest + reg_base, typ, addr: rename(addr), offset }
+        }
+        Instr::Store { typ, ref value, ref addr, offset } => {
+            Instr::Store { typ, value: rename(value), addr: rename(addr), offset }
+        }
+        Instr::FrameAddr { dest, slot } => {
+            Instr::FrameAddr { dest: dest + reg_base, slot: slot + slot_base }
+        }
+        Instr::Call { ref dests, ref name, ref args, line } => {
+            Instr::Call {
+                dests: dests.iter().map(|&dest| dest + reg_base).collect(),
+                name: name.clone(),
+                args: args.iter().map(rename).collect(),
+                line,
+            }
+        }
+    }
+}
+
+// Replace the call at the index of the block with the callee's body, whose
+// instructions are said to be added by the compiler
+fn inline_call(caller: &mut IrFunction, block: BlockId, index: usize,
+               callee: &IrFunction) {
+    let after = caller.blocks[block].instrs.split_off(index + 1);
+    let after_lines = caller.blocks[block].lines.split_off(index + 1);
+    let (dests, args) = match caller.blocks[block].instrs.pop() {
+        Some(Instr::Call { dests, args, .. }) => (dests, args),
+        _ => panic!("Can only inline a call"),
+    };
+    caller.blocks[block].lines.pop();
+    let terminator = std::mem::replace(&mut caller.blocks[block].terminator,
+                                       Terminator::Unreachable);
+
+    let reg_base = caller.vreg_types.len();
+    caller.vreg_types.extend(callee.vreg_types.iter().cloned());
+    let slot_base = caller.slots.len();
+    caller.slots.extend(callee.slots.iter().cloned());
+
+    let continuation = caller.blocks.len();
+    caller.blocks.push(BasicBlock {
+        instrs: after,
+        lines: after_lines,
+        terminator,
+    });
+    let block_base = caller.blocks.len();
+
+    // The arguments go into the renamed parameters
+    for (&param, arg) in callee.params.iter().zip(args) {
+        caller.blocks[block].instrs.push(Instr::Copy {
+            dest: param + reg_base,
+            src: arg,
+        });
+        caller.blocks[block].lines.push(0);
+    }
+    caller.blocks[block].terminator = Terminator::Jump(block_base);
+
+    for callee_block in callee.blocks.iter() {
+        let mut instrs: Vec<Instr> = callee_block.instrs.iter()
+            .map(|instr| rename_instr(instr, reg_base, slot_base))
+            .collect();
+        let terminator = match callee_block.terminator {
+            Terminator::Jump(b) => Terminator::Jump(b + block_base),
+            Terminator::Branch { ref cond, then_block, else_block } => {
+                Terminator::Branch {
+                    cond: rename_value(cond, reg_base),
+                    then_block: then_block + block_base,
+                    else_block: else_block + block_base,
+                }
+            }
+            Terminator::Return(ref values) => {
+                for (&dest, value) in dests.iter().zip(values.iter()) {
+                    instrs.push(Instr::Copy {
+                        dest,
+                        src: rename_value(value, reg_base),
+                    });
+                }
+                Terminator::Jump(continuation)
+            }
+            // Calls are only made tail calls after inlining (see main.rs)
+            Terminator::TailCall { .. } => {
+                panic!("Tail call in {}, which is being inlined", callee.name)
+            }
+            Terminator::Trap(kind, line) => Terminator::Trap(kind, line),
+            Terminator::Unreachable => Terminator::Unreachable,
+        };
+        let lines = vec![0; instrs.len()];
+        caller.blocks.push(BasicBlock { instrs, lines, terminator });
+    }
+}
+
+fn describe_call_site(caller: &str, callee: &str, line: usize) -> String {
+    if line == 0 {
+        format!("call to {} in {}", callee, caller)
+    } else {
+        format!("call to {} in {} at line {}", callee, caller, line)
+    }
+}
+
+/// Inline the calls to small functions. Returns what happened to each call
+/// to a function of the program.
+pub fn inline_functions(program: &mut IrProgram) -> Vec<String> {
+    let originals = program.functions.clone();
+    let graph = get_call_graph(&originals);
+
+    // Why each function can't be inlined, if it can't
+    let mut reasons: HashMap<&str, String> = HashMap::new();
+    for fun in originals.iter() {
+        let size = get_size(fun);
+        let reason = if is_recursive(&graph, &fun.name) {
+            format!("{} is recursive", fun.name)
+        } else if has_named_checks(fun) {
+            format!("{} has runtime checks which report the function they're in",
+                    fun.name)
+        } else if !fun.is_inline && size > INLINE_THRESHOLD {
+            format!("{} has {} instructions, more than {}", fun.name, size,
+                    INLINE_THRESHOLD)
+        } else {
+            continue;
+        };
+        reasons.insert(fun.name.as_str(), reason);
+    }
+
+    let mut report = Vec::new();
+    for caller in program.functions.iter_mut() {
+        // Inlined bodies are added to the end, so calls in them get
+        // inlined too
+        let mut block = 0;
+        while block < caller.blocks.len() {
+            let mut inlined = None;
+            for (i, instr) in caller.blocks[block].instrs.iter().enumerate() {
+                let (name, line) = match *instr {
+                    Instr::Call { ref name, line, .. } => (name, line),
+                    _ => continue,
+                };
+                let callee = match originals.iter().find(|fun| fun.name == *name) {
+                    Some(callee) => callee,
+                    None => continue,
+                };
+
+                let site = describe_call_site(&caller.name, name, line);
+                match reasons.get(name.as_str()) {
+                    Some(reason) => {
+                        report.push(format!("not inlining {}: {}", site, reason));
+                    }
+                    None => {
+                        report.push(format!("inlining {}", site));
+                        inlined = Some((i, callee));
+                        break;
+                    }
+                }
+            }
+
+            // What came after the call is now in a block of its own, which
+            // is looked at later
+            if let Some((i, callee)) = inlined {
+                inline_call(caller, block, i, callee);
+            }
+            block += 1;
+        }
+    }
+    report
+}
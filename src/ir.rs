@@ -0,0 +1,490 @@
+use cfg::FlowGraph;
+
+use std::fmt;
+
+// A target independent intermediate representation, lowered from the typed
+// AST by ir_lowering and turned into assembly by the x86 code generator.
+//
+// Each function is a list of basic blocks. A block is a list of
+// instructions, which never branch, followed by a terminator, which is the
+// only thing that can leave the block. Values are computed into virtual
+// registers (%0, %1, ...), of which there is an unlimited number. Virtual
+// registers can be assigned more than once, which is how local variables
+// are represented.
+//
+// Locals whose address is taken, and structs, live in frame slots ($0,
+// $1, ...) instead, and are read and written with explicit loads and
+// stores.
+
+pub type VReg = usize;
+pub type BlockId = usize;
+pub type SlotId = usize;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum IrType {
+    // A char, zero extended to 32 bits while it's in a register
+    I8,
+    I32,
+    Ptr,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Reg(VReg),
+    Const(i32),
+    // The address of a label, like a string constant
+    Global(String),
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum IrBinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    // Unsigned comparisons, used for bounds checks
+    Below,
+    BelowOrEqual,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TrapKind {
+    BoundsError,
+    DivisionByZero,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Instr {
+    Copy { dest: VReg, src: Value },
+    // Keep only the low byte of src
+    Trunc { dest: VReg, src: Value },
+    // overflow_check has the line to report if the operation overflows
+    BinOp {
+        dest: VReg,
+        op: IrBinOp,
+        left: Value,
+        right: Value,
+        overflow_check: Option<usize>,
+    },
+    Load { dest: VReg, typ: IrType, addr: Value, offset: i32 },
+    Store { typ: IrType, value: Value, addr: Value, offset: i32 },
+    FrameAddr { dest: VReg, slot: SlotId },
+    // Arguments are given one word at a time, in the order they're laid
+    // out in memory. Arrays take two words, so they return into two
+    // registers. line is where the call is in the source, 0 for calls
+    // added by the compiler.
+    Call { dests: Vec<VReg>, name: String, args: Vec<Value>, line: usize },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Terminator {
+    Jump(BlockId),
+    // Goes to then_block if cond isn't 0
+    Branch { cond: Value, then_block: BlockId, else_block: BlockId },
+    Return(Vec<Value>),
+    // Return whatever calling the function returns, reusing this
+    // function's frame and arguments
+    TailCall { name: String, args: Vec<Value>, line: usize },
+    // Stop the program with an error, reporting the line
+    Trap(TrapKind, usize),
+    // The end of a block no path reaches the end of
+    Unreachable,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct BasicBlock {
+    pub instrs: Vec<Instr>,
+    // The line of the source each instruction comes from, 0 for the ones
+    // added by the compiler
+    pub lines: Vec<usize>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct IrFunction {
+    pub name: String,
+    // One register per word of the arguments
+    pub params: Vec<VReg>,
+    // How many words the function returns: 1, or 2 for an array
+    pub return_words: usize,
+    // The type of every virtual register
+    pub vreg_types: Vec<IrType>,
+    // The size of every frame slot
+    pub slots: Vec<i32>,
+    // The first block is where the function starts
+    pub blocks: Vec<BasicBlock>,
+    // Marked inline in the source
+    pub is_inline: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct IrProgram {
+    pub functions: Vec<IrFunction>,
+    // Label, then the string (with its quotes) of every string constant
+    pub strings: Vec<(String, String)>,
+}
+
+impl IrFunction {
+    pub fn new(name: &str, return_words: usize) -> IrFunction {
+        IrFunction {
+            name: name.to_string(),
+            params: Vec::new(),
+            return_words,
+            vreg_types: Vec::new(),
+            slots: Vec::new(),
+            blocks: Vec::new(),
+            is_inline: false,
+        }
+    }
+
+    /// The graph of the blocks, with one more node after them, which every
+    /// block that leaves the function goes to
+    pub fn get_flow_graph(&self) -> FlowGraph {
+        let exit = self.blocks.len();
+        let mut graph = FlowGraph::new(0, exit);
+        for _ in 0..exit + 1 {
+            graph.add_node();
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            let successors = block.terminator.get_successors();
+            if successors.is_empty() {
+                graph.add_edge(i, exit);
+            }
+            for next in successors {
+                graph.add_edge(i, next);
+            }
+        }
+        graph
+    }
+}
+
+impl Instr {
+    /// The register the instruction writes to, if there's one
+    pub fn get_dests(&self) -> Vec<VReg> {
+        match *self {
+            Instr::Copy { dest, .. } | Instr::Trunc { dest, .. } |
+            Instr::BinOp { dest, .. } | Instr::Load { dest, .. } |
+            Instr::FrameAddr { dest, .. } => vec![dest],
+            Instr::Call { ref dests, .. } => dests.clone(),
+            Instr::Store { .. } => vec![],
+        }
+    }
+
+    /// Every value the instruction reads
+    pub fn get_uses(&self) -> Vec<&Value> {
+        match *self {
+            Instr::Copy { ref src, .. } | Instr::Trunc { ref src, .. } => {
+                vec![src]
+            }
+            Instr::BinOp { ref left, ref right, .. } => vec![left, right],
+            Instr::Load { ref addr, .. } => vec![addr],
+            Instr::Store { ref value, ref addr, .. } => vec![value, addr],
+            Instr::FrameAddr { .. } => vec![],
+            Instr::Call { ref args, .. } => args.iter().collect(),
+        }
+    }
+}
+
+impl Terminator {
+    /// The blocks control can go to next
+    pub fn get_successors(&self) -> Vec<BlockId> {
+        match *self {
+            Terminator::Jump(b) => vec![b],
+            Terminator::Branch { then_block, else_block, .. } => {
+                vec![then_block, else_block]
+            }
+            Terminator::Return(_) | Terminator::TailCall { .. } |
+            Terminator::Trap(_, _) | Terminator::Unreachable => vec![],
+        }
+    }
+
+    pub fn get_uses(&self) -> Vec<&Value> {
+        match *self {
+            Terminator::Branch { ref cond, .. } => vec![cond],
+            Terminator::Return(ref values) => values.iter().collect(),
+            Terminator::TailCall { ref args, .. } => args.iter().collect(),
+            Terminator::Jump(_) | Terminator::Trap(_, _) |
+            Terminator::Unreachable => vec![],
+        }
+    }
+}
+
+// The textual form, printed by --dump-ir. For example:
+//
+// function max(%0: i32, %1: i32) -> 1 word
+// bb0:
+//   %2 = gt %0, %1
+//   branch %2, bb1, bb2
+// bb1:
+//   return %0
+// ...
+
+impl fmt::Display for IrType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            IrType::I8 => "i8",
+            IrType::I32 => "i32",
+            IrType::Ptr => "ptr",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Reg(r) => write!(f, "%{}", r),
+            Value::Const(c) => write!(f, "{}", c),
+            Value::Global(ref label) => write!(f, "@{}", label),
+        }
+    }
+}
+
+impl fmt::Display for IrBinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            IrBinOp::Add => "add",
+            IrBinOp::Sub => "sub",
+            IrBinOp::Mul => "mul",
+            IrBinOp::Div => "div",
+            IrBinOp::Eq => "eq",
+            IrBinOp::Ne => "ne",
+            IrBinOp::Lt => "lt",
+            IrBinOp::Le => "le",
+            IrBinOp::Gt => "gt",
+            IrBinOp::Ge => "ge",
+            IrBinOp::Below => "below",
+            IrBinOp::BelowOrEqual => "below_or_equal",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for TrapKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            TrapKind::BoundsError => "bounds_error",
+            TrapKind::DivisionByZero => "division_by_zero",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter,
+                               items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+fn write_address(f: &mut fmt::Formatter, addr: &Value,
+                 offset: i32) -> fmt::Result {
+    if offset == 0 {
+        write!(f, "[{}]", addr)
+    } else {
+        write!(f, "[{} + {}]", addr, offset)
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instr::Copy { dest, ref src } => write!(f, "%{} = {}", dest, src),
+            Instr::Trunc { dest, ref src } => {
+                write!(f, "%{} = trunc {}", dest, src)
+            }
+            Instr::BinOp { dest, op, ref left, ref right, overflow_check } => {
+                write!(f, "%{} = {} {}, {}", dest, op, left, right)?;
+                if let Some(line) = overflow_check {
+                    write!(f, " !overflow_check line {}", line)?;
+                }
+                Ok(())
+            }
+            Instr::Load { dest, typ, ref addr, offset } => {
+                write!(f, "%{} = load {} ", dest, typ)?;
+                write_address(f, addr, offset)
+            }
+            Instr::Store { typ, ref value, ref addr, offset } => {
+                write!(f, "store {} {}, ", typ, value)?;
+                write_address(f, addr, offset)
+            }
+            Instr::FrameAddr { dest, slot } => {
+                write!(f, "%{} = frame_addr ${}", dest, slot)
+            }
+            Instr::Call { ref dests, ref name, ref args, .. } => {
+                if !dests.is_empty() {
+                    let dests: Vec<String> = dests.iter()
+                        .map(|d| format!("%{}", d))
+                        .collect();
+                    write!(f, "{} = ", dests.join(", "))?;
+                }
+                write!(f, "call {}(", name)?;
+                write_list(f, args)?;
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Terminator::Jump(b) => write!(f, "jump bb{}", b),
+            Terminator::Branch { ref cond, then_block, else_block } => {
+                write!(f, "branch {}, bb{}, bb{}", cond, then_block, else_block)
+            }
+            Terminator::Return(ref values) => {
+                write!(f, "return ")?;
+                write_list(f, values)
+            }
+            Terminator::TailCall { ref name, ref args, .. } => {
+                write!(f, "tail_call {}(", name)?;
+                write_list(f, args)?;
+                write!(f, ")")
+            }
+            Terminator::Trap(kind, line) => {
+                write!(f, "trap {} line {}", kind, line)
+            }
+            Terminator::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+
+impl fmt::Display for IrFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params: Vec<String> = self.params.iter()
+            .map(|&p| format!("%{}: {}", p, self.vreg_types[p]))
+            .collect();
+        if self.is_inline {
+            write!(f, "inline ")?;
+        }
+        writeln!(f, "function {}({}) -> {} word{}", self.name,
+                 params.join(", "), self.return_words,
+                 if self.return_words == 1 { "" } else { "s" })?;
+        for (i, size) in self.slots.iter().enumerate() {
+            writeln!(f, "  slot ${}: {} bytes", i, size)?;
+        }
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "bb{}:", i)?;
+            for instr in block.instrs.iter() {
+                writeln!(f, "  {}", instr)?;
+            }
+            writeln!(f, "  {}", block.terminator)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for IrProgram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (label, string) in self.strings.iter() {
+            writeln!(f, "@{} = {}", label, string)?;
+        }
+        for function in self.functions.iter() {
+            writeln!(f)?;
+            write!(f, "{}", function)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_format() {
+        let mut get = IrFunction::new("get", 1);
+        get.params = vec![0, 1];
+        get.vreg_types = vec![IrType::Ptr, IrType::I32, IrType::I32, IrType::I32];
+        get.blocks = vec![
+            BasicBlock {
+                instrs: vec![Instr::BinOp { dest: 2, op: IrBinOp::Below,
+                                            left: Value::Reg(1),
+                                            right: Value::Const(4),
+                                            overflow_check: None }],
+                lines: vec![2],
+                terminator: Terminator::Branch { cond: Value::Reg(2),
+                                                 then_block: 1, else_block: 2 },
+            },
+            BasicBlock {
+                instrs: vec![Instr::Load { dest: 3, typ: IrType::I32,
+                                           addr: Value::Reg(0), offset: 4 }],
+                lines: vec![3],
+                terminator: Terminator::Return(vec![Value::Reg(3)]),
+            },
+            BasicBlock {
+                instrs: vec![],
+                lines: vec![],
+                terminator: Terminator::Trap(TrapKind::BoundsError, 7),
+            },
+        ];
+
+        let mut main = IrFunction::new("main", 1);
+        main.is_inline = true;
+        main.params = vec![0];
+        main.vreg_types = vec![IrType::I32, IrType::Ptr, IrType::I32, IrType::I8];
+        main.slots = vec![8];
+        main.blocks = vec![
+            BasicBlock {
+                instrs: vec![
+                    Instr::FrameAddr { dest: 1, slot: 0 },
+                    Instr::Store { typ: IrType::I32, value: Value::Const(1),
+                                   addr: Value::Reg(1), offset: 0 },
+                    Instr::BinOp { dest: 2, op: IrBinOp::Add,
+                                   left: Value::Reg(0), right: Value::Const(1),
+                                   overflow_check: Some(12) },
+                    Instr::Trunc { dest: 3, src: Value::Reg(2) },
+                    Instr::Call { dests: vec![], name: "printf".to_string(),
+                                  args: vec![Value::Global("s0".to_string()),
+                                             Value::Reg(3)],
+                                  line: 13 },
+                ],
+                lines: vec![10, 11, 12, 12, 13],
+                terminator: Terminator::TailCall { name: "get".to_string(),
+                                                   args: vec![Value::Reg(1),
+                                                              Value::Reg(2)],
+                                                   line: 14 },
+            },
+        ];
+
+        let program = IrProgram {
+            functions: vec![get, main],
+            strings: vec![("s0".to_string(), "\"%c\\n\"".to_string())],
+        };
+        let expected = "@s0 = \"%c\\n\"
+
+function get(%0: ptr, %1: i32) -> 1 word
+bb0:
+  %2 = below %1, 4
+  branch %2, bb1, bb2
+bb1:
+  %3 = load i32 [%0 + 4]
+  return %3
+bb2:
+  trap bounds_error line 7
+
+inline function main(%0: i32) -> 1 word
+  slot $0: 8 bytes
+bb0:
+  %1 = frame_addr $0
+  store i32 1, [%1]
+  %2 = add %0, 1 !overflow_check line 12
+  %3 = trunc %2
+  call printf(@s0, %3)
+  tail_call get(%1, %2)
+";
+        assert_eq!(program.to_string(), expected);
+    }
+}
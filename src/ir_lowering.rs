@@ -0,0 +1,1004 @@
+use ast::AstExpressionNode;
+use ast::Block;
+use ast::BinaryOp;
+use ast::Expression;
+use ast::Function;
+use ast::FunctionCall;
+use ast::PointerType;
+use ast::Program;
+use ast::Statement;
+use ast::VarType;
+
+use ir::BasicBlock;
+use ir::BlockId;
+use ir::Instr;
+use ir::IrBinOp;
+use ir::IrFunction;
+use ir::IrProgram;
+use ir::IrType;
+use ir::SlotId;
+use ir::Terminator;
+use ir::TrapKind;
+use ir::VReg;
+use ir::Value;
+
+use ast_helper::collect_address_taken_block;
+use ast_helper::get_constant_value;
+use ast_helper::get_statement_line;
+use ast_helper::is_pointer;
+
+use assembly_helper::get_mtype_size;
+use assembly_helper::WORD_SIZE;
+
+use code_block::CodeBlock;
+
+use representation_manager::RepresentationManager;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// Turns the typed AST into the IR. This is where the layout of values is
+// decided: how big types are, where fields and a shared_pointer's value
+// are, and how arrays are passed around. The checks done at run time
+// (bounds, division by zero, --overflow-checks and --sanitize=memory) are
+// added here too.
+
+// Globals in runtime/memory_sanitizer.c that hold the location of the
+// code calling it
+const SANITIZER_LINE: &str = "__msan_line";
+const SANITIZER_FUNCTION: &str = "__msan_function";
+
+// The name of every function is stored at this prefix followed by the
+// function's name, for the memory sanitizer's error messages
+const SANITIZER_FN_NAME_PREFIX: &str = "__fn_name_";
+
+// The format print uses
+const DECIMAL_FORMAT_LABEL: &str = "decimal_format_str";
+
+// Where a local variable is kept
+#[derive(Clone)]
+enum Storage {
+    Register(VReg),
+    // The address of the first element, and the length
+    Array(VReg, VReg),
+    // Variables whose address is taken, and structs
+    Slot(SlotId),
+}
+
+fn is_array_expr(expr_node: &AstExpressionNode) -> bool {
+    matches!(expr_node.typ, Some(VarType::Array(_, _)))
+}
+
+fn get_ir_type(typ: &VarType) -> IrType {
+    match *typ {
+        VarType::Int => IrType::I32,
+        VarType::Char => IrType::I8,
+        VarType::Pointer(_, _) => IrType::Ptr,
+        _ => panic!("{:?} doesn't fit in a register", typ),
+    }
+}
+
+// Where the value a pointer points to starts. shared_pointers point to
+// their reference count, which comes right before the value.
+fn get_payload_offset(ptr_type: &VarType) -> i32 {
+    match *ptr_type {
+        VarType::Pointer(PointerType::Shared, _) => WORD_SIZE,
+        _ => 0,
+    }
+}
+
+fn get_ir_binop(op: BinaryOp) -> IrBinOp {
+    match op {
+        BinaryOp::Plus => IrBinOp::Add,
+        BinaryOp::Minus => IrBinOp::Sub,
+        BinaryOp::Multiply => IrBinOp::Mul,
+        BinaryOp::Divide => IrBinOp::Div,
+        BinaryOp::CompareEqual => IrBinOp::Eq,
+        BinaryOp::CompareNotEqual => IrBinOp::Ne,
+        BinaryOp::CompareLess => IrBinOp::Lt,
+        BinaryOp::CompareLessOrEqual => IrBinOp::Le,
+        BinaryOp::CompareGreater => IrBinOp::Gt,
+        BinaryOp::CompareGreaterOrEqual => IrBinOp::Ge,
+    }
+}
+
+pub struct IrLowering {
+    representation_mgr: RepresentationManager,
+    function_to_return_type: HashMap<String, VarType>,
+
+    strings: Vec<(String, String)>,
+    string_to_label: HashMap<String, String>,
+
+    // Whether to check + - * / for signed overflow (--overflow-checks)
+    overflow_checks: bool,
+
+    // Whether to use the memory sanitizer runtime for allocations, and
+    // check every dereference with it (--sanitize=memory)
+    sanitize_memory: bool,
+
+    // The function being lowered, and the block we're adding to
+    function: IrFunction,
+    current_block: BlockId,
+
+    variable_to_storage: HashMap<String, Storage>,
+    blocks: Vec<CodeBlock>,
+    address_taken: HashSet<String>,
+
+    // Slots of variables whose block has ended, which the variables of
+    // later blocks can have
+    free_slots: Vec<SlotId>,
+
+    // The line of the statement being lowered
+    line: usize,
+}
+
+impl IrLowering {
+    pub fn new() -> IrLowering {
+        IrLowering {
+            representation_mgr: RepresentationManager::new(),
+            function_to_return_type: HashMap::new(),
+            strings: Vec::new(),
+            string_to_label: HashMap::new(),
+            overflow_checks: false,
+            sanitize_memory: false,
+            function: IrFunction::new("", 1),
+            current_block: 0,
+            variable_to_storage: HashMap::new(),
+            blocks: Vec::new(),
+            address_taken: HashSet::new(),
+            free_slots: Vec::new(),
+            line: 0,
+        }
+    }
+
+    pub fn set_overflow_checks(&mut self, overflow_checks: bool) {
+        self.overflow_checks = overflow_checks;
+    }
+
+    pub fn set_sanitize_memory(&mut self, sanitize_memory: bool) {
+        self.sanitize_memory = sanitize_memory;
+    }
+
+    fn new_vreg(&mut self, typ: IrType) -> VReg {
+        self.function.vreg_types.push(typ);
+        self.function.vreg_types.len() - 1
+    }
+
+    fn new_slot(&mut self, size: i32) -> SlotId {
+        // Blocks that never run at the same time share their slots
+        let slots = &self.function.slots;
+        if let Some(i) = self.free_slots.iter().position(|&slot| slots[slot] >= size) {
+            return self.free_slots.remove(i);
+        }
+        self.function.slots.push(size);
+        self.function.slots.len() - 1
+    }
+
+    // Blocks start out unreachable, until they're given a terminator
+    fn new_block(&mut self) -> BlockId {
+        self.function.blocks.push(BasicBlock {
+            instrs: Vec::new(),
+            lines: Vec::new(),
+            terminator: Terminator::Unreachable,
+        });
+        self.function.blocks.len() - 1
+    }
+
+    fn emit(&mut self, instr: Instr) {
+        let block = &mut self.function.blocks[self.current_block];
+        block.instrs.push(instr);
+        block.lines.push(self.line);
+    }
+
+    // End the current block, and continue in the given one
+    fn terminate(&mut self, terminator: Terminator, next_block: BlockId) {
+        self.function.blocks[self.current_block].terminator = terminator;
+        self.current_block = next_block;
+    }
+
+    fn get_type_size(&self, typ: &VarType) -> i32 {
+        get_mtype_size(self.representation_mgr.get_machine_type(typ))
+    }
+
+    fn get_string_label(&mut self, string: &str) -> String {
+        if let Some(label) = self.string_to_label.get(string) {
+            return label.clone();
+        }
+
+        let label = format!(".LC{}", self.string_to_label.len());
+        self.string_to_label.insert(string.to_string(), label.clone());
+        self.strings.push((label.clone(), string.to_string()));
+        label
+    }
+
+    fn get_return_type(&self, name: &str) -> VarType {
+        match self.function_to_return_type.get(name) {
+            Some(typ) => typ.clone(),
+            None => match name {
+                "allocate" | "alloc_int" | "alloc_owned_int" |
+                "__shared_alloc" => {
+                    VarType::Pointer(PointerType::Raw, Box::new(VarType::Char))
+                }
+                _ => VarType::Int,
+            },
+        }
+    }
+
+    fn binop(&mut self, op: IrBinOp, left: Value, right: Value,
+             typ: IrType) -> Value {
+        let dest = self.new_vreg(typ);
+        self.emit(Instr::BinOp {
+            dest,
+            op,
+            left,
+            right,
+            overflow_check: None,
+        });
+        Value::Reg(dest)
+    }
+
+    fn add_offset(&mut self, addr: Value, offset: i32) -> Value {
+        if offset == 0 {
+            addr
+        } else {
+            self.binop(IrBinOp::Add, addr, Value::Const(offset), IrType::Ptr)
+        }
+    }
+
+    fn load(&mut self, typ: &VarType, addr: Value, offset: i32) -> Value {
+        let ir_type = get_ir_type(typ);
+        let dest = self.new_vreg(ir_type);
+        self.emit(Instr::Load {
+            dest,
+            typ: ir_type,
+            addr,
+            offset,
+        });
+        Value::Reg(dest)
+    }
+
+    fn frame_addr(&mut self, slot: SlotId) -> Value {
+        let dest = self.new_vreg(IrType::Ptr);
+        self.emit(Instr::FrameAddr { dest, slot });
+        Value::Reg(dest)
+    }
+
+    fn call(&mut self, name: &str, args: Vec<Value>, dest_types: &[IrType],
+            line: usize) -> Vec<Value> {
+        let dests: Vec<VReg> = dest_types.iter()
+            .map(|&typ| self.new_vreg(typ))
+            .collect();
+        self.emit(Instr::Call {
+            dests: dests.clone(),
+            name: name.to_string(),
+            args,
+            line,
+        });
+        dests.into_iter().map(Value::Reg).collect()
+    }
+
+    // Go on to a new block if cond isn't 0, or stop the program
+    fn check(&mut self, cond: Value, trap: TrapKind, line: usize) {
+        let trap_block = self.new_block();
+        let ok_block = self.new_block();
+        self.terminate(Terminator::Branch {
+            cond,
+            then_block: ok_block,
+            else_block: trap_block,
+        }, trap_block);
+        self.terminate(Terminator::Trap(trap, line), ok_block);
+    }
+
+    // Copy value to a register holding a variable, keeping only the low
+    // byte if the variable is a char
+    fn assign_register(&mut self, dest: VReg, value: Value) {
+        let is_byte = match value {
+            Value::Reg(r) => self.function.vreg_types[r] == IrType::I8,
+            Value::Const(c) => (0..256).contains(&c),
+            Value::Global(_) => false,
+        };
+
+        if self.function.vreg_types[dest] == IrType::I8 && !is_byte {
+            self.emit(Instr::Trunc { dest, src: value });
+        } else {
+            self.emit(Instr::Copy { dest, src: value });
+        }
+    }
+
+    // The memory sanitizer's version of an allocation function
+    fn get_allocator_name(&self, name: &str) -> String {
+        if self.sanitize_memory {
+            format!("__msan_{}", name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    // Tell the memory sanitizer where the code calling it is, so it can
+    // say where an error happened
+    fn set_sanitizer_location(&mut self, line: usize) {
+        if !self.sanitize_memory {
+            return;
+        }
+
+        self.emit(Instr::Store {
+            typ: IrType::I32,
+            value: Value::Const(line as i32),
+            addr: Value::Global(SANITIZER_LINE.to_string()),
+            offset: 0,
+        });
+        let fn_name = format!("{}{}", SANITIZER_FN_NAME_PREFIX,
+                              self.function.name);
+        self.emit(Instr::Store {
+            typ: IrType::Ptr,
+            value: Value::Global(fn_name),
+            addr: Value::Global(SANITIZER_FUNCTION.to_string()),
+            offset: 0,
+        });
+    }
+
+    // Have the memory sanitizer check that the value a pointer of type
+    // ptr_type points to, at addr + offset, can be accessed
+    fn check_memory_access(&mut self, addr: &Value, offset: i32,
+                           ptr_type: &VarType, line: usize) {
+        if !self.sanitize_memory {
+            return;
+        }
+
+        let size = match *ptr_type {
+            VarType::Pointer(_, ref t) => self.get_type_size(t),
+            _ => panic!("Cannot dereference non pointer"),
+        };
+
+        self.set_sanitizer_location(line);
+        let access = self.add_offset(addr.clone(), offset);
+        self.call("__msan_check_access", vec![access, Value::Const(size)],
+                  &[], line);
+    }
+
+    // Return the address of an assignable expression, as a value and an
+    // offset from it
+    fn lower_address(&mut self, expr_node: &AstExpressionNode) -> (Value, i32) {
+        match expr_node.expr {
+            Expression::Variable(ref name) => {
+                match self.variable_to_storage.get(name).cloned() {
+                    Some(Storage::Slot(slot)) => (self.frame_addr(slot), 0),
+                    _ => panic!("{} is kept in a register, so it has no \
+                                 address", name),
+                }
+            }
+            Expression::Dereference(ref expr) => {
+                let addr = self.lower_expr(expr);
+                let typ = expr.typ.as_ref().unwrap();
+                let offset = get_payload_offset(typ);
+                self.check_memory_access(&addr, offset, typ, expr.line);
+                (addr, offset)
+            }
+            Expression::FieldAccess(ref expr, ref field_name) => {
+                let (addr, struct_offset) = self.lower_address(expr);
+
+                // The field is at its offset within the struct
+                let field_offset = self.representation_mgr
+                    .get_field_info(expr.typ.as_ref().unwrap(), field_name)
+                    .offset;
+                (addr, struct_offset + field_offset)
+            }
+            Expression::Index(ref array, ref index) => {
+                let index_value = self.lower_expr(index);
+                let (addr, len) = self.lower_array(array);
+
+                // Make sure 0 <= index < length. Comparing them as unsigned
+                // numbers catches negative indices too.
+                let in_bounds = self.binop(IrBinOp::Below, index_value.clone(),
+                                           len, IrType::I32);
+                self.check(in_bounds, TrapKind::BoundsError, index.line);
+
+                let element_size = self.get_element_size(array);
+                let offset = self.binop(IrBinOp::Mul, index_value,
+                                        Value::Const(element_size),
+                                        IrType::I32);
+                (self.binop(IrBinOp::Add, addr, offset, IrType::Ptr), 0)
+            }
+            _ => panic!("Cannot take the address of {:?}", expr_node.expr),
+        }
+    }
+
+    fn get_element_size(&self, array: &AstExpressionNode) -> i32 {
+        match array.typ {
+            Some(VarType::Array(_, ref element_type)) => {
+                self.get_type_size(element_type)
+            }
+            _ => panic!("Not an array!"),
+        }
+    }
+
+    // &*p is p, except that a shared_pointer points to its count. An empty
+    // one gives 0 instead of the address after the count.
+    fn lower_address_of_dereference(&mut self,
+                                    ptr_expr: &AstExpressionNode) -> Value {
+        let ptr = self.lower_expr(ptr_expr);
+        let offset = get_payload_offset(ptr_expr.typ.as_ref().unwrap());
+        if offset == 0 {
+            return ptr;
+        }
+
+        let not_empty = self.binop(IrBinOp::Ne, ptr.clone(), Value::Const(0),
+                                   IrType::I32);
+        let offset = self.binop(IrBinOp::Mul, not_empty, Value::Const(offset),
+                                IrType::I32);
+        self.binop(IrBinOp::Add, ptr, offset, IrType::Ptr)
+    }
+
+    fn lower_binary_op(&mut self, expr_node: &AstExpressionNode, op: BinaryOp,
+                       l_node: &AstExpressionNode,
+                       r_node: &AstExpressionNode) -> Value {
+        let left = self.lower_expr(l_node);
+        let right = self.lower_expr(r_node);
+
+        // We don't need to check a divisor we know isn't zero (the type
+        // checker doesn't allow ones we know are zero)
+        if op == BinaryOp::Divide && get_constant_value(r_node).is_none() {
+            let not_zero = self.binop(IrBinOp::Ne, right.clone(),
+                                      Value::Const(0), IrType::I32);
+            self.check(not_zero, TrapKind::DivisionByZero, r_node.line);
+        }
+
+        // Pointer arithmetic is left alone, it's checked by other means
+        let is_arithmetic = op == BinaryOp::Plus || op == BinaryOp::Minus ||
+            op == BinaryOp::Multiply || op == BinaryOp::Divide;
+        let check_overflow = self.overflow_checks && is_arithmetic &&
+            !l_node.typ.as_ref().is_some_and(is_pointer) &&
+            !r_node.typ.as_ref().is_some_and(is_pointer);
+
+        let typ = if expr_node.typ.as_ref().is_some_and(is_pointer) {
+            IrType::Ptr
+        } else {
+            IrType::I32
+        };
+        let dest = self.new_vreg(typ);
+        self.emit(Instr::BinOp {
+            dest,
+            op: get_ir_binop(op),
+            left,
+            right,
+            overflow_check: if check_overflow { Some(l_node.line) } else { None },
+        });
+        Value::Reg(dest)
+    }
+
+    // Lower an expression that fits in a register
+    fn lower_expr(&mut self, expr_node: &AstExpressionNode) -> Value {
+        // The value of an array in a register is the address of its first
+        // element. That's enough to free it or check if it's empty.
+        if is_array_expr(expr_node) {
+            return self.lower_array(expr_node).0;
+        }
+
+        match expr_node.expr {
+            Expression::Value(v) => Value::Const(v),
+            Expression::SizeOf(ref typ) => Value::Const(self.get_type_size(typ)),
+            Expression::StringValue(ref s) => {
+                Value::Global(self.get_string_label(s))
+            }
+            Expression::Variable(ref name) => {
+                match self.variable_to_storage.get(name).cloned() {
+                    Some(Storage::Register(reg)) => Value::Reg(reg),
+                    Some(Storage::Slot(_)) => {
+                        let (addr, offset) = self.lower_address(expr_node);
+                        self.load(expr_node.typ.as_ref().unwrap(), addr, offset)
+                    }
+                    _ => panic!("Unknown variable {}", name),
+                }
+            }
+            Expression::BinaryOp(op, ref l, ref r) => {
+                self.lower_binary_op(expr_node, op, l, r)
+            }
+            Expression::Call(ref fn_call) => {
+                self.lower_call(fn_call, expr_node.line).remove(0)
+            }
+            Expression::Reference(ref expr) => {
+                if let Expression::Dereference(ref ptr_expr) = expr.expr {
+                    return self.lower_address_of_dereference(ptr_expr);
+                }
+
+                let (addr, offset) = self.lower_address(expr);
+                self.add_offset(addr, offset)
+            }
+            Expression::Dereference(_) | Expression::FieldAccess(_, _) |
+            Expression::Index(_, _) => {
+                let (addr, offset) = self.lower_address(expr_node);
+                self.load(expr_node.typ.as_ref().unwrap(), addr, offset)
+            }
+            Expression::Slice(_, _, _) => {
+                panic!("Slices are arrays, and handled by lower_array")
+            }
+        }
+    }
+
+    // Lower an array expression to the address of its first element and
+    // its length
+    fn lower_array(&mut self, expr_node: &AstExpressionNode) -> (Value, Value) {
+        match expr_node.expr {
+            Expression::Variable(ref name) => {
+                match self.variable_to_storage.get(name) {
+                    Some(&Storage::Array(addr, len)) => {
+                        (Value::Reg(addr), Value::Reg(len))
+                    }
+                    _ => panic!("{} is not an array", name),
+                }
+            }
+            // Moved-from arrays are set to 0
+            Expression::Value(0) => (Value::Const(0), Value::Const(0)),
+            // new_array(T, n) is calloc(n, sizeof(T))
+            Expression::Call(ref fn_call) if fn_call.name == "new_array" => {
+                let line = fn_call.args_exprs[1].line;
+                let len = self.lower_expr(&fn_call.args_exprs[1]);
+                let size = self.lower_expr(&fn_call.args_exprs[0]);
+
+                // A negative length would pass the unsigned bounds checks
+                let len_ok = self.binop(IrBinOp::Ge, len.clone(),
+                                        Value::Const(0), IrType::I32);
+                self.check(len_ok, TrapKind::BoundsError, line);
+
+                self.set_sanitizer_location(line);
+                let calloc = self.get_allocator_name("calloc");
+                let addr = self.call(&calloc, vec![len.clone(), size],
+                                     &[IrType::Ptr], line).remove(0);
+
+                // Indexing a failed allocation would write near address 0
+                let addr_ok = self.binop(IrBinOp::Ne, addr.clone(),
+                                         Value::Const(0), IrType::I32);
+                self.check(addr_ok, TrapKind::BoundsError, line);
+                (addr, len)
+            }
+            Expression::Call(ref fn_call) => {
+                let mut words = self.lower_call(fn_call, expr_node.line);
+                let len = words.pop().unwrap();
+                (words.pop().unwrap(), len)
+            }
+            Expression::Slice(ref array, ref start, ref end) => {
+                let end_value = self.lower_expr(end);
+                let start_value = self.lower_expr(start);
+                let (addr, len) = self.lower_array(array);
+
+                // Make sure 0 <= start <= end <= length
+                let end_ok = self.binop(IrBinOp::BelowOrEqual, end_value.clone(),
+                                        len, IrType::I32);
+                self.check(end_ok, TrapKind::BoundsError, end.line);
+                let start_ok = self.binop(IrBinOp::BelowOrEqual,
+                                          start_value.clone(),
+                                          end_value.clone(), IrType::I32);
+                self.check(start_ok, TrapKind::BoundsError, start.line);
+
+                // The view starts at the start'th element, and has
+                // end - start elements
+                let view_len = self.binop(IrBinOp::Sub, end_value,
+                                          start_value.clone(), IrType::I32);
+                let element_size = self.get_element_size(array);
+                let offset = self.binop(IrBinOp::Mul, start_value,
+                                        Value::Const(element_size),
+                                        IrType::I32);
+                (self.binop(IrBinOp::Add, addr, offset, IrType::Ptr), view_len)
+            }
+            _ => panic!("Cannot evaluate {:?} as an array", expr_node.expr),
+        }
+    }
+
+    // The functions the owned_pointer transformer inserts to manage
+    // shared_pointers. A shared_pointer points to its reference count.
+    fn lower_shared_pointer_call(&mut self, fn_call: &FunctionCall) -> Value {
+        let arg = self.lower_expr(&fn_call.args_exprs[0]);
+
+        match &fn_call.name[..] {
+            // Allocate room for the count too, and start it at 1
+            "__shared_alloc" => {
+                let size = self.binop(IrBinOp::Add, arg,
+                                      Value::Const(WORD_SIZE), IrType::I32);
+                self.set_sanitizer_location(fn_call.args_exprs[0].line);
+                let calloc = self.get_allocator_name("calloc");
+                let ptr = self.call(&calloc, vec![Value::Const(1), size],
+                                    &[IrType::Ptr],
+                                    fn_call.args_exprs[0].line).remove(0);
+                self.emit(Instr::Store {
+                    typ: IrType::I32,
+                    value: Value::Const(1),
+                    addr: ptr.clone(),
+                    offset: 0,
+                });
+                ptr
+            }
+            // Increment the count, unless the pointer is 0
+            "__shared_retain" => {
+                let is_empty = self.binop(IrBinOp::Eq, arg.clone(),
+                                          Value::Const(0), IrType::I32);
+                let increment_block = self.new_block();
+                let done_block = self.new_block();
+                self.terminate(Terminator::Branch {
+                    cond: is_empty,
+                    then_block: done_block,
+                    else_block: increment_block,
+                }, increment_block);
+
+                let count = self.load(&VarType::Int, arg.clone(), 0);
+                let count = self.binop(IrBinOp::Add, count, Value::Const(1),
+                                       IrType::I32);
+                self.emit(Instr::Store {
+                    typ: IrType::I32,
+                    value: count,
+                    addr: arg,
+                    offset: 0,
+                });
+                self.terminate(Terminator::Jump(done_block), done_block);
+                Value::Const(0)
+            }
+            // Decrement the count, unless the pointer is 0. Gives 1 if the
+            // count reached 0, and the value has to be destroyed.
+            "__shared_release" => {
+                let result = self.new_vreg(IrType::I32);
+                self.emit(Instr::Copy { dest: result, src: Value::Const(0) });
+
+                let is_empty = self.binop(IrBinOp::Eq, arg.clone(),
+                                          Value::Const(0), IrType::I32);
+                let decrement_block = self.new_block();
+                let done_block = self.new_block();
+                self.terminate(Terminator::Branch {
+                    cond: is_empty,
+                    then_block: done_block,
+                    else_block: decrement_block,
+                }, decrement_block);
+
+                let count = self.load(&VarType::Int, arg.clone(), 0);
+                let count = self.binop(IrBinOp::Sub, count, Value::Const(1),
+                                       IrType::I32);
+                self.emit(Instr::Store {
+                    typ: IrType::I32,
+                    value: count.clone(),
+                    addr: arg,
+                    offset: 0,
+                });
+                self.emit(Instr::BinOp {
+                    dest: result,
+                    op: IrBinOp::Eq,
+                    left: count,
+                    right: Value::Const(0),
+                    overflow_check: None,
+                });
+                self.terminate(Terminator::Jump(done_block), done_block);
+                Value::Reg(result)
+            }
+            _ => panic!("Unknown shared_pointer function {}", fn_call.name),
+        }
+    }
+
+    // Return the words the call gives back: two for an array, otherwise one
+    // line is where the call starts, which is what tail calls are found by
+    fn lower_call(&mut self, fn_call: &FunctionCall, line: usize) -> Vec<Value> {
+        if fn_call.name == "len" {
+            return vec![self.lower_array(&fn_call.args_exprs[0]).1];
+        }
+        if matches!(&fn_call.name[..],
+                    "__shared_alloc" | "__shared_retain" | "__shared_release") {
+            return vec![self.lower_shared_pointer_call(fn_call)];
+        }
+
+        // Arguments are evaluated last to first. free only needs the
+        // address of an array's elements.
+        let mut arg_words = Vec::new();
+        for arg_expr in fn_call.args_exprs.iter().rev() {
+            if is_array_expr(arg_expr) && fn_call.name != "free" {
+                let (addr, len) = self.lower_array(arg_expr);
+                arg_words.push(vec![addr, len]);
+            } else {
+                arg_words.push(vec![self.lower_expr(arg_expr)]);
+            }
+        }
+        arg_words.reverse();
+        let mut args: Vec<Value> = arg_words.into_iter().flatten().collect();
+
+        let name = match &fn_call.name[..] {
+            "alloc_int" | "alloc_owned_int" => {
+                // alloc_int(n) allocates n ints
+                args[0] = self.binop(IrBinOp::Mul, args[0].clone(),
+                                     Value::Const(WORD_SIZE), IrType::I32);
+                self.get_allocator_name("malloc")
+            }
+            "allocate" => {
+                // allocate(n) is calloc(1, n), so the memory starts out
+                // zeroed and any owned_pointers stored in it start out empty
+                args.insert(0, Value::Const(1));
+                self.get_allocator_name("calloc")
+            }
+            "free" | "free_int" => self.get_allocator_name("free"),
+            _ => fn_call.name.clone(),
+        };
+        if name.starts_with("__msan_") {
+            self.set_sanitizer_location(fn_call.args_exprs[0].line);
+        }
+
+        let dest_types = match self.get_return_type(&fn_call.name) {
+            VarType::Array(_, _) => vec![IrType::Ptr, IrType::I32],
+            VarType::Pointer(_, _) => vec![IrType::Ptr],
+            // Chars aren't cut down to a byte until they're stored
+            _ => vec![IrType::I32],
+        };
+        self.call(&name, args, &dest_types, line)
+    }
+
+    fn declare_variable(&mut self, name: &str, storage: Storage) {
+        self.blocks.last_mut()
+            .expect("No current block!")
+            .declared_variables.insert(name.to_string());
+        self.variable_to_storage.insert(name.to_string(), storage);
+    }
+
+    // Where a new variable of the given type is kept
+    fn new_storage(&mut self, name: &str, typ: &VarType) -> Storage {
+        match *typ {
+            VarType::Array(_, _) => {
+                let addr = self.new_vreg(IrType::Ptr);
+                let len = self.new_vreg(IrType::I32);
+                Storage::Array(addr, len)
+            }
+            VarType::Struct(_) => {
+                Storage::Slot(self.new_slot(self.get_type_size(typ)))
+            }
+            _ if self.address_taken.contains(name) => {
+                Storage::Slot(self.new_slot(self.get_type_size(typ)))
+            }
+            _ => Storage::Register(self.new_vreg(get_ir_type(typ))),
+        }
+    }
+
+    fn lower_assign(&mut self, left: &AstExpressionNode,
+                    right: &AstExpressionNode) {
+        let storage = match left.expr {
+            Expression::Variable(ref name) => {
+                self.variable_to_storage.get(name).cloned()
+            }
+            _ => None,
+        };
+
+        match storage {
+            // Arrays are only stored in local variables
+            Some(Storage::Array(addr, len)) => {
+                let (addr_value, len_value) = self.lower_array(right);
+                self.emit(Instr::Copy { dest: addr, src: addr_value });
+                self.emit(Instr::Copy { dest: len, src: len_value });
+            }
+            Some(Storage::Register(reg)) => {
+                let value = self.lower_expr(right);
+                self.assign_register(reg, value);
+            }
+            _ => {
+                let typ = left.typ.as_ref().unwrap();
+                if let VarType::Struct(_) = *typ {
+                    panic!("Cannot copy a whole struct");
+                }
+
+                // Figure out where we're going to store this, then what
+                let (addr, offset) = self.lower_address(left);
+                let value = self.lower_expr(right);
+                self.emit(Instr::Store {
+                    typ: get_ir_type(typ),
+                    value,
+                    addr,
+                    offset,
+                });
+            }
+        }
+    }
+
+    fn lower_statement(&mut self, stmt: &Statement) {
+        self.line = get_statement_line(stmt);
+        match *stmt {
+            Statement::Return(ref expr) => {
+                let values = if is_array_expr(expr) {
+                    let (addr, len) = self.lower_array(expr);
+                    vec![addr, len]
+                } else {
+                    vec![self.lower_expr(expr)]
+                };
+
+                // Anything after the return is unreachable
+                let next_block = self.new_block();
+                self.terminate(Terminator::Return(values), next_block);
+            }
+            Statement::Print(ref expr) => {
+                let value = self.lower_expr(expr);
+                let format = Value::Global(DECIMAL_FORMAT_LABEL.to_string());
+                self.call("printf", vec![format, value], &[], expr.line);
+                self.call("fflush", vec![Value::Const(0)], &[], expr.line);
+            }
+            Statement::If(ref condition, ref then_block, ref else_block_opt) => {
+                let cond = self.lower_expr(condition);
+                let then_start = self.new_block();
+                let else_start = if else_block_opt.is_some() {
+                    Some(self.new_block())
+                } else {
+                    None
+                };
+                let after = self.new_block();
+
+                self.terminate(Terminator::Branch {
+                    cond,
+                    then_block: then_start,
+                    else_block: else_start.unwrap_or(after),
+                }, then_start);
+                self.lower_block(then_block);
+
+                if let Some(ref else_block) = *else_block_opt {
+                    self.terminate(Terminator::Jump(after), else_start.unwrap());
+                    self.lower_block(else_block);
+                }
+                self.terminate(Terminator::Jump(after), after);
+            }
+            Statement::While(ref condition, ref block) => {
+                // The condition comes after the body, so each iteration
+                // only needs one jump
+                let body_start = self.new_block();
+                let condition_start = self.new_block();
+                let after = self.new_block();
+
+                self.terminate(Terminator::Jump(condition_start), body_start);
+                self.lower_block(block);
+                self.terminate(Terminator::Jump(condition_start),
+                               condition_start);
+
+                self.line = condition.line;
+                let cond = self.lower_expr(condition);
+                self.terminate(Terminator::Branch {
+                    cond,
+                    then_block: body_start,
+                    else_block: after,
+                }, after);
+            }
+            Statement::Let(ref name, ref typ, ref value_opt) => {
+                let storage = self.new_storage(name, typ);
+
+                match storage {
+                    Storage::Array(addr, len) => {
+                        let (addr_value, len_value) = match *value_opt {
+                            Some(ref value) => self.lower_array(value),
+                            None => (Value::Const(0), Value::Const(0)),
+                        };
+                        self.emit(Instr::Copy { dest: addr, src: addr_value });
+                        self.emit(Instr::Copy { dest: len, src: len_value });
+                    }
+                    // Registers always hold something, so they start at 0
+                    Storage::Register(reg) => {
+                        let value = match *value_opt {
+                            Some(ref value) => self.lower_expr(value),
+                            None => Value::Const(0),
+                        };
+                        self.assign_register(reg, value);
+                    }
+                    Storage::Slot(slot) => {
+                        if let Some(ref value) = *value_opt {
+                            let value = self.lower_expr(value);
+                            let addr = self.frame_addr(slot);
+                            self.emit(Instr::Store {
+                                typ: get_ir_type(typ),
+                                value,
+                                addr,
+                                offset: 0,
+                            });
+                        }
+                    }
+                }
+                self.declare_variable(name, storage);
+            }
+            Statement::Assign(ref left, ref right) => {
+                self.lower_assign(left, right);
+            }
+            Statement::Call(ref fn_call) => {
+                let line = self.line;
+                self.lower_call(fn_call, line);
+            }
+            Statement::Unsafe(_, ref block) => self.lower_block(block),
+        }
+    }
+
+    fn lower_block(&mut self, block: &Block) {
+        self.blocks.push(CodeBlock::new());
+        for stmt in block.statements.iter() {
+            self.lower_statement(stmt);
+        }
+
+        // The block's variables can't be used anymore, so their slots are
+        // free again
+        let b = self.blocks.pop().expect("No current block!");
+        for variable in b.declared_variables {
+            if let Some(Storage::Slot(slot)) = self.variable_to_storage.remove(&variable) {
+                self.free_slots.push(slot);
+            }
+        }
+    }
+
+    fn lower_function(&mut self, fun: &Function) -> IrFunction {
+        let return_words = match fun.fn_type.return_type {
+            VarType::Array(_, _) => 2,
+            _ => 1,
+        };
+        self.function = IrFunction::new(&fun.name, return_words);
+        self.function.is_inline = fun.is_inline;
+        self.current_block = self.new_block();
+
+        self.address_taken.clear();
+        collect_address_taken_block(&fun.statements, &mut self.address_taken);
+
+        // Every argument takes up a word, except arrays, which take up two.
+        // Arguments that are kept somewhere else are copied there first.
+        self.blocks.push(CodeBlock::new());
+        for (name, typ) in fun.args.iter().zip(fun.fn_type.arg_types.iter()) {
+            let storage = self.new_storage(name, typ);
+            match storage {
+                Storage::Array(addr, len) => {
+                    self.function.params.push(addr);
+                    self.function.params.push(len);
+                }
+                // chars are passed as a whole word
+                Storage::Register(reg) if *typ == VarType::Char => {
+                    let param = self.new_vreg(IrType::I32);
+                    self.function.params.push(param);
+                    self.assign_register(reg, Value::Reg(param));
+                }
+                Storage::Register(reg) => self.function.params.push(reg),
+                Storage::Slot(slot) => {
+                    let param = self.new_vreg(get_ir_type(typ));
+                    self.function.params.push(param);
+                    let addr = self.frame_addr(slot);
+                    self.emit(Instr::Store {
+                        typ: get_ir_type(typ),
+                        value: Value::Reg(param),
+                        addr,
+                        offset: 0,
+                    });
+                }
+            }
+            self.declare_variable(name, storage);
+        }
+
+        self.lower_block(&fun.statements);
+        self.blocks.pop();
+        self.variable_to_storage.clear();
+        self.free_slots.clear();
+
+        // main returns 0 if it gets to the end. Other functions can't get
+        // there, the type checker makes sure they return first.
+        let end = if fun.name == "main" {
+            Terminator::Return(vec![Value::Const(0)])
+        } else {
+            Terminator::Unreachable
+        };
+        let current_block = self.current_block;
+        self.terminate(end, current_block);
+
+        ::std::mem::replace(&mut self.function, IrFunction::new("", 1))
+    }
+
+    pub fn lower_program(&mut self, prog: &Program) -> IrProgram {
+        self.representation_mgr.init(&prog.structs);
+        for fun in prog.functions.iter() {
+            self.function_to_return_type.insert(fun.name.clone(),
+                                                fun.fn_type.return_type.clone());
+        }
+
+        self.strings.push((DECIMAL_FORMAT_LABEL.to_string(),
+                           "\"%d\\n\"".to_string()));
+        if self.sanitize_memory {
+            for fun in prog.functions.iter() {
+                self.strings.push((format!("{}{}", SANITIZER_FN_NAME_PREFIX,
+                                           fun.name),
+                                   format!("\"{}\"", fun.name)));
+            }
+        }
+
+        let functions = prog.functions.iter()
+            .map(|fun| self.lower_function(fun))
+            .collect();
+        IrProgram {
+            functions,
+            strings: self.strings.clone(),
+        }
+    }
+}
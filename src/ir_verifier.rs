@@ -0,0 +1,409 @@
+use ir::Instr;
+use ir::IrBinOp;
+use ir::IrFunction;
+use ir::IrProgram;
+use ir::IrType;
+use ir::Terminator;
+use ir::Value;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// Checks that IR is well formed, so that a pass which breaks it is caught
+// right after it runs instead of as wrong assembly. The IR is fine if
+// verify_program gives no errors.
+
+struct Verifier<'a> {
+    function: &'a IrFunction,
+    // Name -> (argument words, returned words) of the program's functions
+    signatures: &'a HashMap<String, (usize, usize)>,
+    // Registers which are written somewhere in the function
+    defined: HashSet<usize>,
+    errors: Vec<String>,
+}
+
+impl<'a> Verifier<'a> {
+    fn error(&mut self, message: String) {
+        self.errors.push(format!("In {}: {}", self.function.name, message));
+    }
+
+    fn check_reg(&mut self, reg: usize, place: &str) {
+        if reg >= self.function.vreg_types.len() {
+            self.error(format!("{}: %{} has no type", place, reg));
+        }
+    }
+
+    // The type of a value, if it's a register with a type
+    fn get_type(&self, value: &Value) -> Option<IrType> {
+        match *value {
+            Value::Reg(r) => self.function.vreg_types.get(r).cloned(),
+            Value::Const(_) => None,
+            Value::Global(_) => Some(IrType::Ptr),
+        }
+    }
+
+    fn check_use(&mut self, value: &Value, place: &str) {
+        if let Value::Reg(r) = *value {
+            self.check_reg(r, place);
+            if !self.defined.contains(&r) {
+                self.error(format!("{}: %{} is used but never written", place, r));
+            }
+        }
+    }
+
+    fn check_block_id(&mut self, block: usize, place: &str) {
+        if block >= self.function.blocks.len() {
+            self.error(format!("{}: bb{} doesn't exist", place, block));
+        }
+    }
+
+    // A register holding a char must always hold a value that fits in a
+    // byte, so only a char or a small constant can be copied into one
+    fn check_fits(&mut self, dest: usize, value: &Value, place: &str) {
+        if self.function.vreg_types.get(dest) != Some(&IrType::I8) {
+            return;
+        }
+
+        let fits = match *value {
+            Value::Const(c) => (0..256).contains(&c),
+            _ => self.get_type(value) == Some(IrType::I8),
+        };
+        if !fits {
+            self.error(format!("{}: {} may not fit in the i8 %{}", place,
+                               value, dest));
+        }
+    }
+
+    fn check_instr(&mut self, instr: &Instr, place: &str) {
+        for value in instr.get_uses() {
+            self.check_use(value, place);
+        }
+        for dest in instr.get_dests() {
+            self.check_reg(dest, place);
+        }
+
+        match *instr {
+            Instr::Copy { dest, ref src } => self.check_fits(dest, src, place),
+            Instr::Trunc { dest, .. } => {
+                if self.function.vreg_types.get(dest) != Some(&IrType::I8) {
+                    self.error(format!("{}: trunc must give an i8", place));
+                }
+            }
+            Instr::BinOp { dest, op, .. } => {
+                match self.function.vreg_types.get(dest) {
+                    Some(&IrType::I8) => {
+                        self.error(format!("{}: {} can't give an i8", place, op));
+                    }
+                    Some(&IrType::Ptr) if op != IrBinOp::Add &&
+                        op != IrBinOp::Sub => {
+                        self.error(format!("{}: {} can't give a pointer",
+                                           place, op));
+                    }
+                    _ => {}
+                }
+            }
+            Instr::Load { dest, typ, .. } => {
+                if self.function.vreg_types.get(dest) != Some(&typ) {
+                    self.error(format!("{}: load {} into %{}, which has a \
+                                        different type", place, typ, dest));
+                }
+            }
+            Instr::Store { .. } => {}
+            Instr::FrameAddr { dest, slot } => {
+                if slot >= self.function.slots.len() {
+                    self.error(format!("{}: slot ${} doesn't exist", place, slot));
+                }
+                if self.function.vreg_types.get(dest) != Some(&IrType::Ptr) {
+                    self.error(format!("{}: frame_addr must give a pointer",
+                                       place));
+                }
+            }
+            Instr::Call { ref dests, ref name, ref args, .. } => {
+                if dests.len() > 2 {
+                    self.error(format!("{}: a call returns at most 2 words",
+                                       place));
+                }
+                if let Some(&(arg_words, return_words)) = self.signatures.get(name) {
+                    if args.len() != arg_words {
+                        self.error(format!("{}: {} takes {} words, but is \
+                                            given {}", place, name, arg_words,
+                                           args.len()));
+                    }
+                    if dests.len() > return_words {
+                        self.error(format!("{}: {} only returns {} words",
+                                           place, name, return_words));
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_terminator(&mut self, terminator: &Terminator, place: &str) {
+        for value in terminator.get_uses() {
+            self.check_use(value, place);
+        }
+        for block in terminator.get_successors() {
+            self.check_block_id(block, place);
+        }
+
+        match *terminator {
+            Terminator::Return(ref values)
+                if values.len() != self.function.return_words => {
+                self.error(format!("{}: returns {} words instead of {}",
+                                   place, values.len(),
+                                   self.function.return_words));
+            }
+            // The arguments go where ours are, and the result is returned
+            // as ours
+            Terminator::TailCall { ref name, ref args, .. } => {
+                if args.len() > self.function.params.len() {
+                    self.error(format!("{}: tail call with {} words, but only \
+                                        {} are passed to us", place, args.len(),
+                                       self.function.params.len()));
+                }
+                match self.signatures.get(name) {
+                    Some(&(arg_words, return_words)) => {
+                        if args.len() != arg_words {
+                            self.error(format!("{}: {} takes {} words, but is \
+                                                given {}", place, name,
+                                               arg_words, args.len()));
+                        }
+                        if return_words != self.function.return_words {
+                            self.error(format!("{}: {} returns {} words instead \
+                                                of {}", place, name, return_words,
+                                               self.function.return_words));
+                        }
+                    }
+                    None => {
+                        self.error(format!("{}: tail call to {}, which isn't \
+                                            in the program", place, name));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn verify(&mut self) {
+        if self.function.blocks.is_empty() {
+            self.error("There are no blocks".to_string());
+            return;
+        }
+
+        for &param in self.function.params.iter() {
+            self.check_reg(param, "parameters");
+            self.defined.insert(param);
+        }
+        for block in self.function.blocks.iter() {
+            for instr in block.instrs.iter() {
+                self.defined.extend(instr.get_dests());
+            }
+        }
+
+        for (i, block) in self.function.blocks.iter().enumerate() {
+            if block.lines.len() != block.instrs.len() {
+                self.error(format!("bb{}: {} instructions, but {} lines", i,
+                                   block.instrs.len(), block.lines.len()));
+            }
+            for (j, instr) in block.instrs.iter().enumerate() {
+                self.check_instr(instr, &format!("bb{} instruction {}", i, j));
+            }
+            self.check_terminator(&block.terminator, &format!("bb{} end", i));
+        }
+    }
+}
+
+/// Return everything wrong with the program
+pub fn verify_program(program: &IrProgram) -> Vec<String> {
+    let mut signatures = HashMap::new();
+    for function in program.functions.iter() {
+        if signatures.insert(function.name.clone(),
+                             (function.params.len(),
+                              function.return_words)).is_some() {
+            return vec![format!("{} is defined twice", function.name)];
+        }
+    }
+
+    let mut errors = Vec::new();
+    for function in program.functions.iter() {
+        let mut verifier = Verifier {
+            function,
+            signatures: &signatures,
+            defined: HashSet::new(),
+            errors: Vec::new(),
+        };
+        verifier.verify();
+        errors.append(&mut verifier.errors);
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ir::BasicBlock;
+
+    // Takes one word and returns it
+    fn identity() -> IrFunction {
+        let mut fun = IrFunction::new("identity", 1);
+        fun.params = vec![0];
+        fun.vreg_types = vec![IrType::I32];
+        fun.blocks = vec![BasicBlock {
+            instrs: vec![],
+            lines: vec![],
+            terminator: Terminator::Return(vec![Value::Reg(0)]),
+        }];
+        fun
+    }
+
+    // A function with the instructions and terminator, and registers of
+    // the given types, the first of which is its only parameter
+    fn function_with(vreg_types: Vec<IrType>, instrs: Vec<Instr>,
+                     terminator: Terminator) -> IrFunction {
+        let mut fun = IrFunction::new("f", 1);
+        fun.params = vec![0];
+        fun.vreg_types = vreg_types;
+        let lines = vec![1; instrs.len()];
+        fun.blocks = vec![BasicBlock { instrs, lines, terminator }];
+        fun
+    }
+
+    fn verify(fun: IrFunction) -> Vec<String> {
+        verify_program(&IrProgram {
+            functions: vec![identity(), fun],
+            strings: vec![],
+        })
+    }
+
+    fn return_reg(reg: usize) -> Terminator {
+        Terminator::Return(vec![Value::Reg(reg)])
+    }
+
+    #[test]
+    fn well_formed_program() {
+        let call = Instr::Call { dests: vec![1], name: "identity".to_string(),
+                                 args: vec![Value::Reg(0)], line: 3 };
+        let fun = function_with(vec![IrType::I32, IrType::I32], vec![call],
+                                return_reg(1));
+        assert_eq!(verify(fun), Vec::<String>::new());
+    }
+
+    #[test]
+    fn register_never_written() {
+        let fun = function_with(vec![IrType::I32, IrType::I32], vec![],
+                                return_reg(1));
+        assert_eq!(verify(fun), vec!["In f: bb0 end: %1 is used but never written"]);
+    }
+
+    #[test]
+    fn register_without_type() {
+        let copy = Instr::Copy { dest: 1, src: Value::Reg(0) };
+        let fun = function_with(vec![IrType::I32], vec![copy], return_reg(1));
+        assert_eq!(verify(fun), vec!["In f: bb0 instruction 0: %1 has no type",
+                                     "In f: bb0 end: %1 has no type"]);
+    }
+
+    #[test]
+    fn missing_block() {
+        let fun = function_with(vec![IrType::I32], vec![], Terminator::Jump(3));
+        assert_eq!(verify(fun), vec!["In f: bb0 end: bb3 doesn't exist"]);
+    }
+
+    #[test]
+    fn missing_lines() {
+        let copy = Instr::Copy { dest: 1, src: Value::Reg(0) };
+        let mut fun = function_with(vec![IrType::I32, IrType::I32], vec![copy],
+                                    return_reg(1));
+        fun.blocks[0].lines.clear();
+        assert_eq!(verify(fun), vec!["In f: bb0: 1 instructions, but 0 lines"]);
+    }
+
+    #[test]
+    fn no_blocks() {
+        let mut fun = IrFunction::new("f", 1);
+        fun.params = vec![0];
+        fun.vreg_types = vec![IrType::I32];
+        assert_eq!(verify(fun), vec!["In f: There are no blocks"]);
+    }
+
+    #[test]
+    fn wrong_number_of_returned_words() {
+        let fun = function_with(vec![IrType::I32], vec![],
+                                Terminator::Return(vec![Value::Reg(0),
+                                                        Value::Reg(0)]));
+        assert_eq!(verify(fun), vec!["In f: bb0 end: returns 2 words instead of 1"]);
+    }
+
+    #[test]
+    fn bad_types() {
+        let instrs = vec![
+            Instr::Copy { dest: 1, src: Value::Reg(0) },
+            Instr::Copy { dest: 1, src: Value::Const(300) },
+            Instr::Trunc { dest: 2, src: Value::Reg(0) },
+            Instr::BinOp { dest: 1, op: IrBinOp::Add, left: Value::Reg(0),
+                           right: Value::Const(1), overflow_check: None },
+            Instr::BinOp { dest: 3, op: IrBinOp::Mul, left: Value::Reg(0),
+                           right: Value::Const(4), overflow_check: None },
+            Instr::Load { dest: 2, typ: IrType::I8, addr: Value::Reg(3),
+                          offset: 0 },
+            Instr::FrameAddr { dest: 2, slot: 0 },
+        ];
+        let fun = function_with(vec![IrType::I32, IrType::I8, IrType::I32,
+                                     IrType::Ptr],
+                                instrs, return_reg(0));
+        assert_eq!(verify(fun), vec![
+            "In f: bb0 instruction 0: %0 may not fit in the i8 %1",
+            "In f: bb0 instruction 1: 300 may not fit in the i8 %1",
+            "In f: bb0 instruction 2: trunc must give an i8",
+            "In f: bb0 instruction 3: add can't give an i8",
+            "In f: bb0 instruction 4: mul can't give a pointer",
+            "In f: bb0 instruction 5: load i8 into %2, which has a different type",
+            "In f: bb0 instruction 6: slot $0 doesn't exist",
+            "In f: bb0 instruction 6: frame_addr must give a pointer",
+        ]);
+    }
+
+    #[test]
+    fn bad_calls() {
+        let instrs = vec![
+            Instr::Call { dests: vec![1], name: "identity".to_string(),
+                          args: vec![], line: 2 },
+            Instr::Call { dests: vec![1, 1], name: "identity".to_string(),
+                          args: vec![Value::Reg(0)], line: 3 },
+            Instr::Call { dests: vec![1, 1, 1], name: "printf".to_string(),
+                          args: vec![], line: 4 },
+        ];
+        let fun = function_with(vec![IrType::I32, IrType::I32], instrs,
+                                return_reg(1));
+        assert_eq!(verify(fun), vec![
+            "In f: bb0 instruction 0: identity takes 1 words, but is given 0",
+            "In f: bb0 instruction 1: identity only returns 1 words",
+            "In f: bb0 instruction 2: a call returns at most 2 words",
+        ]);
+    }
+
+    #[test]
+    fn bad_tail_calls() {
+        let too_many = Terminator::TailCall {
+            name: "identity".to_string(),
+            args: vec![Value::Reg(0), Value::Reg(0)],
+            line: 2,
+        };
+        assert_eq!(verify(function_with(vec![IrType::I32], vec![], too_many)), vec![
+            "In f: bb0 end: tail call with 2 words, but only 1 are passed to us",
+            "In f: bb0 end: identity takes 1 words, but is given 2",
+        ]);
+
+        let unknown = Terminator::TailCall { name: "g".to_string(),
+                                             args: vec![Value::Reg(0)],
+                                             line: 2 };
+        assert_eq!(verify(function_with(vec![IrType::I32], vec![], unknown)),
+                   vec!["In f: bb0 end: tail call to g, which isn't in the program"]);
+    }
+
+    #[test]
+    fn function_defined_twice() {
+        assert_eq!(verify(identity()), vec!["identity is defined twice"]);
+    }
+}
@@ -0,0 +1,449 @@
+use ast::AstExpressionNode;
+use ast::BinaryOp;
+use ast::Block;
+use ast::Expression;
+use ast::FunctionCall;
+use ast::PointerType;
+use ast::Program;
+use ast::Statement;
+use ast::VarType;
+
+use code_block::CodeBlock;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// Optional lint that looks for memory from allocate() which is never freed.
+//
+// Every call to allocate() whose result is put in a raw pointer variable is
+// an allocation that the variable (and any copies of it) hold. The memory
+// stops being our problem when it's freed, returned, passed to another
+// function or stored somewhere other than a local variable, like a struct
+// field. If the last variable holding it is overwritten or goes out of
+// scope first, or we return without freeing it, then it's leaked.
+//
+// The state of each path through an if or a loop is tracked separately, so
+// we warn if the memory leaks on any path.
+
+#[derive(Clone, Default, PartialEq, Debug)]
+struct LeakState {
+    // Variable -> the allocations it may hold
+    held: HashMap<String, HashSet<usize>>,
+
+    // Allocations which may not have been freed or handed off yet
+    live: HashSet<usize>,
+}
+
+// The state after either a or b was executed
+fn merge_states(a: &LeakState, b: &LeakState) -> LeakState {
+    let mut res = a.clone();
+    for (name, allocations) in b.held.iter() {
+        res.held.entry(name.clone())
+            .or_default()
+            .extend(allocations.iter().cloned());
+    }
+    res.live.extend(b.live.iter().cloned());
+    res
+}
+
+fn is_raw_pointer(expr_node: &AstExpressionNode) -> bool {
+    matches!(expr_node.typ, Some(VarType::Pointer(PointerType::Raw, _)))
+}
+
+fn is_allocation(expr_node: &AstExpressionNode) -> bool {
+    matches!(expr_node.expr,
+             Expression::Call(ref fn_call) if fn_call.name == "allocate")
+}
+
+// The pointer variable that an address is computed from, like p in p or
+// p + 1
+fn get_pointer_variable(expr_node: &AstExpressionNode) -> Option<&String> {
+    match expr_node.expr {
+        Expression::Variable(ref name) if is_raw_pointer(expr_node) => Some(name),
+        Expression::BinaryOp(BinaryOp::Plus, ref l, ref r) |
+        Expression::BinaryOp(BinaryOp::Minus, ref l, ref r)
+            if is_raw_pointer(expr_node) => {
+            get_pointer_variable(l).or_else(|| get_pointer_variable(r))
+        }
+        _ => None,
+    }
+}
+
+pub struct LeakChecker {
+    state: LeakState,
+    blocks: Vec<CodeBlock>,
+    current_fn: String,
+
+    // Allocations are numbered in the order they appear in the function,
+    // so going through a loop again gives them the same numbers
+    next_allocation: usize,
+    allocation_to_variable: HashMap<usize, String>,
+
+    // Turned off while we look for the fixed point of a loop, so that we
+    // don't report the same leak once per iteration
+    report: bool,
+    reported: HashSet<usize>,
+
+    warnings_found: Vec<String>,
+}
+
+impl LeakChecker {
+    pub fn new() -> LeakChecker {
+        LeakChecker {
+            state: LeakState::default(),
+            blocks: Vec::new(),
+            current_fn: String::new(),
+            next_allocation: 0,
+            allocation_to_variable: HashMap::new(),
+            report: true,
+            reported: HashSet::new(),
+            warnings_found: Vec::new(),
+        }
+    }
+
+    fn report_leak(&mut self, allocation: usize, how: &str) {
+        self.state.live.remove(&allocation);
+        if !self.report || !self.reported.insert(allocation) {
+            return;
+        }
+
+        let name = &self.allocation_to_variable[&allocation];
+        self.warnings_found.push(format!(
+            "In {}: memory allocated for {} {}. Consider making {} an \
+             owned_pointer", self.current_fn, name, how, name));
+    }
+
+    // Leaks all the live allocations which nothing holds anymore
+    fn report_unheld(&mut self, how: &str) {
+        let mut unheld: Vec<usize> = self.state.live.iter()
+            .filter(|a| !self.state.held.values().any(|h| h.contains(a)))
+            .cloned()
+            .collect();
+        unheld.sort();
+        for allocation in unheld {
+            self.report_leak(allocation, how);
+        }
+    }
+
+    // The memory that name holds has been freed or stored elsewhere
+    fn release(&mut self, name: &str) {
+        if let Some(allocations) = self.state.held.get(name) {
+            for allocation in allocations.iter() {
+                self.state.live.remove(allocation);
+            }
+        }
+    }
+
+    // Release the memory held by every pointer variable in the expression
+    fn release_expr(&mut self, expr_node: &AstExpressionNode) {
+        if let Some(name) = get_pointer_variable(expr_node) {
+            self.release(name);
+        }
+    }
+
+    // name no longer holds what it used to
+    fn forget(&mut self, name: &str, how: &str) {
+        if self.state.held.remove(name).is_some() {
+            self.report_unheld(how);
+        }
+    }
+
+    fn assign_pointer(&mut self, name: &str,
+                      value_opt: Option<&AstExpressionNode>) {
+        let value = match value_opt {
+            Some(value) if is_allocation(value) => {
+                let allocation = self.next_allocation;
+                self.next_allocation += 1;
+                self.allocation_to_variable.insert(allocation, name.to_string());
+                self.state.live.insert(allocation);
+                Some(allocation).into_iter().collect()
+            }
+            // name now holds a copy of whatever source holds
+            Some(value) => {
+                get_pointer_variable(value)
+                    .and_then(|source| self.state.held.get(source))
+                    .cloned()
+                    .unwrap_or_default()
+            }
+            None => HashSet::new(),
+        };
+
+        let old_value = self.state.held.remove(name);
+        if !value.is_empty() {
+            self.state.held.insert(name.to_string(), value);
+        }
+        if old_value.is_some() {
+            self.report_unheld(&format!("is lost when {} is overwritten",
+                                        name));
+        }
+    }
+
+    fn check_call(&mut self, fn_call: &FunctionCall) {
+        for arg in fn_call.args_exprs.iter() {
+            self.check_expr(arg);
+        }
+
+        // Passing a pointer to another function hands off the memory, since
+        // we can't tell what it does with it. free() is the same thing.
+        for arg in fn_call.args_exprs.iter() {
+            self.release_expr(arg);
+        }
+    }
+
+    fn check_expr(&mut self, expr_node: &AstExpressionNode) {
+        match expr_node.expr {
+            Expression::Call(ref fn_call) => self.check_call(fn_call),
+            Expression::BinaryOp(_, ref l, ref r) => {
+                self.check_expr(l);
+                self.check_expr(r);
+            }
+            Expression::Reference(ref expr) |
+            Expression::Dereference(ref expr) |
+            Expression::FieldAccess(ref expr, _) => self.check_expr(expr),
+            Expression::Index(ref array, ref index) => {
+                self.check_expr(array);
+                self.check_expr(index);
+            }
+            Expression::Slice(ref array, ref start, ref end) => {
+                self.check_expr(array);
+                self.check_expr(start);
+                self.check_expr(end);
+            }
+            Expression::Value(_) | Expression::SizeOf(_) |
+            Expression::Variable(_) | Expression::StringValue(_) => {}
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &Statement) {
+        match *stmt {
+            Statement::Return(ref expr) => {
+                self.check_expr(expr);
+                self.release_expr(expr);
+
+                let mut live: Vec<usize> = self.state.live.iter()
+                    .cloned()
+                    .collect();
+                live.sort();
+                for allocation in live {
+                    self.report_leak(allocation, "is not freed before returning");
+                }
+
+                // Nothing is held after this, so this path doesn't change
+                // the state after an if
+                self.state.held.clear();
+            }
+            Statement::Print(ref expr) => self.check_expr(expr),
+            Statement::Call(ref fn_call) => {
+                self.check_call(fn_call);
+                if fn_call.name == "allocate" && self.report {
+                    self.warnings_found.push(format!(
+                        "In {}: result of allocate is never used",
+                        self.current_fn));
+                }
+            }
+            Statement::Let(ref name, _, ref value_opt) => {
+                self.blocks.last_mut()
+                    .expect("No current block!")
+                    .declared_variables.insert(name.clone());
+                if let Some(ref value) = *value_opt {
+                    self.check_expr(value);
+                }
+                self.assign_pointer(name, value_opt.as_ref());
+            }
+            Statement::Assign(ref left, ref right) => {
+                self.check_expr(left);
+                self.check_expr(right);
+                match left.expr {
+                    Expression::Variable(ref name) => {
+                        self.assign_pointer(name, Some(right));
+                    }
+                    // Stored in memory somewhere, like a struct field
+                    _ => self.release_expr(right),
+                }
+            }
+            Statement::If(ref condition, ref then_block, ref else_block_opt) => {
+                self.check_expr(condition);
+                let before = self.state.clone();
+
+                self.check_block(then_block);
+                let after_then = std::mem::replace(&mut self.state, before);
+
+                if let Some(ref else_block) = *else_block_opt {
+                    self.check_block(else_block);
+                }
+
+                self.state = merge_states(&after_then, &self.state);
+            }
+            Statement::Unsafe(_, ref block) => self.check_block(block),
+            Statement::While(ref condition, ref block) => {
+                // Find what may be live at the start of an iteration
+                let report = self.report;
+                let first_allocation = self.next_allocation;
+                self.report = false;
+                let mut loop_start = self.state.clone();
+                loop {
+                    self.state = loop_start.clone();
+                    self.next_allocation = first_allocation;
+                    self.check_expr(condition);
+                    self.check_block(block);
+
+                    let next_start = merge_states(&loop_start, &self.state);
+                    if next_start == loop_start {
+                        break;
+                    }
+                    loop_start = next_start;
+                }
+
+                // Now go through it once more to report leaks
+                self.report = report;
+                self.state = loop_start.clone();
+                self.next_allocation = first_allocation;
+                self.check_expr(condition);
+                self.check_block(block);
+                self.state = loop_start;
+            }
+        }
+    }
+
+    fn check_block(&mut self, block: &Block) {
+        self.blocks.push(CodeBlock::new());
+        for stmt in block.statements.iter() {
+            self.check_stmt(stmt);
+
+            // Nothing after a return can run
+            if let Statement::Return(_) = *stmt {
+                break;
+            }
+        }
+
+        let b = self.blocks.pop().expect("No current block!");
+        let mut variables: Vec<String> = b.declared_variables.into_iter()
+            .collect();
+        variables.sort();
+        for variable in variables {
+            let how = format!("is lost when {} goes out of scope", variable);
+            self.forget(&variable, &how);
+        }
+    }
+
+    pub fn check_program(&mut self, program: &Program) {
+        for fun in program.functions.iter() {
+            self.current_fn = fun.name.clone();
+            self.state = LeakState::default();
+            self.next_allocation = 0;
+            self.allocation_to_variable.clear();
+            self.reported.clear();
+            self.check_block(&fun.statements);
+        }
+    }
+
+    pub fn get_warnings(&self) -> &Vec<String> {
+        &self.warnings_found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parser::parse;
+    use scanner::get_tokens;
+    use type_checker::TypeChecker;
+
+    // The warnings for the program, which has to type check
+    fn get_leaks(source: &str) -> Vec<String> {
+        let mut prog = parse(&mut get_tokens(source));
+        assert!(TypeChecker::new().annotate_types(&mut prog));
+        let mut leak_checker = LeakChecker::new();
+        leak_checker.check_program(&prog);
+        leak_checker.get_warnings().clone()
+    }
+
+    #[test]
+    fn freed_and_returned_memory_is_not_leaked() {
+        let source = "int* make (int n)
+{
+    int* p = allocate(4);
+    *p = n;
+    return p;
+}
+
+int main (int arg)
+{
+    int* p = make(3);
+    int* q = p;
+    print *q;
+    free(q);
+}
+";
+        assert_eq!(get_leaks(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn overwritten_pointer() {
+        let source = "int main (int arg)
+{
+    int* p = allocate(4);
+    p = allocate(8);
+    free(p);
+}
+";
+        assert_eq!(get_leaks(source), vec![
+            "In main: memory allocated for p is lost when p is overwritten. \
+             Consider making p an owned_pointer"]);
+    }
+
+    #[test]
+    fn pointer_goes_out_of_scope() {
+        let source = "int main (int arg)
+{
+    if (arg > 1) {
+        int* p = allocate(4);
+        *p = arg;
+    }
+}
+";
+        assert_eq!(get_leaks(source), vec![
+            "In main: memory allocated for p is lost when p goes out of scope. \
+             Consider making p an owned_pointer"]);
+    }
+
+    #[test]
+    fn leaked_on_one_path() {
+        let source = "int main (int arg)
+{
+    int* p = allocate(4);
+    if (arg > 1) {
+        free(p);
+    }
+}
+";
+        assert_eq!(get_leaks(source), vec![
+            "In main: memory allocated for p is lost when p goes out of scope. \
+             Consider making p an owned_pointer"]);
+    }
+
+    #[test]
+    fn leaked_in_a_loop_once() {
+        let source = "int main (int arg)
+{
+    int i = 0;
+    while (i < arg) {
+        int* p = allocate(4);
+        i = i + 1;
+    }
+}
+";
+        assert_eq!(get_leaks(source).len(), 1);
+    }
+
+    #[test]
+    fn unused_allocation() {
+        let source = "int main (int arg)
+{
+    allocate(4);
+}
+";
+        assert_eq!(get_leaks(source), vec!["In main: result of allocate is never used"]);
+    }
+}
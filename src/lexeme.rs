@@ -17,6 +17,11 @@ pub enum VarType {
     Int,
     Char,
     OwnedPointer,
+    SharedPointer,
+    Ref,
+    MutRef,
+    OwnedArray,
+    ArrayView,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -35,6 +40,8 @@ pub enum Lexeme {
     Return,
     Struct,
     Print,
+    Unsafe,
+    Inline,
     LParen,
     RParen,
     EndOfStatement, // ;
@@ -42,4 +49,7 @@ pub enum Lexeme {
     EndBlock,
     Comma,
     Dot,
+    DotDot, // ..
+    LBracket,
+    RBracket,
 }
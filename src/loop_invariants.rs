@@ -0,0 +1,249 @@
+use cfg::compute_dominator_tree;
+use cfg::dominates;
+
+use dataflow::solve;
+use dataflow::IrLiveness;
+
+use ir::BasicBlock;
+use ir::BlockId;
+use ir::Instr;
+use ir::IrBinOp;
+use ir::IrFunction;
+use ir::IrProgram;
+use ir::Terminator;
+use ir::VReg;
+use ir::Value;
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+
+// Moves instructions which compute the same thing on every iteration of a
+// loop to right before it (done at -O1 and above). Loops are found in the
+// IR from its back edges: a jump to a block which dominates the one doing
+// the jump, like the one from the end of a while's body to its condition.
+//
+// Virtual registers can be assigned more than once, so an instruction is
+// only moved if it's the only one in the loop writing its register, and
+// no read of the register in the loop or after it can see an older value.
+// The body of a loop may not run at all, so only instructions that can't
+// fail are moved, and loads only if they'd run before the loop can be left
+// and nothing in the loop stores to memory or calls a function.
+
+// The blocks of each loop, by the block they start at
+fn find_loops(fun: &IrFunction) -> BTreeMap<BlockId, BTreeSet<BlockId>> {
+    let graph = fun.get_flow_graph();
+    let dominator_tree = compute_dominator_tree(&graph);
+    let reachable = graph.get_reachable();
+
+    let mut loops: BTreeMap<BlockId, BTreeSet<BlockId>> = BTreeMap::new();
+    for (end, block) in fun.blocks.iter().enumerate() {
+        if !reachable[end] {
+            continue;
+        }
+        for header in block.terminator.get_successors() {
+            if !dominates(&dominator_tree, header, end) {
+                continue;
+            }
+
+            // Everything that gets to the back edge without going through
+            // the header again
+            let blocks = loops.entry(header).or_default();
+            blocks.insert(header);
+            let mut stack = vec![end];
+            while let Some(b) = stack.pop() {
+                if blocks.insert(b) {
+                    stack.extend(graph.predecessors[b].iter().cloned());
+                }
+            }
+        }
+    }
+    loops
+}
+
+// Whether the instruction can run even when it wouldn't have, without
+// failing or changing anything but its register
+fn is_safe_to_speculate(instr: &Instr) -> bool {
+    match *instr {
+        Instr::Copy { .. } | Instr::Trunc { .. } | Instr::FrameAddr { .. } => true,
+        Instr::BinOp { op, overflow_check, .. } => {
+            op != IrBinOp::Div && overflow_check.is_none()
+        }
+        Instr::Load { .. } | Instr::Store { .. } | Instr::Call { .. } => false,
+    }
+}
+
+fn retarget(terminator: &mut Terminator, from: BlockId, to: BlockId) {
+    match *terminator {
+        Terminator::Jump(ref mut b) => {
+            if *b == from {
+                *b = to;
+            }
+        }
+        Terminator::Branch { ref mut then_block, ref mut else_block, .. } => {
+            if *then_block == from {
+                *then_block = to;
+            }
+            if *else_block == from {
+                *else_block = to;
+            }
+        }
+        Terminator::Return(_) | Terminator::TailCall { .. } |
+        Terminator::Trap(_, _) | Terminator::Unreachable => {}
+    }
+}
+
+// Put the instructions in a block that runs right before the loop, which
+// is the block jumping to it if there's only one, and a new one otherwise
+fn add_preheader(fun: &mut IrFunction, header: BlockId, entering: &[BlockId],
+                 instrs: Vec<Instr>, lines: Vec<usize>) {
+    if let [before] = *entering {
+        if fun.blocks[before].terminator == Terminator::Jump(header) {
+            fun.blocks[before].instrs.extend(instrs);
+            fun.blocks[before].lines.extend(lines);
+            return;
+        }
+    }
+
+    let preheader = fun.blocks.len();
+    fun.blocks.push(BasicBlock {
+        instrs,
+        lines,
+        terminator: Terminator::Jump(header),
+    });
+    for &b in entering.iter() {
+        retarget(&mut fun.blocks[b].terminator, header, preheader);
+    }
+}
+
+// Move what's invariant out of the loop. Returns how many instructions
+// were moved.
+fn hoist_invariants(fun: &mut IrFunction, header: BlockId,
+                    blocks: &BTreeSet<BlockId>) -> usize {
+    // The entry can't have anything put before it
+    if header == 0 {
+        return 0;
+    }
+
+    let graph = fun.get_flow_graph();
+    let dominator_tree = compute_dominator_tree(&graph);
+    let liveness = solve(&IrLiveness::new(fun), &graph);
+
+    // The edges leaving the loop, including to the exit node for blocks
+    // leaving the function
+    let exits: Vec<(BlockId, BlockId)> = blocks.iter()
+        .flat_map(|&b| {
+            graph.successors[b].iter()
+                .filter(|next| !blocks.contains(next))
+                .map(move |&next| (b, next))
+        })
+        .collect();
+    let entering: Vec<BlockId> = graph.predecessors[header].iter()
+        .filter(|b| !blocks.contains(b))
+        .cloned()
+        .collect();
+
+    let changes_memory = blocks.iter().any(|&b| {
+        fun.blocks[b].instrs.iter().any(|instr| {
+            matches!(*instr, Instr::Store { .. } | Instr::Call { .. })
+        })
+    });
+
+    // How many instructions left in the loop write each register
+    let mut num_defs: HashMap<VReg, usize> = HashMap::new();
+    for &b in blocks.iter() {
+        for instr in fun.blocks[b].instrs.iter() {
+            for dest in instr.get_dests() {
+                *num_defs.entry(dest).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let can_hoist = |instr: &Instr, b: BlockId, num_defs: &HashMap<VReg, usize>| {
+        let runs_every_iteration = exits.iter()
+            .all(|&(exiting, _)| dominates(&dominator_tree, b, exiting));
+        let is_safe = match *instr {
+            Instr::Load { .. } => !changes_memory && runs_every_iteration,
+            _ => is_safe_to_speculate(instr),
+        };
+        let dest = match instr.get_dests().as_slice() {
+            [dest] => *dest,
+            _ => return false,
+        };
+        let is_invariant = instr.get_uses().iter().all(|value| match **value {
+            Value::Reg(reg) => num_defs.get(&reg).cloned().unwrap_or(0) == 0,
+            Value::Const(_) | Value::Global(_) => true,
+        });
+        // No read of the register can see a value from before the loop, or
+        // leave the loop without the instruction having run
+        let only_value = num_defs[&dest] == 1 &&
+            !liveness.fact_in[header].contains(&dest) &&
+            exits.iter().all(|&(exiting, next)| {
+                !liveness.fact_in[next].contains(&dest) ||
+                    dominates(&dominator_tree, b, exiting)
+            });
+        is_safe && is_invariant && only_value
+    };
+
+    // Moving an instruction can make the ones reading it invariant too.
+    // They're moved in the order they're found, so each comes after the
+    // ones it reads.
+    let mut hoisted = Vec::new();
+    let mut hoisted_lines = Vec::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &b in blocks.iter() {
+            let mut i = 0;
+            while i < fun.blocks[b].instrs.len() {
+                if !can_hoist(&fun.blocks[b].instrs[i], b, &num_defs) {
+                    i += 1;
+                    continue;
+                }
+                let instr = fun.blocks[b].instrs.remove(i);
+                hoisted_lines.push(fun.blocks[b].lines.remove(i));
+                for dest in instr.get_dests() {
+                    *num_defs.get_mut(&dest).unwrap() -= 1;
+                }
+                hoisted.push(instr);
+                changed = true;
+            }
+        }
+    }
+
+    let num_hoisted = hoisted.len();
+    if num_hoisted > 0 {
+        add_preheader(fun, header, &entering, hoisted, hoisted_lines);
+    }
+    num_hoisted
+}
+
+/// Move the instructions of loops that compute the same value on every
+/// iteration out of them. Returns how many were moved in each function.
+pub fn hoist_loop_invariants(program: &mut IrProgram) -> Vec<String> {
+    let mut report = Vec::new();
+    for fun in program.functions.iter_mut() {
+        // Inner loops first, so what's moved out of them can then be moved
+        // out of the outer ones. Loops are found again each time, as the
+        // block added before an inner loop is part of the outer one.
+        let mut done = BTreeSet::new();
+        let mut num_hoisted = 0;
+        loop {
+            let loops = find_loops(fun);
+            let next = loops.into_iter()
+                .filter(|&(header, _)| !done.contains(&header))
+                .min_by_key(|(_, blocks)| blocks.len());
+            let (header, blocks) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            done.insert(header);
+            num_hoisted += hoist_invariants(fun, header, &blocks);
+        }
+        if num_hoisted > 0 {
+            report.push(format!("instructions moved out of loops in {}: {}",
+                                fun.name, num_hoisted));
+        }
+    }
+    report
+}
@@ -3,17 +3,35 @@ mod assembly_helper;
 mod assembly_printer;
 mod ast;
 mod ast_helper;
+mod cfg;
 mod code_block;
+mod common_subexpressions;
 mod code_generator;
+mod constant_folder;
+mod dataflow;
+mod dead_code;
+mod format_string;
+mod free_checker;
+mod inliner;
+mod ir;
+mod ir_lowering;
+mod ir_verifier;
+mod leak_checker;
+mod loop_invariants;
 mod lexeme;
+mod owned_pointer_transformer;
 mod parser;
+mod peephole;
+mod register_allocator;
 mod pointer_arithmetic_transformer;
 mod representation_manager;
 mod scanner;
 mod struct_analyzer;
+mod tail_calls;
 mod token_stream;
 mod type_checker;
 mod type_checker_helper;
+mod unsafe_report;
 mod x86_code_generator;
 
 
@@ -23,7 +41,18 @@ use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 
+use cfg::cfg_to_dot;
+use common_subexpressions::eliminate_common_subexpressions;
+use constant_folder::fold_constants;
+use dead_code::eliminate_dead_code;
+use inliner::inline_functions;
+use ir_lowering::IrLowering;
+use ir_verifier::verify_program;
+use loop_invariants::hoist_loop_invariants;
+use owned_pointer_transformer::OwnedPointerTransformer;
 use pointer_arithmetic_transformer::transform_pointer_arithmetic;
+use tail_calls::get_tail_call_sites;
+use tail_calls::optimize_tail_calls;
 
 use code_generator::GeneratesCode;
 
@@ -56,12 +85,31 @@ fn write_code(complete_code: &String, path: &Path) {
 
 /// Starter of the compiler
 fn main() {
-    let filename_res = env::args().nth(1);
-    if filename_res.is_none() {
-        println!("You can run with cargo run <filename>.sc");
+    // Options like --warn-leaks can come before or after the file name
+    let (flags, names): (Vec<String>, Vec<String>) = env::args().skip(1)
+        .partition(|arg| arg.starts_with('-'));
+    if names.is_empty() {
+        println!("You can run with cargo run <filename>.sc [--warn-leaks] \
+                  [--report-unsafe] [--overflow-checks] \
+                  [--sanitize=memory] [--dump-cfg] [--dump-ir] [-O0|-O1] \
+                  [--require-tail-call] [--verbose]");
         return;
     }
-    let filename = &filename_res.unwrap();
+    let filename = &names[0];
+    let warn_leaks = flags.iter().any(|flag| flag == "--warn-leaks");
+    let report_unsafe = flags.iter().any(|flag| flag == "--report-unsafe");
+    let overflow_checks = flags.iter().any(|flag| flag == "--overflow-checks");
+    let sanitize_memory = flags.iter().any(|flag| flag == "--sanitize=memory");
+    let dump_cfg = flags.iter().any(|flag| flag == "--dump-cfg");
+    let dump_ir = flags.iter().any(|flag| flag == "--dump-ir");
+    let require_tail_call = flags.iter().any(|flag| flag == "--require-tail-call");
+    let verbose = flags.iter().any(|flag| flag == "--verbose");
+    // The last -O<level> given wins
+    let optimization_level = flags.iter()
+        .filter_map(|flag| flag.strip_prefix("-O"))
+        .next_back()
+        .map_or(0, |level| level.parse::<u32>()
+                .unwrap_or_else(|_| panic!("Bad optimization level -O{}", level)));
 
     let result = read_file(filename);
     if let Err(_) = result {
@@ -75,9 +123,18 @@ fn main() {
     // Parsing
     let mut prog = parser::parse(&mut tokens);
 
+    if report_unsafe {
+        for region in unsafe_report::get_unsafe_regions(&prog) {
+            println!("UNSAFE: {}", region);
+        }
+    }
+
     // Type checking
     let mut type_checker = type_checker::TypeChecker::new();
     let passed = type_checker.annotate_types(&mut prog);
+    for warning in type_checker.get_warnings() {
+        println!("WARNING: {}", warning);
+    }
     if !passed {
         println!("FAILED typechecker");
 
@@ -87,6 +144,51 @@ fn main() {
         return;
     }
 
+    // Look for raw pointers used after being freed
+    let mut free_checker = free_checker::FreeChecker::new();
+    let passed = free_checker.check_program(&prog);
+    for warning in free_checker.get_warnings() {
+        println!("WARNING: {}", warning);
+    }
+    if !passed {
+        println!("FAILED free_checker");
+
+        for err in free_checker.get_errors() {
+            println!("{}", err);
+        }
+        return;
+    }
+
+    // Look for allocations that are never freed
+    if warn_leaks {
+        let mut leak_checker = leak_checker::LeakChecker::new();
+        leak_checker.check_program(&prog);
+        for warning in leak_checker.get_warnings() {
+            println!("WARNING: {}", warning);
+        }
+    }
+
+    // The calls in tail position, before the code that frees
+    // owned_pointers is added after some of them
+    let tail_call_sites = get_tail_call_sites(&prog);
+
+    // Check that owned_pointers aren't used after being moved, and
+    // insert the calls which free them
+    let mut owned_pointer_transformer = OwnedPointerTransformer::new(
+        type_checker.get_function_types().clone());
+    if !owned_pointer_transformer.transform_program(&mut prog) {
+        println!("FAILED ownership");
+
+        for err in owned_pointer_transformer.get_errors() {
+            println!("{}", err);
+        }
+        return;
+    }
+
+    // Annotate the types of the statements we just added
+    type_checker.set_allow_internal_calls(true);
+    assert!(type_checker.annotate_types(&mut prog));
+
     // Change all cases of pointer + i to something like
     // pointer + sizeof(type) * i
     transform_pointer_arithmetic(&mut prog);
@@ -95,9 +197,84 @@ fn main() {
     // that doesn't typecheck.
     assert!(type_checker.annotate_types(&mut prog));
 
+    if optimization_level >= 1 {
+        fold_constants(&mut prog, overflow_checks);
+        assert!(type_checker.annotate_types(&mut prog));
+    }
+
+    if dump_cfg {
+        for function in prog.functions.iter() {
+            print!("{}", cfg_to_dot(function));
+        }
+    }
+
+    // Lowering to the IR
+    let mut lowering = IrLowering::new();
+    lowering.set_overflow_checks(overflow_checks);
+    lowering.set_sanitize_memory(sanitize_memory);
+    let mut ir_program = lowering.lower_program(&prog);
+
+    if optimization_level >= 1 {
+        for message in inline_functions(&mut ir_program) {
+            if verbose {
+                println!("INLINE: {}", message);
+            }
+        }
+    }
+
+    // After inlining, which can't inline a function with tail calls
+    if optimization_level >= 1 || require_tail_call {
+        let (done, failed) = optimize_tail_calls(&mut ir_program, &tail_call_sites);
+        if verbose {
+            for message in done.iter().chain(failed.iter()) {
+                println!("TAIL CALL: {}", message);
+            }
+        }
+        if require_tail_call && !failed.is_empty() {
+            println!("FAILED tail_call");
+
+            for err in failed.iter() {
+                println!("{}", err);
+            }
+            return;
+        }
+    }
+
+    if optimization_level >= 1 {
+        // What's moved before a loop may be computed there already
+        for message in hoist_loop_invariants(&mut ir_program) {
+            if verbose {
+                println!("LOOP INVARIANT: {}", message);
+            }
+        }
+        for message in eliminate_common_subexpressions(&mut ir_program) {
+            if verbose {
+                println!("COMMON SUBEXPRESSION: {}", message);
+            }
+        }
+        for message in eliminate_dead_code(&mut ir_program) {
+            if verbose {
+                println!("DEAD CODE: {}", message);
+            }
+        }
+    }
+
+    if dump_ir {
+        print!("{}", ir_program);
+    }
+
+    let ir_errors = verify_program(&ir_program);
+    if !ir_errors.is_empty() {
+        for err in ir_errors.iter() {
+            println!("{}", err);
+        }
+        panic!("The IR is invalid");
+    }
+
     // Generating code
     let mut code_generator = x86_code_generator::X86CodeGenerator::new();
-    let codestr = code_generator.generate_code(&prog);
+    code_generator.set_optimize(optimization_level >= 1);
+    let codestr = code_generator.generate_code(&ir_program);
 
     // Write the code to a file
     let path = Path::new("out/code.s");
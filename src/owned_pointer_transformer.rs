@@ -1,34 +1,58 @@
 use ast;
+use ast::ArrayType;
 use ast::AstExpressionNode;
 use ast::Block;
 use ast::PointerType;
 use ast::Expression;
 use ast::Function;
 use ast::FunctionCall;
+use ast::FunctionType;
 use ast::Program;
 use ast::Statement;
+use ast::StructDefinition;
 use ast::VarType;
 
+use ast_helper::statement_always_returns;
+
 use code_block::CodeBlock;
 
 use std::collections::VecDeque;
 use std::collections::HashMap;
+use std::collections::HashSet;
 
 // This function checks that the "owned_pointers"
 // are "moved."
 // We also insert calls to free() here to destroy the owned_pointers
 // who go out of scope/before "return"
-// This must be done after type checking
+// This must be done after type checking, and the type checker has to be
+// run again afterwards to annotate the statements we insert.
+//
+// A moved-from owned_pointer is set to 0, so the pointer itself acts as
+// its drop flag: when we can't tell statically whether it was moved
+// (it was moved in only one branch of an if) we free it only if it's
+// non-zero.
+//
+// Structs which have owned_pointers inside them get a generated
+// __drop_<struct name> function, which frees those owned_pointers. It's
+// called whenever such a struct goes out of scope or the owned_pointer
+// holding it is freed.
+//
+// A mut_ref borrowed from an owned_pointer (mut_ref(T) r = &*p) keeps p
+// from being used until r goes out of scope.
+//
+// owned_arrays are moved and freed just like owned_pointers. An
+// array_view taken from one (array_view(T) v = a[lo..hi]) borrows it the
+// same way a mut_ref does.
+//
+// shared_pointers are never moved. Every copy of one increments its
+// reference count (__shared_retain), and every shared_pointer going out of
+// scope decrements it (__shared_release). The one that brings the count to
+// 0 destroys and frees the value.
 
 // Problems with this so far:
 // Need to disallow stuff like *&*&my_owned_pointer
-// Need way of destroying structs who have owned_pointers inside them
-// Right now we assume we cannot return owned_pointers
-//
-// TODO: Rerun the typechecker again after this runs,
-// that way we won't have to add in the types which feels hackish
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 enum OwnedPointerStatus {
     // It stores a pointer to something, and we want to free
     // it at the end
@@ -36,156 +60,1228 @@ enum OwnedPointerStatus {
 
     // This pointer has been moved, so it can never be used again
     Moved,
+
+    // This pointer was moved on some paths but not others. It can't be
+    // used, and is only freed if it's not 0.
+    MaybeMoved,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ResourceKind {
+    OwnedPointer,
+
+    // Always Alive, since copying it doesn't move it
+    SharedPointer,
+
+    // Struct variables with owned_pointers inside are tracked as well so
+    // we can destroy them. They are always Alive, since structs can't be
+    // moved.
+    StructValue,
+}
+
+// A ref, mut_ref or array_view variable that borrows from an owner. The
+// owner can't be moved, assigned to or freed while the borrow is around, and
+// can't be used at all while a mutable borrow is.
+struct Borrow {
+    owner: String,
+    is_mutable: bool,
 }
 
 struct OwnedPointerInfo {
     status: OwnedPointerStatus,
     target_type: VarType,
+    kind: ResourceKind,
 }
 
-struct OwnedPointerTransformer {
+pub struct OwnedPointerTransformer {
     blocks: Vec<CodeBlock>,
     pointer_to_info: HashMap<String, OwnedPointerInfo>,
+    errors_found: Vec<String>,
+
+    // Used to find out which calls hand us an owned_pointer
+    function_to_type: HashMap<String, FunctionType>,
+
+    struct_to_definition: HashMap<String, StructDefinition>,
+    // Structs which need a __drop function
+    droppable_structs: HashSet<String>,
+
+    // While checking `a.f = <expr>`, this is a.f. Moving an owned_pointer
+    // out of a struct is only allowed when a replacement is put back in
+    // the same statement.
+    replacement_target: Option<AstExpressionNode>,
+    replacement_moved: bool,
+
+    // Borrowing variable -> what it borrows from
+    borrow_to_owner: HashMap<String, Borrow>,
+
+    // shared_pointers copied by the current statement. Each one gets
+    // retained before the statement runs.
+    shared_copies: Vec<AstExpressionNode>,
+
+    // Used to give temporaries unique names
+    num_temporaries: i32,
 }
 
-fn get_free_call(varname: &str, typ: &VarType) -> Statement {
-    let mut arg = AstExpressionNode::new(
-        Expression::Variable(varname.to_string()));
-    // FIXME: This feels weird, setting the type manually
-    // Instead we should try to re-run the type checker after
-    // this transformation, and it'll do that for us.
-    arg.typ = Some(typ.clone());
+fn variable_expr(name: &str) -> AstExpressionNode {
+    AstExpressionNode::new(Expression::Variable(name.to_string()))
+}
 
+// &*p, a raw pointer to what the owned_pointer p points to
+fn get_raw_pointer(ptr: AstExpressionNode) -> AstExpressionNode {
+    let deref = AstExpressionNode::new(Expression::Dereference(Box::new(ptr)));
+    AstExpressionNode::new(Expression::Reference(Box::new(deref)))
+}
+
+fn get_free_call(ptr: AstExpressionNode) -> Statement {
     let fn_call = FunctionCall {name: "free".to_string(),
-                                args_exprs: vec![arg]
+                                args_exprs: vec![ptr]
     };
     Statement::Call(fn_call)
 }
 
+// if p { <statements> }
+fn get_if_not_null(ptr: AstExpressionNode,
+                   statements: Vec<Statement>) -> Statement {
+    let then_block = Block { statements: statements.into_iter().collect() };
+    Statement::If(ptr, then_block, None)
+}
+
+pub fn get_drop_function_name(struct_name: &str) -> String {
+    format!("__drop_{}", struct_name)
+}
+
+// Return true if both expressions refer to the same place in memory,
+// like a.b.c and a.b.c
+fn is_same_lvalue(a: &AstExpressionNode, b: &AstExpressionNode) -> bool {
+    match (&a.expr, &b.expr) {
+        (Expression::Variable(x), Expression::Variable(y)) => x == y,
+        (Expression::FieldAccess(x, x_field),
+         Expression::FieldAccess(y, y_field)) => {
+            x_field == y_field && is_same_lvalue(x, y)
+        }
+        (Expression::Dereference(x), Expression::Dereference(y)) => {
+            is_same_lvalue(x, y)
+        }
+        _ => false,
+    }
+}
+
+// Describe an lvalue the way it was written, for error messages
+fn lvalue_to_string(expr_node: &AstExpressionNode) -> String {
+    match expr_node.expr {
+        Expression::Variable(ref name) => name.clone(),
+        Expression::FieldAccess(ref expr, ref field) => {
+            format!("{}.{}", lvalue_to_string(expr), field)
+        }
+        Expression::Dereference(ref expr) => {
+            format!("(*{})", lvalue_to_string(expr))
+        }
+        _ => format!("{:?}", expr_node.expr),
+    }
+}
+
+fn is_owned_field_access(expr_node: &AstExpressionNode) -> bool {
+    if let Expression::FieldAccess(_, _) = expr_node.expr {
+        expr_node.typ.as_ref().is_some_and(is_owned_pointer)
+    } else {
+        false
+    }
+}
+
+fn is_shared_field_access(expr_node: &AstExpressionNode) -> bool {
+    if let Expression::FieldAccess(_, _) = expr_node.expr {
+        expr_node.typ.as_ref().is_some_and(is_shared_pointer)
+    } else {
+        false
+    }
+}
+
+// True if the expression reads a shared_pointer stored somewhere, so using
+// its value makes a new copy
+fn is_shared_lvalue(expr_node: &AstExpressionNode) -> bool {
+    match expr_node.expr {
+        Expression::Variable(_) | Expression::FieldAccess(_, _) |
+        Expression::Dereference(_) => {
+            expr_node.typ.as_ref().is_some_and(is_shared_pointer)
+        }
+        _ => false,
+    }
+}
+
+fn get_shared_call(name: &str, ptr: AstExpressionNode) -> FunctionCall {
+    FunctionCall {name: name.to_string(), args_exprs: vec![ptr]}
+}
+
+// A shared_pointer gets its memory from allocate() like any other pointer,
+// but it needs room for the reference count too
+fn get_shared_alloc(typ: &VarType,
+                    expr_node: AstExpressionNode) -> AstExpressionNode {
+    match expr_node.expr {
+        Expression::Call(ref fn_call)
+            if fn_call.name == "allocate" && is_shared_pointer(typ) => {
+            let call = FunctionCall {
+                name: "__shared_alloc".to_string(),
+                args_exprs: fn_call.args_exprs.clone(),
+            };
+            AstExpressionNode::new(Expression::Call(call))
+        }
+        _ => expr_node,
+    }
+}
+
+// p = 0;
+fn get_null_assignment(varname: &str) -> Statement {
+    Statement::Assign(
+        AstExpressionNode::new(Expression::Variable(varname.to_string())),
+        AstExpressionNode::new(Expression::Value(0)))
+}
+
+fn get_struct_name(typ: &VarType) -> String {
+    if let VarType::Struct(ref name) = *typ {
+        name.clone()
+    } else {
+        panic!("Not a struct!");
+    }
+}
+
+// What a pointer points to, or what an array holds
+fn pointed_type(typ: &VarType) -> VarType {
+    match *typ {
+        VarType::Pointer(_, ref to_typ) |
+        VarType::Array(_, ref to_typ) => *to_typ.clone(),
+        _ => panic!("Not a pointer!"),
+    }
+}
+
+fn is_owned_pointer(typ: &VarType) -> bool {
+    matches!(*typ, VarType::Pointer(PointerType::Owned, _))
+}
+
+fn is_owned_array(typ: &VarType) -> bool {
+    matches!(*typ, VarType::Array(ArrayType::Owned, _))
+}
+
+// new_array(T, n) isn't in the function table, since its type depends on T
+fn get_new_array_type(fn_call: &FunctionCall) -> Option<VarType> {
+    if fn_call.name != "new_array" {
+        return None;
+    }
+    match fn_call.args_exprs[0].expr {
+        Expression::SizeOf(ref typ) => {
+            Some(VarType::Array(ArrayType::Owned, Box::new(typ.clone())))
+        }
+        _ => panic!("new_array should be given a type"),
+    }
+}
+
+fn is_shared_pointer(typ: &VarType) -> bool {
+    matches!(*typ, VarType::Pointer(PointerType::Shared, _))
+}
+
+// For error messages
+fn pointer_kind_name(typ: &VarType) -> &'static str {
+    if is_shared_pointer(typ) {
+        "shared_pointer"
+    } else if is_owned_array(typ) {
+        "owned_array"
+    } else {
+        "owned_pointer"
+    }
+}
+
+fn merge_status(a: OwnedPointerStatus,
+                b: OwnedPointerStatus) -> OwnedPointerStatus {
+    if a == b {
+        a
+    } else {
+        OwnedPointerStatus::MaybeMoved
+    }
+}
+
+// Return true if the variable is used anywhere in the expression
+fn expression_uses_variable(expr_node: &AstExpressionNode, name: &str) -> bool {
+    match expr_node.expr {
+        Expression::Variable(ref var) => var == name,
+        Expression::BinaryOp(_, ref l, ref r) => {
+            expression_uses_variable(l, name) ||
+                expression_uses_variable(r, name)
+        }
+        Expression::Call(ref fn_call) => {
+            fn_call.args_exprs.iter()
+                .any(|arg| expression_uses_variable(arg, name))
+        }
+        Expression::Reference(ref expr) |
+        Expression::Dereference(ref expr) |
+        Expression::FieldAccess(ref expr, _) => {
+            expression_uses_variable(expr, name)
+        }
+        Expression::Index(ref array, ref index) => {
+            expression_uses_variable(array, name) ||
+                expression_uses_variable(index, name)
+        }
+        Expression::Slice(ref array, ref start, ref end) => {
+            expression_uses_variable(array, name) ||
+                expression_uses_variable(start, name) ||
+                expression_uses_variable(end, name)
+        }
+        Expression::Value(_) | Expression::SizeOf(_) |
+        Expression::StringValue(_) => false,
+    }
+}
+
 impl OwnedPointerTransformer {
-    pub fn new() -> OwnedPointerTransformer {
+    pub fn new(function_to_type: HashMap<String, FunctionType>)
+               -> OwnedPointerTransformer {
         OwnedPointerTransformer {
             blocks: Vec::new(),
             pointer_to_info: HashMap::new(),
+            errors_found: Vec::new(),
+            function_to_type,
+            struct_to_definition: HashMap::new(),
+            droppable_structs: HashSet::new(),
+            replacement_target: None,
+            replacement_moved: false,
+            borrow_to_owner: HashMap::new(),
+            shared_copies: Vec::new(),
+            num_temporaries: 0,
+        }
+    }
+
+    // Figure out which structs have owned_pointers (or shared_pointers)
+    // inside them, either directly or in a struct field
+    fn find_droppable_structs(&mut self, structs: &[StructDefinition]) {
+        for defn in structs {
+            self.struct_to_definition.insert(defn.name.clone(), defn.clone());
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for defn in structs {
+                if self.droppable_structs.contains(&defn.name) {
+                    continue;
+                }
+
+                let needs_drop = defn.fields.values().any(|typ| {
+                    match *typ {
+                        VarType::Pointer(PointerType::Owned, _) |
+                        VarType::Pointer(PointerType::Shared, _) => true,
+                        VarType::Struct(ref name) => {
+                            self.droppable_structs.contains(name)
+                        }
+                        _ => false,
+                    }
+                });
+                if needs_drop {
+                    self.droppable_structs.insert(defn.name.clone());
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    fn get_sorted_fields(&self, struct_name: &str) -> Vec<(String, VarType)> {
+        let defn = self.struct_to_definition.get(struct_name)
+            .expect("Unkown struct");
+        let mut fields: Vec<(String, VarType)> = defn.fields.iter()
+            .map(|(name, typ)| (name.clone(), typ.clone()))
+            .collect();
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        fields
+    }
+
+    // __drop_S(addr), if values of this type need to be destroyed
+    fn get_drop_call(&self, addr: AstExpressionNode,
+                     typ: &VarType) -> Option<Statement> {
+        match *typ {
+            VarType::Struct(ref name)
+                if self.droppable_structs.contains(name) => {
+                let fn_call = FunctionCall {
+                    name: get_drop_function_name(name),
+                    args_exprs: vec![addr],
+                };
+                Some(Statement::Call(fn_call))
+            }
+            _ => None,
+        }
+    }
+
+    // Statements which destroy what an owned_pointer points to and then
+    // free it
+    fn get_free_statements(&self, ptr: AstExpressionNode,
+                           target_type: &VarType) -> Vec<Statement> {
+        let mut res = Vec::new();
+        let addr = get_raw_pointer(ptr.clone());
+        if let Some(drop_call) = self.get_drop_call(addr, target_type) {
+            res.push(drop_call);
+        }
+        res.push(get_free_call(ptr));
+        res
+    }
+
+    // Statements which drop a reference to what a shared_pointer points to,
+    // destroying and freeing it if that was the last one
+    fn get_release_statements(&self, ptr: AstExpressionNode,
+                              target_type: &VarType) -> Vec<Statement> {
+        let release = AstExpressionNode::new(Expression::Call(
+            get_shared_call("__shared_release", ptr.clone())));
+        vec![get_if_not_null(release,
+                             self.get_free_statements(ptr, target_type))]
+    }
+
+    // Statements which get rid of the value of an owned_pointer or
+    // shared_pointer we own
+    fn get_pointer_destroy_statements(&self, ptr: AstExpressionNode,
+                                      typ: &VarType) -> Vec<Statement> {
+        if is_shared_pointer(typ) {
+            self.get_release_statements(ptr, &pointed_type(typ))
+        } else {
+            self.get_free_statements(ptr, &pointed_type(typ))
+        }
+    }
+
+    // A newly declared struct has garbage in it, so set all its
+    // owned_pointers and shared_pointers to 0 to make it safe to destroy
+    fn get_empty_owned_fields(&self, lvalue: &AstExpressionNode,
+                              struct_name: &str) -> Vec<Statement> {
+        let mut res = Vec::new();
+        for (field, typ) in self.get_sorted_fields(struct_name) {
+            let field_expr = AstExpressionNode::new(
+                Expression::FieldAccess(Box::new(lvalue.clone()), field));
+            match typ {
+                VarType::Pointer(PointerType::Owned, _) |
+                VarType::Pointer(PointerType::Shared, _) => {
+                    res.push(Statement::Assign(
+                        field_expr,
+                        AstExpressionNode::new(Expression::Value(0))));
+                }
+                VarType::Struct(ref name)
+                    if self.droppable_structs.contains(name) => {
+                    res.extend(self.get_empty_owned_fields(&field_expr, name));
+                }
+                _ => {}
+            }
+        }
+        res
+    }
+
+    // Generate
+    // int __drop_S(S* self) {
+    //     if self {
+    //         ...destroy and free every owned_pointer field, and release
+    //         every shared_pointer field...
+    //     }
+    //     return 0;
+    // }
+    fn generate_drop_function(&self, struct_name: &str) -> Function {
+        let arg_name = "self";
+        let arg_type = VarType::Pointer(
+            PointerType::Raw, Box::new(VarType::Struct(struct_name.to_string())));
+
+        let mut destroy_fields = Vec::new();
+        for (field, typ) in self.get_sorted_fields(struct_name) {
+            let field_expr = AstExpressionNode::new(Expression::FieldAccess(
+                Box::new(AstExpressionNode::new(
+                    Expression::Dereference(Box::new(variable_expr(arg_name))))),
+                field));
+            match typ {
+                VarType::Pointer(PointerType::Owned, ref to_typ) => {
+                    destroy_fields.extend(
+                        self.get_free_statements(field_expr, to_typ));
+                }
+                VarType::Pointer(PointerType::Shared, ref to_typ) => {
+                    destroy_fields.extend(
+                        self.get_release_statements(field_expr, to_typ));
+                }
+                VarType::Struct(_) => {
+                    let addr = AstExpressionNode::new(
+                        Expression::Reference(Box::new(field_expr)));
+                    destroy_fields.extend(self.get_drop_call(addr, &typ));
+                }
+                _ => {}
+            }
+        }
+
+        let mut statements = VecDeque::new();
+        statements.push_back(get_if_not_null(variable_expr(arg_name),
+                                             destroy_fields));
+        statements.push_back(Statement::Return(
+            AstExpressionNode::new(Expression::Value(0))));
+
+        Function {
+            name: get_drop_function_name(struct_name),
+            statements: Block { statements },
+            args: vec![arg_name.to_string()],
+            fn_type: FunctionType {
+                return_type: VarType::Int,
+                arg_types: vec![arg_type],
+                is_var_args: false,
+                is_unsafe: false,
+                is_internal: false,
+            },
+            unsafe_line: None,
+            is_inline: false,
         }
     }
 
+    // The type of owned_pointer (or shared_pointer, or owned_array) a
+    // function call gives us ownership of, if it returns one
+    fn get_owned_return_type(&self, fn_call: &FunctionCall) -> Option<VarType> {
+        if let Some(typ) = get_new_array_type(fn_call) {
+            return Some(typ);
+        }
+
+        self.function_to_type.get(&fn_call.name)
+            .map(|fn_type| fn_type.return_type.clone())
+            .filter(|typ| {
+                is_owned_pointer(typ) || is_shared_pointer(typ) ||
+                    is_owned_array(typ)
+            })
+    }
+
+    // Freeing an array only frees the memory holding its elements, so
+    // they can't have anything inside them that needs to be destroyed
+    fn check_new_array(&mut self, fn_call: &FunctionCall) {
+        let element_type = match get_new_array_type(fn_call) {
+            Some(typ) => pointed_type(&typ),
+            None => return,
+        };
+
+        let needs_destroying = match element_type {
+            VarType::Pointer(PointerType::Owned, _) |
+            VarType::Pointer(PointerType::Shared, _) => true,
+            VarType::Struct(ref name) => self.droppable_structs.contains(name),
+            _ => false,
+        };
+        if needs_destroying {
+            self.errors_found.push(format!(
+                "Cannot make an array of {:?}, since its elements would \
+                 have to be destroyed", element_type));
+        }
+    }
+
+    fn get_temporary_name(&mut self) -> String {
+        let name = format!("__owned_tmp{}", self.num_temporaries);
+        self.num_temporaries += 1;
+        name
+    }
+
+    // Start tracking a newly declared owned_pointer, shared_pointer or
+    // owned_array
+    fn add_owned_pointer(&mut self, name: &str, typ: &VarType) {
+        let kind = match *typ {
+            VarType::Pointer(PointerType::Owned, _) |
+            VarType::Array(ArrayType::Owned, _) => ResourceKind::OwnedPointer,
+            VarType::Pointer(PointerType::Shared, _) => ResourceKind::SharedPointer,
+            _ => return,
+        };
+
+        // Keep track of which block this pointer was declared in
+        self.blocks.last_mut()
+            .expect("No current block!")
+            .declared_variables.insert(name.to_string());
+
+        let info = OwnedPointerInfo {
+            status: OwnedPointerStatus::Alive,
+            target_type: pointed_type(typ),
+            kind,
+        };
+        self.pointer_to_info.insert(name.to_string(), info);
+    }
+
+    // Start tracking a struct variable, if it has owned_pointers inside
+    fn add_struct_value(&mut self, name: &str, typ: &VarType) -> bool {
+        match *typ {
+            VarType::Struct(ref struct_name)
+                if self.droppable_structs.contains(struct_name) => {}
+            _ => return false,
+        }
+
+        self.blocks.last_mut()
+            .expect("No current block!")
+            .declared_variables.insert(name.to_string());
+        let info = OwnedPointerInfo {
+            status: OwnedPointerStatus::Alive,
+            target_type: typ.clone(),
+            kind: ResourceKind::StructValue,
+        };
+        self.pointer_to_info.insert(name.to_string(), info);
+        true
+    }
+
+    fn get_status_snapshot(&self) -> HashMap<String, OwnedPointerStatus> {
+        self.pointer_to_info.iter()
+            .map(|(name, info)| (name.clone(), info.status))
+            .collect()
+    }
+
+    fn restore_status_snapshot(&mut self,
+                               snapshot: &HashMap<String, OwnedPointerStatus>) {
+        for (name, status) in snapshot.iter() {
+            if let Some(info) = self.pointer_to_info.get_mut(name) {
+                info.status = *status;
+            }
+        }
+    }
+
+    // The owned_pointer whose memory &<lvalue> points into, if any
+    fn get_lvalue_owner(&self, expr_node: &AstExpressionNode) -> Option<String> {
+        match expr_node.expr {
+            Expression::Dereference(ref expr) => {
+                if let Expression::Variable(ref name) = expr.expr {
+                    if self.pointer_to_info.contains_key(name) {
+                        return Some(name.clone());
+                    }
+                }
+                self.get_lvalue_owner(expr)
+            }
+            Expression::FieldAccess(ref expr, _) => self.get_lvalue_owner(expr),
+            _ => None,
+        }
+    }
+
+    // Remember that a ref or mut_ref variable borrows from an
+    // owned_pointer, or that an array_view variable borrows from an
+    // owned_array
+    fn add_borrow(&mut self, name: &str, typ: &VarType,
+                  value: &AstExpressionNode) {
+        let owner = match (typ, &value.expr) {
+            (VarType::Pointer(PointerType::Ref, _),
+             Expression::Reference(lvalue)) |
+            (VarType::Pointer(PointerType::MutRef, _),
+             Expression::Reference(lvalue)) => self.get_lvalue_owner(lvalue),
+            // A copy of a ref borrows from the same owned_pointer
+            (VarType::Pointer(PointerType::Ref, _),
+             Expression::Variable(ref_name)) => {
+                self.borrow_to_owner.get(ref_name)
+                    .map(|borrow| borrow.owner.clone())
+            }
+            (VarType::Array(ArrayType::View, _),
+             Expression::Slice(array, _, _)) => {
+                match array.expr {
+                    Expression::Variable(ref array_name)
+                        if self.pointer_to_info.contains_key(array_name) => {
+                        Some(array_name.clone())
+                    }
+                    // A view of a view borrows from the same array
+                    Expression::Variable(ref array_name) => {
+                        self.borrow_to_owner.get(array_name)
+                            .map(|borrow| borrow.owner.clone())
+                    }
+                    _ => None,
+                }
+            }
+            (VarType::Pointer(PointerType::Ref, _), _) |
+            (VarType::Pointer(PointerType::MutRef, _), _) |
+            (VarType::Array(ArrayType::View, _), _) => None,
+            _ => return,
+        };
+
+        self.borrow_to_owner.remove(name);
+        if let Some(owner) = owner {
+            let is_mutable = !matches!(typ, VarType::Pointer(PointerType::Ref, _));
+            self.borrow_to_owner.insert(name.to_string(),
+                                        Borrow { owner, is_mutable });
+        }
+    }
+
+    // The first variable (by name) that borrows from name
+    fn get_borrower(&self, name: &str, only_mutable: bool) -> Option<String> {
+        let mut borrowers: Vec<&String> = self.borrow_to_owner.iter()
+            .filter(|&(_, borrow)| {
+                borrow.owner == name && (borrow.is_mutable || !only_mutable)
+            })
+            .map(|(borrower, _)| borrower)
+            .collect();
+        borrowers.sort();
+        borrowers.first().map(|borrower| borrower.to_string())
+    }
+
+    // Moving, assigning to or freeing an owner would leave its borrows
+    // pointing at memory it no longer owns
+    fn check_not_borrowed(&mut self, name: &str, action: &str) {
+        if let Some(borrower) = self.get_borrower(name, false) {
+            self.errors_found.push(format!(
+                "Cannot {} {} while it is borrowed by {}",
+                action, name, borrower));
+        }
+    }
+
+    fn check_variable_use(&mut self, name: &str) {
+        if let Some(borrower) = self.get_borrower(name, true) {
+            let msg = format!("Cannot use {} while it is mutably borrowed \
+                               by {}", name, borrower);
+            self.errors_found.push(msg);
+        }
+        self.check_pointer_status(name);
+    }
+
+    fn check_pointer_status(&mut self, name: &str) {
+        let status = match self.pointer_to_info.get(name) {
+            Some(info) => info.status,
+            None => return,
+        };
+
+        match status {
+            OwnedPointerStatus::Alive => {}
+            OwnedPointerStatus::Moved => {
+                self.errors_found.push(format!(
+                    "Use of moved owned_pointer {}", name));
+            }
+            OwnedPointerStatus::MaybeMoved => {
+                self.errors_found.push(format!(
+                    "Use of owned_pointer {} which may have been moved",
+                    name));
+            }
+        }
+    }
+
+    // Check all the variables used by the expression. Owned pointers
+    // passed as arguments to a function are moved into it.
+    fn use_expr(&mut self, expr_node: &AstExpressionNode,
+                moved: &mut Vec<String>) {
+        match expr_node.expr {
+            Expression::Variable(ref name) => self.check_variable_use(name),
+            Expression::BinaryOp(_, ref l, ref r) => {
+                self.use_expr(l, moved);
+                self.use_expr(r, moved);
+            }
+            Expression::Call(ref fn_call) => {
+                self.check_new_array(fn_call);
+                self.move_call_args(fn_call, moved);
+
+                // Nothing would own the result, so it could never be freed
+                if let Some(typ) = self.get_owned_return_type(fn_call) {
+                    self.errors_found.push(format!(
+                        "The {} returned by {} must be stored in a variable",
+                        pointer_kind_name(&typ), fn_call.name));
+                }
+            }
+            Expression::Reference(ref expr) |
+            Expression::Dereference(ref expr) |
+            Expression::FieldAccess(ref expr, _) => {
+                self.use_expr(expr, moved);
+            }
+            Expression::Index(ref array, ref index) => {
+                self.use_expr(array, moved);
+                self.use_expr(index, moved);
+            }
+            Expression::Slice(ref array, ref start, ref end) => {
+                self.use_expr(array, moved);
+                self.use_expr(start, moved);
+                self.use_expr(end, moved);
+            }
+            Expression::Value(_) | Expression::SizeOf(_) |
+            Expression::StringValue(_) => {}
+        }
+    }
+
+    // Owned pointers passed to a function are moved into it. len(a) only
+    // looks at the array, though.
+    fn move_call_args(&mut self, fn_call: &FunctionCall,
+                      moved: &mut Vec<String>) {
+        for arg in fn_call.args_exprs.iter() {
+            match arg.expr {
+                _ if fn_call.name == "len" => self.use_expr(arg, moved),
+                Expression::Variable(ref name)
+                    if fn_call.name == "free" && self.is_owned(name) => {
+                    self.move_variable(name, "free", moved);
+                }
+                _ => self.move_expr(arg, moved),
+            }
+        }
+    }
+
+    fn is_owned(&self, name: &str) -> bool {
+        self.pointer_to_info.get(name).is_some_and(|info| {
+            info.kind == ResourceKind::OwnedPointer
+        })
+    }
+
+    fn move_variable(&mut self, name: &str, action: &str,
+                     moved: &mut Vec<String>) {
+        self.check_not_borrowed(name, action);
+        self.check_pointer_status(name);
+        self.pointer_to_info.get_mut(name).unwrap().status =
+            OwnedPointerStatus::Moved;
+        moved.push(name.to_string());
+    }
+
+    // Evaluate an expression whose value is being handed off somewhere
+    // else. If it's an owned_pointer variable, that variable gets moved.
+    // If it's a shared_pointer, it gets copied.
+    fn move_expr(&mut self, expr_node: &AstExpressionNode,
+                 moved: &mut Vec<String>) {
+        match expr_node.expr {
+            _ if is_shared_lvalue(expr_node) => {
+                self.use_expr(expr_node, moved);
+                self.shared_copies.push(expr_node.clone());
+            }
+            Expression::Variable(ref name) if self.is_owned(name) => {
+                self.move_variable(name, "move", moved);
+            }
+            Expression::Call(ref fn_call) => {
+                // Whoever we hand the result to becomes its owner
+                self.check_new_array(fn_call);
+                self.move_call_args(fn_call, moved);
+            }
+            Expression::FieldAccess(_, _) if is_owned_field_access(expr_node) => {
+                self.use_expr(expr_node, moved);
+
+                let is_replaced = self.replacement_target.as_ref()
+                    .is_some_and(|target| is_same_lvalue(target, expr_node));
+                if is_replaced {
+                    self.replacement_moved = true;
+                } else {
+                    self.errors_found.push(format!(
+                        "Cannot move owned_pointer {} out of a struct \
+                         unless a replacement is assigned to it in the \
+                         same statement", lvalue_to_string(expr_node)));
+                }
+            }
+            _ => self.use_expr(expr_node, moved),
+        }
+    }
+
+    // Statements which destroy an owned_pointer based on what we know
+    // about it
+    fn get_destroy_statements(&self, name: &str) -> Vec<Statement> {
+        let info = self.pointer_to_info.get(name)
+            .expect("var not in pointer_to_info");
+        match info.kind {
+            ResourceKind::StructValue => {
+                let addr = AstExpressionNode::new(
+                    Expression::Reference(Box::new(variable_expr(name))));
+                return self.get_drop_call(addr, &info.target_type)
+                    .into_iter()
+                    .collect();
+            }
+            ResourceKind::SharedPointer => {
+                return self.get_release_statements(variable_expr(name),
+                                                   &info.target_type);
+            }
+            ResourceKind::OwnedPointer => {}
+        }
+
+        match info.status {
+            OwnedPointerStatus::Alive => {
+                self.get_free_statements(variable_expr(name), &info.target_type)
+            }
+            OwnedPointerStatus::MaybeMoved => {
+                let free = self.get_free_statements(variable_expr(name),
+                                                    &info.target_type);
+                vec![get_if_not_null(variable_expr(name), free)]
+            }
+            OwnedPointerStatus::Moved => Vec::new(),
+        }
+    }
+
+    // Moved-from pointers are set to 0 so that they can act as their
+    // own drop flag
+    fn get_null_assignments(&self, moved: &[String]) -> Vec<Statement> {
+        moved.iter()
+            .filter(|name| {
+                self.pointer_to_info.get(*name).unwrap().status ==
+                    OwnedPointerStatus::Moved
+            })
+            .map(|name| get_null_assignment(name))
+            .collect()
+    }
+
+    fn transform_assignment(&mut self, left_expr: AstExpressionNode,
+                            right_expr: AstExpressionNode,
+                            moved: &mut Vec<String>) -> Vec<Statement> {
+        let right_expr = match left_expr.typ {
+            Some(ref typ) => get_shared_alloc(typ, right_expr),
+            None => right_expr,
+        };
+
+        if is_owned_field_access(&left_expr) || is_shared_field_access(&left_expr) {
+            return self.transform_field_assignment(left_expr, right_expr,
+                                                   moved);
+        }
+
+        self.move_expr(&right_expr, moved);
+
+        if let (Expression::Variable(name), Some(typ)) =
+            (&left_expr.expr, &left_expr.typ) {
+            self.add_borrow(name, typ, &right_expr);
+        }
+
+        let name = match left_expr.expr {
+            Expression::Variable(ref name)
+                if self.pointer_to_info.contains_key(name) => name.clone(),
+            _ => {
+                self.use_expr(&left_expr, moved);
+                return vec![Statement::Assign(left_expr, right_expr)];
+            }
+        };
+
+        // We're overwriting an owned_pointer, so whatever it held
+        // before has to be destroyed first
+        self.check_not_borrowed(&name, "assign to");
+        let mut res = Vec::new();
+        let destroy_old = self.get_destroy_statements(&name);
+        if !destroy_old.is_empty() &&
+            expression_uses_variable(&right_expr, &name) {
+            // The new value is computed from the old one, so compute
+            // it before destroying anything
+            let typ = left_expr.typ.clone()
+                .expect("Expressions should all have types now!");
+            let tmp_name = self.get_temporary_name();
+
+            res.push(Statement::Let(tmp_name.clone(), typ,
+                                    Some(right_expr)));
+            res.extend(destroy_old);
+            res.push(Statement::Assign(
+                left_expr,
+                AstExpressionNode::new(Expression::Variable(tmp_name))));
+        } else {
+            res.extend(destroy_old);
+            res.push(Statement::Assign(left_expr, right_expr));
+        }
+
+        self.pointer_to_info.get_mut(&name).unwrap().status =
+            OwnedPointerStatus::Alive;
+        res
+    }
+
+    // Assigning to an owned_pointer or shared_pointer inside a struct
+    fn transform_field_assignment(&mut self, left_expr: AstExpressionNode,
+                                  right_expr: AstExpressionNode,
+                                  moved: &mut Vec<String>) -> Vec<Statement> {
+        self.use_expr(&left_expr, moved);
+
+        self.replacement_target = Some(left_expr.clone());
+        self.replacement_moved = false;
+        self.move_expr(&right_expr, moved);
+        self.replacement_target = None;
+
+        // The old value was moved out by the right hand side, so it's
+        // not ours to destroy
+        if self.replacement_moved {
+            return vec![Statement::Assign(left_expr, right_expr)];
+        }
+
+        // Compute the new value, destroy the old one, and then store the
+        // new one
+        let typ = left_expr.typ.clone()
+            .expect("Expressions should all have types now!");
+        let tmp_name = self.get_temporary_name();
+        let mut res = vec![Statement::Let(tmp_name.clone(), typ.clone(),
+                                          Some(right_expr))];
+        res.extend(self.get_pointer_destroy_statements(left_expr.clone(),
+                                                       &typ));
+        res.push(Statement::Assign(left_expr, variable_expr(&tmp_name)));
+        res
+    }
+
     // Given a statement, return a list of statements to replace it with
     fn transform_stmt(&mut self,
                       stmt: Statement) -> Vec<Statement> {
-        
+        // Statements inside an if or while keep track of their own copies
+        let outer_copies = std::mem::take(&mut self.shared_copies);
+        let transformed = self.transform_stmt_moves(stmt);
+        let copies = std::mem::replace(&mut self.shared_copies, outer_copies);
+
+        // Each copy of a shared_pointer is another reference to its value
+        let mut res: Vec<Statement> = copies.into_iter()
+            .map(|ptr| Statement::Call(get_shared_call("__shared_retain", ptr)))
+            .collect();
+        res.extend(transformed);
+        res
+    }
+
+    fn transform_stmt_moves(&mut self,
+                            stmt: Statement) -> Vec<Statement> {
+        // Owned pointers moved by this statement
+        let mut moved = Vec::new();
+
         // FIXME: Do match *&mut stmt instead, to avoid having all the weird returns
-        match stmt {
+        let mut res = match stmt {
             Statement::Return(expr) => {
-                // TODO: Right now we assume no owned_pointer is returned
-                let mut res = Vec::new();
-
+                // Returning an owned_pointer moves it to the caller, so it
+                // won't be freed below
+                self.move_expr(&expr, &mut moved);
                 // We're about to return, so destroy all alive owned pointers
-                for (name, info) in self.pointer_to_info.iter() {
-                    if info.status == OwnedPointerStatus::Moved {
-                        continue;
-                    }
+                let mut names: Vec<String> = self.pointer_to_info.keys()
+                    .cloned()
+                    .collect();
+                names.sort();
+                let mut destroy = Vec::new();
+                for name in names.iter() {
+                    destroy.extend(self.get_destroy_statements(name));
+                }
 
-                    res.push(get_free_call(name, &info.target_type));
+                if destroy.is_empty() {
+                    return vec![Statement::Return(expr)];
                 }
 
-                res.push(Statement::Return(expr));
-                res
+                // The return value may be computed from what we're about to
+                // free, so compute it first
+                let typ = expr.typ.clone()
+                    .expect("Expressions should all have types now!");
+                let tmp_name = self.get_temporary_name();
+                let mut res = vec![Statement::Let(tmp_name.clone(), typ,
+                                                  Some(expr))];
+                res.extend(destroy);
+                res.push(Statement::Return(AstExpressionNode::new(
+                    Expression::Variable(tmp_name))));
+                return res;
             }
-            Statement::If(expr, then_block, else_block_opt) => {
-                vec![Statement::If(expr, then_block, else_block_opt)]
+            Statement::If(expr, mut then_block, else_block_opt) => {
+                self.use_expr(&expr, &mut moved);
+                let before = self.get_status_snapshot();
+
+                let then_returns = self.transform_block(&mut then_block);
+                let after_then = self.get_status_snapshot();
+                self.restore_status_snapshot(&before);
+
+                let mut else_returns = false;
+                let transformed_else_block =
+                if let Some(mut else_block) = else_block_opt {
+                    else_returns = self.transform_block(&mut else_block);
+                    Some(else_block)
+                } else {
+                    None
+                };
+                let after_else = self.get_status_snapshot();
+
+                // A branch that returns doesn't reach the code after the if,
+                // so whatever it did to the pointers doesn't matter
+                let merged = if then_returns {
+                    after_else
+                } else if else_returns {
+                    after_then
+                } else {
+                    after_then.iter()
+                        .map(|(name, status)| {
+                            (name.clone(),
+                             merge_status(*status, after_else[name]))
+                        })
+                        .collect()
+                };
+                self.restore_status_snapshot(&merged);
+
+                vec![Statement::If(expr, then_block, transformed_else_block)]
             }
-            Statement::While(expr, block) => {
+            Statement::Unsafe(line, mut block) => {
+                self.transform_block(&mut block);
+                vec![Statement::Unsafe(line, block)]
+            }
+            Statement::While(expr, mut block) => {
+                self.use_expr(&expr, &mut moved);
+                // The copies would have to be retained on every iteration
+                if !self.shared_copies.is_empty() {
+                    self.errors_found.push(format!(
+                        "Cannot copy shared_pointer {} in a loop condition",
+                        lvalue_to_string(&self.shared_copies[0])));
+                }
+                let before = self.get_status_snapshot();
+                let body_returns = self.transform_block(&mut block);
+                let after = self.get_status_snapshot();
+
+                if !body_returns {
+                    let mut names: Vec<&String> = before.keys().collect();
+                    names.sort();
+                    for name in names {
+                        if before[name] == OwnedPointerStatus::Alive &&
+                            after[name] != OwnedPointerStatus::Alive {
+                            self.errors_found.push(format!(
+                                "owned_pointer {} is moved inside a loop and \
+                                 could be used again on the next iteration",
+                                name));
+                        }
+                    }
+                }
+
+                // The loop may not run at all
+                let merged = before.iter()
+                    .map(|(name, status)| {
+                        (name.clone(), merge_status(*status, after[name]))
+                    })
+                    .collect();
+                self.restore_status_snapshot(&merged);
+
                 vec![Statement::While(expr, block)]
             }
             Statement::Let(name, typ, value_expr) => {
-
-                if let VarType::Pointer(PointerType::Owned,
-                                        ref to_typ) = *&typ {
-                    // Keep track of which block this owned_pointer was
-                    // declared in
+                let value_expr = value_expr.map(|expr| get_shared_alloc(&typ, expr));
+                // A borrow has to end with the block even if it's only
+                // given a value later
+                let is_borrow = matches!(typ, VarType::Pointer(PointerType::Ref, _) |
+                                              VarType::Pointer(PointerType::MutRef, _) |
+                                              VarType::Array(ArrayType::View, _));
+                if is_borrow {
                     self.blocks.last_mut()
                         .expect("No current block!")
                         .declared_variables.insert(name.clone());
+                }
+                if let Some(ref expr) = value_expr {
+                    self.move_expr(expr, &mut moved);
+
+                    if is_borrow {
+                        self.add_borrow(&name, &typ, expr);
+                    }
+                }
+
+                if is_owned_pointer(&typ) || is_shared_pointer(&typ) ||
+                    is_owned_array(&typ) {
+                    self.add_owned_pointer(&name, &typ);
 
-                    // TODO deal with case where we're moving another
-                    // shared pointer
-                    let info = OwnedPointerInfo {
-                        status: OwnedPointerStatus::Alive,
-                        target_type: *(to_typ.clone()),
-                    };
-                    self.pointer_to_info.insert(name.clone(), info);
+                    // An uninitialized pointer starts out as 0 so that it's
+                    // safe to free
+                    let value_expr = value_expr.or_else(|| {
+                        Some(AstExpressionNode::new(Expression::Value(0)))
+                    });
+                    vec![Statement::Let(name, typ.clone(), value_expr)]
+                } else if self.add_struct_value(&name, &typ) {
+                    let empty_fields = self.get_empty_owned_fields(
+                        &variable_expr(&name), &get_struct_name(&typ));
+                    let mut res = vec![Statement::Let(name, typ, value_expr)];
+                    res.extend(empty_fields);
+                    res
+                } else {
+                    vec![Statement::Let(name, typ, value_expr)]
                 }
-                vec![Statement::Let(name, typ, value_expr)]
             }
             Statement::Assign(left_expr, right_expr) => {
-                vec![Statement::Assign(left_expr, right_expr)]
+                self.transform_assignment(left_expr, right_expr, &mut moved)
             }
             Statement::Call(fn_call) => {
-                vec![Statement::Call(fn_call)]
+                self.check_new_array(&fn_call);
+                self.move_call_args(&fn_call, &mut moved);
+
+                if let Some(typ) = self.get_owned_return_type(&fn_call) {
+                    // The result is ignored, so we own it just long enough
+                    // to free it
+                    let tmp_name = self.get_temporary_name();
+                    let call = AstExpressionNode::new(Expression::Call(fn_call));
+                    let mut res = vec![Statement::Let(tmp_name.clone(),
+                                                      typ.clone(),
+                                                      Some(call))];
+                    res.extend(self.get_pointer_destroy_statements(
+                        variable_expr(&tmp_name), &typ));
+                    res
+                } else if fn_call.name == "free" {
+                    // Whatever an owned_pointer points to has to be
+                    // destroyed before its memory is freed
+                    let mut res = Vec::new();
+                    let arg = &fn_call.args_exprs[0];
+                    match arg.typ {
+                        Some(VarType::Pointer(PointerType::Owned, ref to_typ)) => {
+                            res.extend(self.get_drop_call(
+                                get_raw_pointer(arg.clone()), to_typ));
+                        }
+                        Some(VarType::Pointer(PointerType::Shared, _)) => {
+                            self.errors_found.push(format!(
+                                "Cannot free shared_pointer {}. It is freed \
+                                 when its last copy goes out of scope",
+                                lvalue_to_string(arg)));
+                        }
+                        _ => {}
+                    }
+                    res.push(Statement::Call(fn_call));
+                    res
+                } else {
+                    vec![Statement::Call(fn_call)]
+                }
             }
             Statement::Print(expr) => {
+                self.use_expr(&expr, &mut moved);
                 vec![Statement::Print(expr)]
             }
-        }
+        };
+
+        res.extend(self.get_null_assignments(&moved));
+        res
     }
 
     // Free all the pointers who were declared in the current block
     // Add the free instructions to the end of the block.
     fn free_pointers_in_cur_block(&mut self, block: &mut Block) {
-        // If the last statement is a return statement, we don't
-        // need to add free calls after it
-        if let Some(&Statement::Return(_)) = block.statements.back() {
-            return;
-        }
-
-        // Else free everything declared in that block
         let b = self.blocks.last().expect("No current block!");
-        for varname in b.declared_variables.iter() {
-            let var_info = self.pointer_to_info.get(varname)
-                .expect("var not in pointer_to_info");
-
-            let call = get_free_call(&varname, &var_info.target_type);
-            block.statements.push_back(call);
-                                               
+        let mut names: Vec<&String> = b.declared_variables.iter()
+            .filter(|name| self.pointer_to_info.contains_key(*name))
+            .collect();
+        names.sort();
+        for varname in names {
+            block.statements.extend(self.get_destroy_statements(varname));
         }
     }
 
-    fn transform_block(&mut self, block: &mut ast::Block) {
+    // Return true if the block always returns (and so never reaches its
+    // end)
+    fn transform_block(&mut self, block: &mut ast::Block) -> bool {
         // Clear some data structures here probably
         self.blocks.push(CodeBlock::new());
- 
+
         // Analyze each statement, and replace it with whatever the
         // transform function tells us to
         let mut new_statements = VecDeque::new();
+        let mut always_returns = false;
         while let Some(stmt) = block.statements.pop_front() {
+            always_returns = always_returns || statement_always_returns(&stmt);
             let replacement = self.transform_stmt(stmt);
             new_statements.extend(replacement);
         }
         block.statements = new_statements;
 
-        // If the last statement of the block is not a return, then we need
+        // If the block doesn't always return, then we need
         // to free all of the owned_pointers in that block
-        self.free_pointers_in_cur_block(block);
+        if !always_returns {
+            self.free_pointers_in_cur_block(block);
+        }
 
         let b = self.blocks.pop().expect("No current block!");
         for variable in b.declared_variables {
             self.pointer_to_info.remove(&variable);
+            self.borrow_to_owner.remove(&variable);
         }
+
+        always_returns
     }
 
     fn transform_function(&mut self, function: &mut Function) {
-        self.transform_block(&mut function.statements);
+        // Borrows never outlive the function they're in
+        self.borrow_to_owner.clear();
+
+        // The function owns any owned_pointers passed to it
+        self.blocks.push(CodeBlock::new());
+        for (arg, typ) in function.args.iter()
+            .zip(function.fn_type.arg_types.iter()) {
+            self.add_owned_pointer(arg, typ);
+        }
+
+        let always_returns = self.transform_block(&mut function.statements);
+        if !always_returns {
+            self.free_pointers_in_cur_block(&mut function.statements);
+        }
+
+        let b = self.blocks.pop().expect("No current block!");
+        for variable in b.declared_variables {
+            self.pointer_to_info.remove(&variable);
+            self.borrow_to_owner.remove(&variable);
+        }
     }
 
-    pub fn transform_program(&mut self, program: &mut Program) {
+    pub fn transform_program(&mut self, program: &mut Program) -> bool {
+        self.find_droppable_structs(&program.structs);
+
         for fun in program.functions.iter_mut() {
             self.transform_function(fun);
         }
+
+        let mut droppable: Vec<&String> = self.droppable_structs.iter().collect();
+        droppable.sort();
+        for struct_name in droppable {
+            program.functions.push(self.generate_drop_function(struct_name));
+        }
+
+        self.errors_found.is_empty()
     }
-}
 
-pub fn transform_program(program: &mut Program) {
-    let mut t = OwnedPointerTransformer::new();
-    t.transform_program(program);
+    pub fn get_errors(&self) -> &Vec<String> {
+        &self.errors_found
+    }
 }
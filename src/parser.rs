@@ -1,6 +1,7 @@
 use ast;
 use ast::Block;
 use ast::AstExpressionNode;
+use ast::ArrayType;
 use ast::PointerType;
 use ast::FunctionCall;
 use ast::StructDefinition;
@@ -28,7 +29,9 @@ fn lexeme_var_type_to_ast(t: lexeme::VarType) -> ast::VarType {
     match t {
         lexeme::VarType::Int => ast::VarType::Int,
         lexeme::VarType::Char => ast::VarType::Char,
-        lexeme::VarType::OwnedPointer => panic!("Use parse_type function!")
+        lexeme::VarType::OwnedPointer | lexeme::VarType::SharedPointer |
+        lexeme::VarType::Ref | lexeme::VarType::MutRef |
+        lexeme::VarType::OwnedArray | lexeme::VarType::ArrayView => panic!("Use parse_type function!")
     }
 }
 
@@ -71,6 +74,38 @@ fn get_precedence(op: &OperatorType) -> i32 {
     }
 }
 
+/// Give the expression, and everything in it that doesn't have a line yet,
+/// the line it's on
+fn set_line(expr_node: &mut AstExpressionNode, line: usize) {
+    if expr_node.line != 0 {
+        return;
+    }
+    expr_node.line = line;
+
+    match expr_node.expr {
+        Expression::BinaryOp(_, ref mut l, ref mut r) |
+        Expression::Index(ref mut l, ref mut r) => {
+            set_line(l, line);
+            set_line(r, line);
+        }
+        Expression::Slice(ref mut array, ref mut start, ref mut end) => {
+            set_line(array, line);
+            set_line(start, line);
+            set_line(end, line);
+        }
+        Expression::Reference(ref mut expr) |
+        Expression::Dereference(ref mut expr) |
+        Expression::FieldAccess(ref mut expr, _) => set_line(expr, line),
+        Expression::Call(ref mut fn_call) => {
+            for arg in fn_call.args_exprs.iter_mut() {
+                set_line(arg, line);
+            }
+        }
+        Expression::Value(_) | Expression::SizeOf(_) |
+        Expression::Variable(_) | Expression::StringValue(_) => {}
+    }
+}
+
 /// Check if the input is a identifier and return the 'name' (string) of the identifier
 /// ```
 /// self.expect_identifier(Lexeme::identifier("foo")) = "foo"
@@ -154,14 +189,22 @@ impl Parser {
                             tok)
             };
 
-        // Now parse all the field accesses. self is for cases like
-        // (*p).x.y.z
+        // Now parse all the field accesses and indexing. self is for cases
+        // like (*p).x.y.z or a.b[i]
         let mut next_tok = tokens.consume();
-        while let Lexeme::Dot = next_tok {
-            let field_name = expect_identifier(tokens.consume());
-            let object_factor = AstExpressionNode::new(factor);
-            factor = Expression::FieldAccess(Box::new(object_factor),
-                                             field_name);
+        loop {
+            match next_tok {
+                Lexeme::Dot => {
+                    let field_name = expect_identifier(tokens.consume());
+                    let object_factor = AstExpressionNode::new(factor);
+                    factor = Expression::FieldAccess(Box::new(object_factor),
+                                                     field_name);
+                }
+                Lexeme::LBracket => {
+                    factor = self.parse_index(tokens, factor);
+                }
+                _ => break,
+            }
 
             next_tok = tokens.consume();
         }
@@ -171,6 +214,23 @@ impl Parser {
         factor
     }
 
+    /// Parse the [i] or [lo..hi] after an array. The [ was already consumed.
+    fn parse_index(&mut self, tokens: &mut TokenStream,
+                   array: Expression) -> Expression {
+        let array = Box::new(AstExpressionNode::new(array));
+        let index = Box::new(self.parse_expression(tokens));
+
+        let tok = tokens.consume();
+        if tok == Lexeme::DotDot {
+            let end = Box::new(self.parse_expression(tokens));
+            assert_eq!(tokens.consume(), Lexeme::RBracket);
+            Expression::Slice(array, index, end)
+        } else {
+            assert_eq!(tok, Lexeme::RBracket);
+            Expression::Index(array, index)
+        }
+    }
+
     /// Executing the two stack algorithm
     fn two_stack_algo(&mut self, tokens: &mut TokenStream) -> Expression {
         let mut operator_stack = Vec::new();
@@ -284,8 +344,11 @@ impl Parser {
 
     /// Parse a expression using two-stack algorithm
     fn parse_expression(&mut self, tokens: &mut TokenStream) -> AstExpressionNode {
+        let line = tokens.line();
         let expr = self.two_stack_algo(tokens);
-        AstExpressionNode::new(expr)
+        let mut expr_node = AstExpressionNode::new(expr);
+        set_line(&mut expr_node, line);
+        expr_node
     }
 
     /// Parse the type
@@ -294,12 +357,23 @@ impl Parser {
 
         match tok {
             Lexeme::Type(lexeme::VarType::OwnedPointer) => {
-                assert_eq!(tokens.consume(), Lexeme::LParen);
-                let inner_type = self.parse_type(tokens);
-                assert_eq!(tokens.consume(), Lexeme::RParen);
                 // Note you can't have a pointer to an owned_pointer
-                ast::VarType::Pointer(PointerType::Owned,
-                                      Box::new(inner_type))
+                self.parse_pointer_type(tokens, PointerType::Owned)
+            }
+            Lexeme::Type(lexeme::VarType::SharedPointer) => {
+                self.parse_pointer_type(tokens, PointerType::Shared)
+            }
+            Lexeme::Type(lexeme::VarType::Ref) => {
+                self.parse_pointer_type(tokens, PointerType::Ref)
+            }
+            Lexeme::Type(lexeme::VarType::MutRef) => {
+                self.parse_pointer_type(tokens, PointerType::MutRef)
+            }
+            Lexeme::Type(lexeme::VarType::OwnedArray) => {
+                self.parse_array_type(tokens, ArrayType::Owned)
+            }
+            Lexeme::Type(lexeme::VarType::ArrayView) => {
+                self.parse_array_type(tokens, ArrayType::View)
             }
             Lexeme::Type(t) => {
                 let base_type = lexeme_var_type_to_ast(t);
@@ -312,6 +386,24 @@ impl Parser {
         }
     }
 
+    /// Parse the (T) part of a type like owned_pointer(T)
+    fn parse_pointer_type(&mut self, tokens: &mut TokenStream,
+                          pointer_type: PointerType) -> ast::VarType {
+        assert_eq!(tokens.consume(), Lexeme::LParen);
+        let inner_type = self.parse_type(tokens);
+        assert_eq!(tokens.consume(), Lexeme::RParen);
+        ast::VarType::Pointer(pointer_type, Box::new(inner_type))
+    }
+
+    /// Parse the (T) part of a type like owned_array(T)
+    fn parse_array_type(&mut self, tokens: &mut TokenStream,
+                        array_type: ArrayType) -> ast::VarType {
+        assert_eq!(tokens.consume(), Lexeme::LParen);
+        let element_type = self.parse_type(tokens);
+        assert_eq!(tokens.consume(), Lexeme::RParen);
+        ast::VarType::Array(array_type, Box::new(element_type))
+    }
+
     /// Parse pointer if needed
     fn parse_pointer(&mut self, tokens: &mut TokenStream, base_type: ast::VarType) -> ast::VarType {
         let mut res = base_type;
@@ -407,6 +499,16 @@ impl Parser {
         if let Lexeme::Identifier(fn_name) = tok {
             assert_eq!(tokens.consume(), Lexeme::LParen);
             let mut args_exprs = Vec::new();
+
+            // new_array(T, n) takes a type. We pass along its size, which
+            // also tells the type checker what T is.
+            if fn_name == "new_array" {
+                let element_type = self.parse_type(tokens);
+                args_exprs.push(AstExpressionNode::new(
+                    Expression::SizeOf(element_type)));
+                assert_eq!(tokens.consume(), Lexeme::Comma);
+            }
+
             loop {
                 let arg_expr = self.parse_expression(tokens);
                 args_exprs.push(arg_expr);
@@ -422,6 +524,19 @@ impl Parser {
 
     /// Parse a function definition
     fn parse_function(&mut self, tokens: &mut TokenStream) -> Function {
+        let is_inline = tokens.peek() == Lexeme::Inline;
+        if is_inline {
+            tokens.consume();
+        }
+
+        let unsafe_line = if tokens.peek() == Lexeme::Unsafe {
+            let line = tokens.line();
+            tokens.consume();
+            Some(line)
+        } else {
+            None
+        };
+
         let return_type = self.parse_type(tokens);
 
         let fn_name = expect_identifier(tokens.consume());
@@ -448,7 +563,11 @@ impl Parser {
                              arg_types: arg_types,
                              return_type: return_type,
                              is_var_args: false,
-                         }
+                             is_unsafe: unsafe_line.is_some(),
+                             is_internal: false,
+                         },
+                         unsafe_line,
+                         is_inline,
         }
     }
 
@@ -485,6 +604,11 @@ impl Parser {
             Lexeme::Print => self.parse_print(tokens),
             Lexeme::If => self.parse_if(tokens),
             Lexeme::While => self.parse_while(tokens),
+            Lexeme::Unsafe => {
+                let line = tokens.line();
+                tokens.consume();
+                Statement::Unsafe(line, self.parse_block(tokens))
+            }
             Lexeme::Type(_) => self.parse_declaration(tokens),
             Lexeme::Identifier(ref struct_name) if self.struct_table.contains(struct_name) =>
                 self.parse_declaration(tokens),
@@ -527,7 +651,8 @@ impl Parser {
         while !tokens.is_empty() {
             let t = tokens.peek();
             match t {
-                Lexeme::Type(_) | Lexeme::Identifier(_)=>
+                Lexeme::Type(_) | Lexeme::Identifier(_) | Lexeme::Unsafe |
+                Lexeme::Inline =>
                     functions.push(self.parse_function(tokens)),
                 Lexeme::Struct => structs.push(self.parse_struct(tokens)),
                 _ => panic!("Illegal token {:?}", t),
@@ -0,0 +1,409 @@
+use assembly::Instruction;
+use assembly::Instruction::*;
+use assembly::Operand;
+use assembly::Operand::*;
+use assembly::RegisterVal;
+use assembly::RegisterVal::*;
+
+// Rewrites short sequences of instructions into cheaper ones, looking at
+// one or two instructions at a time. Comments are skipped over, so they
+// never get in the way of a rule. The rules are applied until none of them
+// changes anything.
+
+type Rule = fn(&mut Vec<Instruction>) -> bool;
+
+const RULES: [Rule; 7] = [
+    remove_nops,
+    remove_self_moves,
+    push_pop_to_move,
+    remove_reload_after_store,
+    merge_stack_adjustments,
+    fold_constant_push,
+    remove_jump_to_next_label,
+];
+
+/// Optimize the instructions of a function
+pub fn optimize(mut instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for rule in RULES.iter() {
+            changed |= rule(&mut instructions);
+        }
+    }
+    instructions
+}
+
+// The index of the first instruction from start on which isn't a comment
+fn skip_comments(instructions: &[Instruction], start: usize) -> Option<usize> {
+    (start..instructions.len()).find(|&i| !matches!(instructions[i], Comment(_)))
+}
+
+fn is_same_register(a: RegisterVal, b: RegisterVal) -> bool {
+    let full_register = |r| match r {
+        AL => EAX,
+        CL => ECX,
+        _ => r,
+    };
+    full_register(a) == full_register(b)
+}
+
+fn reads_register(op: &Operand, reg: RegisterVal) -> bool {
+    match *op {
+        Register(r) | Dereference(r, _) => is_same_register(r, reg),
+        IntConstant(_) | Variable(_) => false,
+    }
+}
+
+fn is_memory(op: &Operand) -> bool {
+    matches!(*op, Dereference(_, _))
+}
+
+fn uses_stack_pointer(op: &Operand) -> bool {
+    reads_register(op, ESP)
+}
+
+// How much the instruction moves the stack pointer, if that's all it does
+fn get_stack_adjustment(instr: &Instruction) -> Option<i32> {
+    match *instr {
+        Add(IntConstant(n), Register(ESP)) => Some(n),
+        Subtract(IntConstant(n), Register(ESP)) => Some(-n),
+        _ => None,
+    }
+}
+
+// True if the register's value is never read after the start. We give up
+// at anything that could go somewhere else.
+fn is_dead_after(instructions: &[Instruction], start: usize,
+                 reg: RegisterVal) -> bool {
+    for instr in instructions[start..].iter() {
+        match *instr {
+            Comment(_) | NOP => {}
+            // Calls don't take arguments in registers, and don't keep
+            // the caller saved ones
+            Call(_) => {
+                return is_same_register(reg, EAX) || is_same_register(reg, ECX);
+            }
+            Move(ref src, Register(dest)) if dest == reg => {
+                return !reads_register(src, reg);
+            }
+            Move(ref a, ref b) | Add(ref a, ref b) | Subtract(ref a, ref b) |
+            Multiply(ref a, ref b) | Compare(ref a, ref b) => {
+                if reads_register(a, reg) || reads_register(b, reg) {
+                    return false;
+                }
+            }
+            Push(ref a) | Pop(ref a) => {
+                if reads_register(a, reg) {
+                    return false;
+                }
+            }
+            Divide(_) | Jump(_) | JumpIfEqual(_) | JumpIfNotEqual(_) |
+            Label(_) | Other(_) | OtherStatic(_) | OtherTwoArg(_, _, _) => {
+                return false;
+            }
+        }
+    }
+    false
+}
+
+/// Remove instructions that do nothing
+fn remove_nops(instructions: &mut Vec<Instruction>) -> bool {
+    let len = instructions.len();
+    instructions.retain(|instr| *instr != NOP);
+    instructions.len() != len
+}
+
+/// Remove moves of something to where it already is
+fn remove_self_moves(instructions: &mut Vec<Instruction>) -> bool {
+    let len = instructions.len();
+    instructions.retain(|instr| match *instr {
+        Move(ref a, ref b) => a != b,
+        _ => true,
+    });
+    instructions.len() != len
+}
+
+/// pushl a; popl b is movl a, b (or nothing, if a is b)
+fn push_pop_to_move(instructions: &mut Vec<Instruction>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < instructions.len() {
+        let j = match skip_comments(instructions, i + 1) {
+            Some(j) => j,
+            None => break,
+        };
+
+        let replacement = match (&instructions[i], &instructions[j]) {
+            (Push(a), Pop(b)) if !uses_stack_pointer(a) &&
+                !uses_stack_pointer(b) && (!is_memory(a) || !is_memory(b)) => {
+                Some(if a == b { NOP } else { Move(a.clone(), b.clone()) })
+            }
+            _ => None,
+        };
+
+        if let Some(replacement) = replacement {
+            instructions[i] = replacement;
+            instructions.remove(j);
+            changed = true;
+        }
+        i += 1;
+    }
+    changed
+}
+
+/// movl %reg, m; movl m, %reg doesn't need the second move
+fn remove_reload_after_store(instructions: &mut Vec<Instruction>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < instructions.len() {
+        let j = match skip_comments(instructions, i + 1) {
+            Some(j) => j,
+            None => break,
+        };
+
+        let is_reload = match (&instructions[i], &instructions[j]) {
+            (Move(Register(r), a), Move(b, Register(s))) => {
+                r == s && a == b && !reads_register(a, *r)
+            }
+            _ => false,
+        };
+
+        if is_reload {
+            instructions.remove(j);
+            changed = true;
+        }
+        i += 1;
+    }
+    changed
+}
+
+/// Two changes to the stack pointer in a row become one
+fn merge_stack_adjustments(instructions: &mut Vec<Instruction>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < instructions.len() {
+        let j = match skip_comments(instructions, i + 1) {
+            Some(j) => j,
+            None => break,
+        };
+
+        match (get_stack_adjustment(&instructions[i]),
+               get_stack_adjustment(&instructions[j])) {
+            (Some(a), Some(b)) => {
+                let total = a + b;
+                instructions[i] = if total > 0 {
+                    Add(IntConstant(total), Register(ESP))
+                } else if total < 0 {
+                    Subtract(IntConstant(-total), Register(ESP))
+                } else {
+                    NOP
+                };
+                instructions.remove(j);
+                changed = true;
+            }
+            _ => i += 1,
+        }
+    }
+    changed
+}
+
+/// movl $c, %reg; pushl %reg is pushl $c, when nothing reads %reg after
+fn fold_constant_push(instructions: &mut Vec<Instruction>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < instructions.len() {
+        let j = match skip_comments(instructions, i + 1) {
+            Some(j) => j,
+            None => break,
+        };
+
+        let constant = match (&instructions[i], &instructions[j]) {
+            (&Move(IntConstant(c), Register(r)), &Push(Register(p)))
+                if r == p && is_dead_after(instructions, j + 1, r) => Some(c),
+            _ => None,
+        };
+
+        if let Some(c) = constant {
+            instructions[i] = Push(IntConstant(c));
+            instructions.remove(j);
+            changed = true;
+        }
+        i += 1;
+    }
+    changed
+}
+
+/// Jumping to the label right after the jump is the same as not jumping
+fn remove_jump_to_next_label(instructions: &mut Vec<Instruction>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < instructions.len() {
+        let target = match instructions[i] {
+            Jump(ref label) | JumpIfEqual(ref label) |
+            JumpIfNotEqual(ref label) => label.clone(),
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+
+        // There can be several labels in a row
+        let mut jumps_to_next = false;
+        let mut j = i + 1;
+        while let Some(k) = skip_comments(instructions, j) {
+            match instructions[k] {
+                Label(ref label) => {
+                    if *label == target {
+                        jumps_to_next = true;
+                        break;
+                    }
+                    j = k + 1;
+                }
+                _ => break,
+            }
+        }
+
+        if jumps_to_next {
+            instructions.remove(i);
+            changed = true;
+        } else {
+            i += 1;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nops_are_removed() {
+        let mut instructions = vec![NOP, Push(Register(EAX)), NOP];
+        assert!(remove_nops(&mut instructions));
+        assert_eq!(instructions, vec![Push(Register(EAX))]);
+    }
+
+    #[test]
+    fn self_moves_are_removed() {
+        let mut instructions = vec![
+            Move(Register(EAX), Register(EAX)),
+            Move(Dereference(EBP, -4), Dereference(EBP, -4)),
+            Move(Register(EAX), Register(ECX)),
+        ];
+        assert!(remove_self_moves(&mut instructions));
+        assert_eq!(instructions, vec![Move(Register(EAX), Register(ECX))]);
+    }
+
+    #[test]
+    fn push_pop_becomes_move() {
+        let mut instructions = vec![
+            Push(Register(EAX)),
+            Comment("x".to_string()),
+            Pop(Register(ECX)),
+            Push(Register(ECX)),
+            Pop(Register(ECX)),
+        ];
+        assert!(push_pop_to_move(&mut instructions));
+        assert_eq!(instructions, vec![
+            Move(Register(EAX), Register(ECX)),
+            Comment("x".to_string()),
+            NOP,
+        ]);
+
+        // There's no move from memory to memory
+        let mut instructions = vec![
+            Push(Dereference(EBP, 8)),
+            Pop(Dereference(EBP, -4)),
+        ];
+        assert!(!push_pop_to_move(&mut instructions));
+    }
+
+    #[test]
+    fn reload_after_store_is_removed() {
+        let mut instructions = vec![
+            Move(Register(EAX), Dereference(EBP, -4)),
+            Comment("x".to_string()),
+            Move(Dereference(EBP, -4), Register(EAX)),
+            Move(Register(EAX), Dereference(EBP, -8)),
+            Move(Dereference(EBP, -8), Register(ECX)),
+        ];
+        assert!(remove_reload_after_store(&mut instructions));
+        assert_eq!(instructions, vec![
+            Move(Register(EAX), Dereference(EBP, -4)),
+            Comment("x".to_string()),
+            Move(Register(EAX), Dereference(EBP, -8)),
+            Move(Dereference(EBP, -8), Register(ECX)),
+        ]);
+    }
+
+    #[test]
+    fn stack_adjustments_are_merged() {
+        let mut instructions = vec![
+            Subtract(IntConstant(8), Register(ESP)),
+            Subtract(IntConstant(4), Register(ESP)),
+            Add(IntConstant(4), Register(ESP)),
+            Call("f".to_string()),
+            Add(IntConstant(8), Register(ESP)),
+            Subtract(IntConstant(8), Register(ESP)),
+        ];
+        assert!(merge_stack_adjustments(&mut instructions));
+        assert_eq!(instructions, vec![
+            Subtract(IntConstant(8), Register(ESP)),
+            Call("f".to_string()),
+            NOP,
+        ]);
+    }
+
+    #[test]
+    fn constant_push_is_folded() {
+        let mut instructions = vec![
+            Move(IntConstant(3), Register(EAX)),
+            Push(Register(EAX)),
+            Call("f".to_string()),
+        ];
+        assert!(fold_constant_push(&mut instructions));
+        assert_eq!(instructions, vec![
+            Push(IntConstant(3)),
+            Call("f".to_string()),
+        ]);
+
+        // Not when the register is read afterwards
+        let mut instructions = vec![
+            Move(IntConstant(3), Register(EAX)),
+            Push(Register(EAX)),
+            Add(Register(EAX), Register(ECX)),
+        ];
+        assert!(!fold_constant_push(&mut instructions));
+    }
+
+    #[test]
+    fn jump_to_next_label_is_removed() {
+        let mut instructions = vec![
+            Jump("a".to_string()),
+            Label("b".to_string()),
+            Label("a".to_string()),
+            JumpIfEqual("c".to_string()),
+            Label("d".to_string()),
+        ];
+        assert!(remove_jump_to_next_label(&mut instructions));
+        assert_eq!(instructions, vec![
+            Label("b".to_string()),
+            Label("a".to_string()),
+            JumpIfEqual("c".to_string()),
+            Label("d".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn rules_are_applied_until_nothing_changes() {
+        let instructions = vec![
+            Push(Register(EAX)),
+            Pop(Register(EAX)),
+            Jump("next".to_string()),
+            Label("next".to_string()),
+        ];
+        assert_eq!(optimize(instructions), vec![Label("next".to_string())]);
+    }
+}
@@ -22,10 +22,12 @@ fn multiply_by(left: AstExpressionNode, right: AstExpressionNode,
     assert!(type_contains(typ, left.typ.as_ref().unwrap()));
     assert!(type_contains(typ, right.typ.as_ref().unwrap()));
 
+    let line = right.line;
     let expr = BinaryOp(ast::BinaryOp::Multiply,
                         Box::new(left), Box::new(right));
     let mut new_expr = AstExpressionNode::new(expr);
     new_expr.typ = Some(typ.clone());
+    new_expr.line = line;
     new_expr
 }
 
@@ -106,11 +108,21 @@ impl PointerArithmeticTransformer {
                 FieldAccess(Box::new(self.transform_expr(*expr)),
                             field_name)
             }
+            Index(array, index) => {
+                Index(Box::new(self.transform_expr(*array)),
+                      Box::new(self.transform_expr(*index)))
+            }
+            Slice(array, start, end) => {
+                Slice(Box::new(self.transform_expr(*array)),
+                      Box::new(self.transform_expr(*start)),
+                      Box::new(self.transform_expr(*end)))
+            }
             _ => expr_node.expr
         };
 
         let mut node = AstExpressionNode::new(new_expr);
         node.typ = expr_node.typ.clone();
+        node.line = expr_node.line;
         node
 
     }
@@ -135,6 +147,10 @@ impl PointerArithmeticTransformer {
                 };
                 Statement::If(self.transform_expr(expr), then_block, transformed_else_block)
             }
+            Statement::Unsafe(line, mut block) => {
+                self.transform_block(&mut block);
+                Statement::Unsafe(line, block)
+            }
             Statement::While(expr, mut block) => {
                 self.transform_block(&mut block);
                 Statement::While(self.transform_expr(expr), block)
@@ -0,0 +1,163 @@
+use assembly::RegisterVal;
+use assembly::RegisterVal::*;
+
+use dataflow::solve;
+use dataflow::IrLiveness;
+
+use ir::Instr;
+use ir::IrBinOp;
+use ir::IrFunction;
+use ir::VReg;
+use ir::Value;
+
+// Linear scan register allocation. Every virtual register gets an interval,
+// from the first instruction it's live at to the last one, in the order
+// the blocks are laid out. The intervals are then gone through by where
+// they start, giving each one a free register, or, when there isn't one,
+// spilling whichever interval ends last to the stack.
+//
+// EAX and ECX aren't handed out: the code generator moves operands into
+// them to compute with. Calls and divisions overwrite EDX, so it's only
+// given to intervals that don't live across either.
+
+/// The registers a virtual register can be kept in, in the order they're
+/// preferred. EDX comes first since it doesn't have to be saved.
+pub const ALLOCATABLE_REGISTERS: [RegisterVal; 4] = [EDX, EBX, ESI, EDI];
+
+/// The registers a function has to give back the way it found them
+pub const CALLEE_SAVED_REGISTERS: [RegisterVal; 3] = [EBX, ESI, EDI];
+
+struct Interval {
+    vreg: VReg,
+    start: usize,
+    end: usize,
+}
+
+// Whether the instruction overwrites EDX
+fn clobbers_edx(instr: &Instr) -> bool {
+    match *instr {
+        Instr::Call { .. } => true,
+        Instr::BinOp { op, .. } => op == IrBinOp::Div,
+        _ => false,
+    }
+}
+
+// The interval of every virtual register that's used, by where it starts,
+// and the places EDX is overwritten
+fn build_intervals(fun: &IrFunction) -> (Vec<Interval>, Vec<usize>) {
+    let graph = fun.get_flow_graph();
+    let liveness = solve(&IrLiveness::new(fun), &graph);
+
+    let mut ranges: Vec<Option<(usize, usize)>> = vec![None; fun.vreg_types.len()];
+    let mut extend = |reg: VReg, pos: usize| {
+        ranges[reg] = Some(match ranges[reg] {
+            Some((start, end)) => (start.min(pos), end.max(pos)),
+            None => (pos, pos),
+        });
+    };
+
+    // The start of every block, every instruction and every terminator has
+    // a position. A register is live from its first position to its last,
+    // so it's enough to extend it to where it's used and written, and to
+    // the start and end of the blocks it's live into and out of. The start
+    // of a block has its own position so that what's live into it is live
+    // across its first instruction.
+    let mut edx_clobbers = Vec::new();
+    let mut pos = 0;
+    for (i, block) in fun.blocks.iter().enumerate() {
+        for &reg in liveness.fact_in[i].iter() {
+            extend(reg, pos);
+        }
+        pos += 1;
+        for instr in block.instrs.iter() {
+            for value in instr.get_uses() {
+                if let Value::Reg(reg) = *value {
+                    extend(reg, pos);
+                }
+            }
+            for reg in instr.get_dests() {
+                extend(reg, pos);
+            }
+            if clobbers_edx(instr) {
+                edx_clobbers.push(pos);
+            }
+            pos += 1;
+        }
+        for value in block.terminator.get_uses() {
+            if let Value::Reg(reg) = *value {
+                extend(reg, pos);
+            }
+        }
+        for &reg in liveness.fact_out[i].iter() {
+            extend(reg, pos);
+        }
+        pos += 1;
+    }
+
+    let mut intervals: Vec<Interval> = ranges.iter()
+        .enumerate()
+        .filter_map(|(vreg, range)| {
+            range.map(|(start, end)| Interval { vreg, start, end })
+        })
+        .collect();
+    intervals.sort_by_key(|interval| interval.start);
+    (intervals, edx_clobbers)
+}
+
+/// Choose a register for each virtual register of the function, out of
+/// the available ones. None means it's kept on the stack.
+pub fn allocate_registers(fun: &IrFunction,
+                          available: &[RegisterVal]) -> Vec<Option<RegisterVal>> {
+    let mut allocation = vec![None; fun.vreg_types.len()];
+    let (intervals, edx_clobbers) = build_intervals(fun);
+
+    // Whether the interval can be kept in the register
+    let can_use = |interval: &Interval, reg: RegisterVal| {
+        reg != EDX || !edx_clobbers.iter()
+            .any(|&pos| interval.start < pos && pos < interval.end)
+    };
+
+    // The intervals that have a register, with where they end
+    let mut active: Vec<(usize, &Interval, RegisterVal)> = Vec::new();
+    let mut free: Vec<RegisterVal> = available.to_vec();
+
+    for interval in intervals.iter() {
+        // Intervals which ended give their register back
+        active.retain(|&(end, _, reg)| {
+            if end < interval.start {
+                free.push(reg);
+                false
+            } else {
+                true
+            }
+        });
+
+        // Reusing the registers already in use keeps down how many have
+        // to be saved
+        let choice = available.iter()
+            .find(|&&reg| free.contains(&reg) && can_use(interval, reg))
+            .cloned();
+        if let Some(reg) = choice {
+            free.retain(|&r| r != reg);
+            allocation[interval.vreg] = Some(reg);
+            active.push((interval.end, interval, reg));
+            continue;
+        }
+
+        // Nothing's free, so spill whichever interval ends last: this one,
+        // or an active one whose register this one can use
+        let victim = active.iter()
+            .enumerate()
+            .filter(|&(_, &(end, _, reg))| end > interval.end && can_use(interval, reg))
+            .max_by_key(|&(_, &(end, _, _))| end)
+            .map(|(i, _)| i);
+        if let Some(i) = victim {
+            let (_, spilled, reg) = active.remove(i);
+            allocation[spilled.vreg] = None;
+            allocation[interval.vreg] = Some(reg);
+            active.push((interval.end, interval, reg));
+        }
+    }
+
+    allocation
+}
@@ -4,12 +4,11 @@ use ast::VarType;
 use assembly::MachineType;
 
 use assembly_helper::get_mtype_size;
+use assembly_helper::WORD_SIZE;
 
 use std::collections::HashMap;
 
 pub struct FieldInfo {
-    pub typ: VarType,
-    pub machine_type: MachineType,
     pub offset: i32,
 }
 
@@ -38,8 +37,6 @@ impl RepresentationManager {
         for (field, typ) in defn.fields.iter() {
             let info = FieldInfo {
                 offset: offset,
-                typ: typ.clone(),
-                machine_type: self.get_machine_type(typ),
             };
 
             field_to_info.insert(field.clone(), info);
@@ -81,6 +78,8 @@ impl RepresentationManager {
     pub fn get_machine_type(&self, typ: &VarType) -> MachineType {
         match *typ {
             VarType::Pointer(_, _) => MachineType::Long,
+            // The address of the first element, then the length
+            VarType::Array(_, _) => MachineType::Chunk(2 * WORD_SIZE),
             VarType::Int => MachineType::Long,
             VarType::Char => MachineType::Byte,
             VarType::Struct(ref name) => {
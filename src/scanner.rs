@@ -25,9 +25,16 @@ fn token_to_lexeme(token: &str) -> Lexeme {
         "return" => Lexeme::Return,
         "print" => Lexeme::Print,
         "struct" => Lexeme::Struct,
+        "unsafe" => Lexeme::Unsafe,
+        "inline" => Lexeme::Inline,
         "int" => Lexeme::Type(VarType::Int),
         "char" => Lexeme::Type(VarType::Char),
         "owned_pointer" => Lexeme::Type(VarType::OwnedPointer),
+        "shared_pointer" => Lexeme::Type(VarType::SharedPointer),
+        "ref" => Lexeme::Type(VarType::Ref),
+        "mut_ref" => Lexeme::Type(VarType::MutRef),
+        "owned_array" => Lexeme::Type(VarType::OwnedArray),
+        "array_view" => Lexeme::Type(VarType::ArrayView),
         "&" => Lexeme::Reference,
         "=" => Lexeme::Assign,
         "==" => Lexeme::Operator(OperatorType::CompareEqual),
@@ -47,6 +54,9 @@ fn token_to_lexeme(token: &str) -> Lexeme {
         ";" => Lexeme::EndOfStatement,
         "," => Lexeme::Comma,
         "." => Lexeme::Dot,
+        ".." => Lexeme::DotDot,
+        "[" => Lexeme::LBracket,
+        "]" => Lexeme::RBracket,
         _ => {
             // Case 1: It's a string constant
             if token.starts_with("\"") && token.ends_with("\"") {
@@ -110,16 +120,21 @@ fn get_string_constant(chars: &mut LinkedList<char>) -> String {
     s
 }
 
-/// Return a linkedList of lexemes given the source code
-fn get_token_strings(source: &str) -> LinkedList<Lexeme> {
+/// Return a linkedList of lexemes given the source code, each with the
+/// line it's on
+fn get_token_strings(source: &str) -> LinkedList<(Lexeme, usize)> {
     let mut chars: LinkedList<char> = source.chars().collect();
     let mut tokens = LinkedList::new();
     if source.len() == 0 {
         return tokens;
     }
 
+    let mut line = 1;
     while let Some(c) = chars.pop_front() {
         if c.is_whitespace() {
+            if c == '\n' {
+                line += 1;
+            }
             continue;
         }
 
@@ -143,6 +158,10 @@ fn get_token_strings(source: &str) -> LinkedList<Lexeme> {
                     s.push(chars.pop_front().unwrap());
                 }
             }
+            '.' if next_char == Some('.') => {
+                // '..' is a single token
+                s.push(chars.pop_front().unwrap());
+            }
             '"' => {
                 // Push c back and get the string constant
                 chars.push_front(c);
@@ -170,7 +189,7 @@ fn get_token_strings(source: &str) -> LinkedList<Lexeme> {
         };
 
         // Store the token in the linkedList
-        tokens.push_back(token_to_lexeme(&s));
+        tokens.push_back((token_to_lexeme(&s), line));
     }
 
     tokens
@@ -1,4 +1,5 @@
 use ast::StructDefinition;
+use ast::PointerType;
 use ast::VarType;
 
 // Make sure our structs don't have any cycles
@@ -44,6 +45,8 @@ impl StructAnalyzer {
             VarType::Int | VarType::Char => true,
             VarType::Pointer(_, ref pointed_type) => 
                 self.is_type_declared(pointed_type),
+            VarType::Array(_, ref element_type) =>
+                self.is_type_declared(element_type),
             VarType::Struct(ref struct_name) => 
                 self.structs_declared.contains(struct_name),
         }
@@ -72,6 +75,24 @@ impl StructAnalyzer {
                     self.errors_found.push(msg);
                     return false;
                 }
+
+                // A struct could outlive whatever the reference was
+                // borrowed from
+                if let VarType::Pointer(PointerType::Ref, _) |
+                       VarType::Pointer(PointerType::MutRef, _) = *typ {
+                    let msg = format!("Field {} of struct {} cannot be a \
+                                       reference", field, struct_defn.name);
+                    self.errors_found.push(msg);
+                    return false;
+                }
+
+                // Arrays can only be stored in local variables for now
+                if let VarType::Array(_, _) = *typ {
+                    let msg = format!("Field {} of struct {} cannot be an \
+                                       array", field, struct_defn.name);
+                    self.errors_found.push(msg);
+                    return false;
+                }
             }
 
             self.structs_defined.insert(struct_defn.name.clone());
@@ -0,0 +1,175 @@
+use ast::Block;
+use ast::Expression;
+use ast::Program;
+use ast::Statement;
+
+use ir::Instr;
+use ir::IrFunction;
+use ir::IrProgram;
+use ir::Terminator;
+use ir::Value;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// Turns calls whose result is returned right away into tail calls, which
+// jump to the function instead of calling it, so that it returns straight
+// to our caller. The arguments are put where ours are, so a tail call can
+// only pass as many words as we were passed. That's enough for a function
+// calling itself, and for siblings that take the same arguments. The
+// callee's frame takes the place of ours, so functions with locals in
+// memory (structs, or variables whose address is taken) can't make them.
+
+/// A `return f(...);` in the source, where f is a function of the program
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TailCallSite {
+    pub function: String,
+    pub callee: String,
+    pub line: usize,
+}
+
+fn collect_sites_block(block: &Block, function: &str, defined: &HashSet<&str>,
+                       sites: &mut HashSet<TailCallSite>) {
+    for stmt in block.statements.iter() {
+        match *stmt {
+            Statement::Return(ref expr) => {
+                if let Expression::Call(ref fn_call) = expr.expr {
+                    if defined.contains(fn_call.name.as_str()) {
+                        sites.insert(TailCallSite {
+                            function: function.to_string(),
+                            callee: fn_call.name.clone(),
+                            line: expr.line,
+                        });
+                    }
+                }
+            }
+            Statement::If(_, ref then_block, ref else_block_opt) => {
+                collect_sites_block(then_block, function, defined, sites);
+                if let Some(ref else_block) = *else_block_opt {
+                    collect_sites_block(else_block, function, defined, sites);
+                }
+            }
+            Statement::While(_, ref block) | Statement::Unsafe(_, ref block) => {
+                collect_sites_block(block, function, defined, sites);
+            }
+            Statement::Print(_) | Statement::Let(_, _, _) |
+            Statement::Assign(_, _) | Statement::Call(_) => {}
+        }
+    }
+}
+
+/// Every call in the program that's in tail position in the source
+pub fn get_tail_call_sites(program: &Program) -> HashSet<TailCallSite> {
+    let defined: HashSet<&str> = program.functions.iter()
+        .map(|function| function.name.as_str())
+        .collect();
+    let mut sites = HashSet::new();
+    for function in program.functions.iter() {
+        collect_sites_block(&function.statements, &function.name, &defined,
+                            &mut sites);
+    }
+    sites
+}
+
+// Why the call at the index of the block can't be a tail call, if it can't
+fn get_blocker(fun: &IrFunction, block: usize, index: usize,
+               signatures: &HashMap<&str, (usize, usize)>) -> Option<String> {
+    let (dests, name, args) = match fun.blocks[block].instrs[index] {
+        Instr::Call { ref dests, ref name, ref args, .. } => (dests, name, args),
+        _ => panic!("Not a call"),
+    };
+
+    let &(_, return_words) = match signatures.get(name.as_str()) {
+        Some(signature) => signature,
+        None => return Some(format!("{} isn't a function of the program", name)),
+    };
+    let returned: Vec<Value> = dests.iter().map(|&dest| Value::Reg(dest)).collect();
+    let is_returned = index + 1 == fun.blocks[block].instrs.len() &&
+        fun.blocks[block].terminator == Terminator::Return(returned);
+    if !is_returned || return_words != fun.return_words {
+        return Some("code has to run between the call and the return, like \
+                     freeing owned_pointers".to_string());
+    }
+    // Whatever the callee is passed could point into our frame, which its
+    // own frame would take the place of
+    if !fun.slots.is_empty() {
+        return Some(format!("{} has locals whose address could be passed to {}",
+                            fun.name, name));
+    }
+    if args.len() > fun.params.len() {
+        return Some(format!("{} is passed {} words, but {} is only passed {}",
+                            name, args.len(), fun.name, fun.params.len()));
+    }
+    None
+}
+
+/// Make every call that can be a tail call one. Returns what was done, and
+/// why each call in tail position in the source that wasn't made a tail
+/// call couldn't be.
+pub fn optimize_tail_calls(program: &mut IrProgram,
+                           sites: &HashSet<TailCallSite>) -> (Vec<String>, Vec<String>) {
+    let signatures: HashMap<&str, (usize, usize)> = program.functions.iter()
+        .map(|fun| (fun.name.as_str(), (fun.params.len(), fun.return_words)))
+        .collect();
+
+    // Find them all first, as the signatures borrow the program
+    let mut tail_calls = Vec::new();
+    let mut blocked: HashMap<TailCallSite, String> = HashMap::new();
+    let mut found = HashSet::new();
+    for (f, fun) in program.functions.iter().enumerate() {
+        for (b, block) in fun.blocks.iter().enumerate() {
+            for (i, instr) in block.instrs.iter().enumerate() {
+                let (name, line) = match *instr {
+                    Instr::Call { ref name, line, .. } => (name, line),
+                    _ => continue,
+                };
+                let site = TailCallSite {
+                    function: fun.name.clone(),
+                    callee: name.clone(),
+                    line,
+                };
+                found.insert(site.clone());
+                match get_blocker(fun, b, i, &signatures) {
+                    None => tail_calls.push((f, b, site)),
+                    Some(reason) => {
+                        if sites.contains(&site) {
+                            blocked.insert(site, reason);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut done = Vec::new();
+    for (f, b, site) in tail_calls {
+        let block = &mut program.functions[f].blocks[b];
+        let (name, args, line) = match block.instrs.pop() {
+            Some(Instr::Call { name, args, line, .. }) => (name, args, line),
+            _ => panic!("Not a call"),
+        };
+        block.lines.pop();
+        block.terminator = Terminator::TailCall { name, args, line };
+        done.push(format!("call to {} in {} at line {} is a tail call",
+                          site.callee, site.function, site.line));
+        // Another call to the same function on the line may not have been
+        // in tail position
+        blocked.remove(&site);
+    }
+
+    // Anything that can't be found may have been changed on the way, so it
+    // can't be said to be a tail call
+    for site in sites.iter().filter(|site| !found.contains(site)) {
+        blocked.insert(site.clone(), "the call can't be found in the IR, like when it was \
+                                   inlined".to_string());
+    }
+
+    let mut failed: Vec<String> = blocked.into_iter()
+        .map(|(site, reason)| {
+            format!("call to {} in {} at line {} can't be a tail call: {}",
+                    site.callee, site.function, site.line, reason)
+        })
+        .collect();
+    failed.sort();
+    (done, failed)
+}
@@ -1,35 +1,47 @@
 use std::collections::LinkedList;
 use lexeme::Lexeme;
 
-/// TokenStream is simply a stack of Lexeme implemented with linkedList
+/// TokenStream is simply a stack of Lexeme implemented with linkedList.
+/// Each Lexeme is kept with the line of the source it came from.
 pub struct TokenStream {
-    token_list: LinkedList<Lexeme>,
+    token_list: LinkedList<(Lexeme, usize)>,
+
+    // The line of the last token consumed
+    last_line: usize,
 }
 
 impl TokenStream {
     // FIXME: Perhaps this should take an iterator and build a linked list from it?
-    pub fn new(tokens: LinkedList<Lexeme>) -> TokenStream {
-        TokenStream{token_list: tokens}
+    pub fn new(tokens: LinkedList<(Lexeme, usize)>) -> TokenStream {
+        TokenStream{token_list: tokens, last_line: 0}
     }
 
     /// Pop the top of the stack
     pub fn consume(&mut self) -> Lexeme {
-        self.token_list.pop_front().expect("no more tokens!")
+        let (tok, line) = self.token_list.pop_front().expect("no more tokens!");
+        self.last_line = line;
+        tok
     }
 
     /// Peek the top of the stack
     pub fn peek(&self) -> Lexeme {
-        self.token_list.front().expect("no more tokens!").clone()
+        self.token_list.front().expect("no more tokens!").0.clone()
     }
 
     /// Peek the nth token of the stack
     pub fn peek_n(&self, n: i32) -> Lexeme {
-        self.token_list.iter().nth((n as usize) - 1).expect("no more tokens!").clone() 
+        self.token_list.iter().nth((n as usize) - 1).expect("no more tokens!").0.clone()
+    }
+
+    /// The line of the top of the stack
+    pub fn line(&self) -> usize {
+        self.token_list.front().map_or(self.last_line, |&(_, line)| line)
     }
 
-    /// Push a lexeme onto the stack
+    /// Push a lexeme onto the stack. It's assumed to be the token that was
+    /// just consumed.
     pub fn push(&mut self, tok: Lexeme) {
-        self.token_list.push_front(tok);
+        self.token_list.push_front((tok, self.last_line));
     }
 
     /// Return true if the stack is empty
@@ -1,5 +1,6 @@
 use ast::AstExpressionNode;
 use ast::Block;
+use ast::ArrayType;
 use ast::PointerType;
 use ast::Expression;
 use ast::FunctionType;
@@ -11,43 +12,101 @@ use ast::VarType;
 use ast::StructDefinition;
 use ast;
 
+use ast_helper::block_always_returns;
+use ast_helper::get_constant_value;
+use ast_helper::get_statement_line;
 use ast_helper::is_pointer;
+use ast_helper::statement_always_returns;
 
 use code_block::CodeBlock;
+use code_block::ScopeRegion;
+use code_block::innermost_region;
+use code_block::merge_regions;
 
 use type_checker_helper;
 use type_checker_helper::type_contains;
 use type_checker_helper::is_pointer_arithmetic;
 use type_checker_helper::is_pointer_comparison;
 use type_checker_helper::expression_has_address;
+use type_checker_helper::can_borrow;
+use type_checker_helper::can_hold_address;
+use type_checker_helper::is_array;
+use type_checker_helper::is_int_to_pointer;
+use type_checker_helper::is_reference;
+use type_checker_helper::is_shared_pointer;
+use type_checker_helper::writes_through_ref;
+
+use format_string::get_conversions;
+use format_string::printf_accepts;
+use format_string::scanf_accepts;
 
 use struct_analyzer::StructAnalyzer;
 
 use std::collections::HashMap;
+use std::collections::HashSet;
 
 // FIXME:/ TODO:
 // Rename some things "annotate_" rather than get_
 
+// What's known about the variables at a point of a function, which depends
+// on the path taken to get there
+#[derive(Clone, PartialEq)]
+struct FlowState {
+    regions: HashMap<String, ScopeRegion>,
+    arithmetic_pointers: HashSet<String>,
+}
+
+// The state after either a or b was executed
+fn merge_flow_states(a: &FlowState, b: &FlowState) -> FlowState {
+    FlowState {
+        regions: merge_regions(&a.regions, &b.regions),
+        arithmetic_pointers: a.arithmetic_pointers.union(&b.arithmetic_pointers)
+            .cloned()
+            .collect(),
+    }
+}
 
 pub struct TypeChecker {
     errors_found: Vec<String>,
+    warnings_found: Vec<String>,
     variable_to_type: HashMap<String, VarType>,
     struct_to_definition: HashMap<String, StructDefinition>,
     blocks: Vec<CodeBlock>,
 
+    // For pointer variables (and struct variables with pointer fields),
+    // the innermost scope that a stored address may point into
+    variable_to_region: HashMap<String, ScopeRegion>,
+
     current_fn: String,
     function_to_type: HashMap<String, ast::FunctionType>,
+
+    // Whether we're inside an unsafe block or function
+    in_unsafe: bool,
+
+    // Pointer variables which may hold the result of pointer arithmetic at
+    // this point of the function, so they can only be dereferenced in
+    // unsafe code
+    arithmetic_pointers: HashSet<String>,
+
+    // Set once the compiler has added its own code, which is the only code
+    // allowed to call the internal functions
+    allow_internal_calls: bool,
 }
 
 impl TypeChecker {
     pub fn new() -> TypeChecker {
         let mut t = TypeChecker {
             errors_found: Vec::new(),
+            warnings_found: Vec::new(),
             variable_to_type: HashMap::new(),
             struct_to_definition: HashMap::new(),
             blocks: Vec::new(),
+            variable_to_region: HashMap::new(),
             function_to_type: HashMap::new(),
             current_fn: "".to_string(),
+            in_unsafe: false,
+            arithmetic_pointers: HashSet::new(),
+            allow_internal_calls: false,
         };
 
         t.function_to_type.insert("printf".to_string(),
@@ -56,6 +115,8 @@ impl TypeChecker {
                                       arg_types: vec![Pointer(PointerType::Raw,
                                                               Box::new(Char))],
                                       is_var_args: true,
+                                      is_unsafe: false,
+                                      is_internal: false,
                                   });
         t.function_to_type.insert("scanf".to_string(),
                                   FunctionType {
@@ -63,12 +124,16 @@ impl TypeChecker {
                                       arg_types: vec![Pointer(PointerType::Raw,
                                                               Box::new(Char))],
                                       is_var_args: true,
+                                      is_unsafe: false,
+                                      is_internal: false,
                                   });
         t.function_to_type.insert("fflush".to_string(),
                                   FunctionType {
                                       return_type: Int,
                                       arg_types: vec![Int],
-                                      is_var_args: false
+                                      is_var_args: false,
+                                      is_unsafe: false,
+                                      is_internal: false,
                                   });
         t.function_to_type.insert("alloc_owned_int".to_string(),
                                   FunctionType {
@@ -76,6 +141,8 @@ impl TypeChecker {
                                                            Box::new(Int)),
                                       arg_types: vec![Int],
                                       is_var_args: false,
+                                      is_unsafe: false,
+                                      is_internal: false,
                                   });
         t.function_to_type.insert("allocate".to_string(),
                                   FunctionType {
@@ -83,6 +150,8 @@ impl TypeChecker {
                                                            Box::new(Char)),
                                       arg_types: vec![Int],
                                       is_var_args: false,
+                                      is_unsafe: false,
+                                      is_internal: false,
                                   });
         t.function_to_type.insert("free".to_string(),
                                   FunctionType {
@@ -90,6 +159,38 @@ impl TypeChecker {
                                       arg_types: vec![Pointer(PointerType::Raw,
                                                               Box::new(Char))],
                                       is_var_args: false,
+                                      is_unsafe: false,
+                                      is_internal: false,
+                                  });
+
+        // Used by the code inserted for shared_pointers. Implemented by the
+        // code generator.
+        t.function_to_type.insert("__shared_alloc".to_string(),
+                                  FunctionType {
+                                      return_type: Pointer(PointerType::Raw,
+                                                           Box::new(Char)),
+                                      arg_types: vec![Int],
+                                      is_var_args: false,
+                                      is_unsafe: false,
+                                      is_internal: true,
+                                  });
+        t.function_to_type.insert("__shared_retain".to_string(),
+                                  FunctionType {
+                                      return_type: Int,
+                                      arg_types: vec![Pointer(PointerType::Raw,
+                                                              Box::new(Char))],
+                                      is_var_args: false,
+                                      is_unsafe: false,
+                                      is_internal: true,
+                                  });
+        t.function_to_type.insert("__shared_release".to_string(),
+                                  FunctionType {
+                                      return_type: Int,
+                                      arg_types: vec![Pointer(PointerType::Raw,
+                                                              Box::new(Char))],
+                                      is_var_args: false,
+                                      is_unsafe: false,
+                                      is_internal: true,
                                   });
 
         t
@@ -100,17 +201,130 @@ impl TypeChecker {
     fn type_exists(&self, typ: &VarType) -> bool {
         match *typ {
             Int | Char => true,
-            Pointer(_, ref t) => self.type_exists(t),
+            // Arrays are only stored in local variables, so there are no
+            // pointers to them or arrays of them
+            Pointer(_, ref t) | Array(_, ref t) if is_array(t) => false,
+            Pointer(_, ref t) | Array(_, ref t) => self.type_exists(t),
             Struct(ref struct_name) => {
                 self.struct_to_definition.get(struct_name).is_some()
             }
         }
     }
 
+    // new_array(T, n). The parser turns T into the first argument, sizeof(T).
+    fn check_new_array(&mut self,
+                       call: &mut FunctionCall) -> Option<VarType> {
+        assert_eq!(call.args_exprs.len(), 2);
+        let element_type = match call.args_exprs[0].expr {
+            Expression::SizeOf(ref typ) => typ.clone(),
+            _ => panic!("The parser should give new_array a type"),
+        };
+        self.annotate_type(&mut call.args_exprs[0])?;
+
+        let len_type = self.annotate_type(&mut call.args_exprs[1])?;
+        if !type_contains(&Int, &len_type) {
+            self.errors_found.push(format!(
+                "The length of an array must be an int, not {:?}", len_type));
+            return None;
+        }
+
+        let array_type = Array(ArrayType::Owned, Box::new(element_type));
+        if !self.type_exists(&array_type) {
+            self.errors_found.push(format!("Type {:?} doesn't exist.",
+                                           array_type));
+            return None;
+        }
+        Some(array_type)
+    }
+
+    // len(a), the number of elements in an array
+    fn check_len(&mut self, call: &mut FunctionCall) -> Option<VarType> {
+        if call.args_exprs.len() != 1 {
+            self.errors_found.push(format!(
+                "len takes 1 argument, but got {}", call.args_exprs.len()));
+            return None;
+        }
+
+        let typ = self.annotate_type(&mut call.args_exprs[0])?;
+        if !is_array(&typ) {
+            self.errors_found.push(format!(
+                "Cannot take the len of something of type {:?}", typ));
+            return None;
+        }
+        Some(Int)
+    }
+
+    // The element type of the array being indexed or sliced
+    fn check_array_access(&mut self, array: &mut AstExpressionNode,
+                          indices: Vec<&mut AstExpressionNode>)
+                          -> Option<VarType> {
+        let array_type = self.annotate_type(array)?;
+        let element_type = match array_type {
+            Array(_, ref t) => *t.clone(),
+            _ => {
+                self.errors_found.push(format!(
+                    "Cannot index into something of type {:?}", array_type));
+                return None;
+            }
+        };
+
+        for index in indices {
+            let index_type = self.annotate_type(index)?;
+            if !type_contains(&Int, &index_type) {
+                self.errors_found.push(format!(
+                    "Array indices must be ints, not {:?}", index_type));
+                return None;
+            }
+        }
+        Some(element_type)
+    }
+
+    // Report an error if we're not in unsafe code
+    fn require_unsafe(&mut self, what: &str) -> bool {
+        if !self.in_unsafe {
+            self.errors_found.push(format!(
+                "{} is only allowed in unsafe code", what));
+        }
+        self.in_unsafe
+    }
+
+    // Whether value is the result of pointer arithmetic, or a variable
+    // holding one
+    fn is_from_arithmetic(&self, value: &AstExpressionNode) -> bool {
+        match value.expr {
+            Expression::BinaryOp(_, _, _) => {
+                value.typ.as_ref().is_some_and(is_pointer)
+            }
+            Expression::Variable(ref source) => {
+                self.arithmetic_pointers.contains(source)
+            }
+            _ => false,
+        }
+    }
+
+    // Remember whether name now holds the result of pointer arithmetic
+    fn track_arithmetic_pointer(&mut self, name: &str,
+                                value: &AstExpressionNode) {
+        if self.is_from_arithmetic(value) {
+            self.arithmetic_pointers.insert(name.to_string());
+        } else {
+            self.arithmetic_pointers.remove(name);
+        }
+    }
+
+    pub fn set_allow_internal_calls(&mut self, allow_internal_calls: bool) {
+        self.allow_internal_calls = allow_internal_calls;
+    }
+
     /// Return None if there exists a type error
     /// Return function's return type otherwise
     fn check_function_call(&mut self,
                            call: &mut FunctionCall) -> Option<VarType> {
+        match &call.name[..] {
+            "new_array" => return self.check_new_array(call),
+            "len" => return self.check_len(call),
+            _ => {}
+        }
 
         // Make sure the function exists
         let fn_type_opt = self.function_to_type.get(&call.name).cloned();
@@ -121,6 +335,18 @@ impl TypeChecker {
         }
         let fn_type = fn_type_opt.unwrap();
 
+        if fn_type.is_internal && !self.allow_internal_calls {
+            self.errors_found.push(format!("{} can only be called by code the \
+                                            compiler adds", call.name));
+            return None;
+        }
+
+        if fn_type.is_unsafe &&
+            !self.require_unsafe(&format!("Calling unsafe function {}",
+                                          call.name)) {
+            return None;
+        }
+
         let call_len = call.args_exprs.len();
         let definition_len = fn_type.arg_types.len();
         if (call_len != definition_len && !fn_type.is_var_args)
@@ -141,8 +367,21 @@ impl TypeChecker {
             // Var args functions may have more arguments than the definition
             if i < definition_len {
                 let param_type = fn_type.arg_types.get(i).unwrap();
-                // HACK: this is how we let free get called with any type
-                if !type_contains(param_type, &arg_type) && call.name != "free" {
+                // free takes any pointer, but an int would be turned into
+                // one without unsafe
+                if call.name == "free" && !is_pointer(&arg_type) &&
+                    !is_array(&arg_type) {
+                    self.errors_found.push(format!(
+                        "Cannot free {:?} at line {}, which isn't a pointer",
+                        call.args_exprs[i].expr, call.args_exprs[i].line));
+                    return None;
+                }
+
+                // HACK: this is how we let free (and the shared_pointer
+                // runtime functions) get called with any type
+                if !type_contains(param_type, &arg_type) &&
+                    !can_borrow(param_type, &call.args_exprs[i]) &&
+                    call.name != "free" && !fn_type.is_internal {
                     let err = format!("Expected type {:?} but got type {:?}",
                                       param_type, arg_type);
                     self.errors_found.push(err);
@@ -153,10 +392,61 @@ impl TypeChecker {
             }
         }
 
+        if (call.name == "printf" || call.name == "scanf") &&
+            !self.check_format_args(call) {
+            return None;
+        }
 
         Some(fn_type.return_type.clone())
     }
 
+    // Make sure the arguments of printf or scanf match the conversions in
+    // its format string. Formats that aren't string constants can't be
+    // checked.
+    fn check_format_args(&mut self, call: &FunctionCall) -> bool {
+        let format = match call.args_exprs[0].expr {
+            Expression::StringValue(ref format) => format,
+            _ => return true,
+        };
+
+        let conversions = match get_conversions(format) {
+            Ok(conversions) => conversions,
+            Err(err) => {
+                self.errors_found.push(err);
+                return false;
+            }
+        };
+
+        let args = &call.args_exprs[1..];
+        if conversions.len() != args.len() {
+            self.errors_found.push(format!(
+                "{} format {} expects {} arguments but got {}",
+                call.name, format, conversions.len(), args.len()));
+            return false;
+        }
+
+        let mut res = true;
+        for (i, (&conversion, arg)) in conversions.iter().zip(args).enumerate() {
+            let arg_type = arg.typ.as_ref()
+                .expect("Arguments should have types by now");
+            let accepted = if call.name == "printf" {
+                printf_accepts(conversion, arg_type)
+            } else {
+                scanf_accepts(conversion, arg_type)
+            };
+
+            if !accepted {
+                self.errors_found.push(format!(
+                    "%{} in {} format {} doesn't match argument {} of \
+                     type {:?}", conversion, call.name, format, i + 2,
+                    arg_type));
+                res = false;
+            }
+        }
+
+        res
+    }
+
     fn get_var_type_or_report(&mut self, name: &str) -> Option<&VarType> {
         let res = self.variable_to_type.get(name);
         if res == None {
@@ -165,6 +455,191 @@ impl TypeChecker {
         res
     }
 
+    // Nesting level of the block that declared a variable. Function
+    // arguments live as long as the function's outermost block.
+    fn get_scope_depth(&self, name: &str) -> usize {
+        self.blocks.iter()
+            .rposition(|b| b.declared_variables.contains(name))
+            .map_or(1, |i| i + 1)
+    }
+
+    // The innermost scope that the address computed by this expression may
+    // point into. None means it doesn't point at a local variable (heap
+    // memory, string constants, ...)
+    fn get_region(&self, expr_node: &AstExpressionNode) -> Option<ScopeRegion> {
+        match expr_node.expr {
+            Expression::Reference(ref expr) => self.get_lvalue_region(expr),
+            Expression::Variable(ref name) => {
+                self.variable_to_region.get(name).cloned()
+            }
+            Expression::FieldAccess(ref expr, _) => self.get_region(expr),
+            // A view into an array can't outlive it
+            Expression::Slice(ref array, _, _) => self.get_elements_region(array),
+            Expression::BinaryOp(_, ref l, ref r) => {
+                innermost_region(self.get_region(l), self.get_region(r))
+            }
+            // What's returned may be any address the function was given
+            Expression::Call(ref fn_call) => {
+                fn_call.args_exprs.iter()
+                    .fold(None, |region, arg| innermost_region(region, self.get_region(arg)))
+            }
+            _ => None,
+        }
+    }
+
+    // The scope that the memory of an lvalue lives in
+    fn get_lvalue_region(&self,
+                         expr_node: &AstExpressionNode) -> Option<ScopeRegion> {
+        match expr_node.expr {
+            Expression::Variable(ref name) => Some(ScopeRegion {
+                depth: self.get_scope_depth(name),
+                variable: name.clone(),
+                line: expr_node.line,
+            }),
+            Expression::FieldAccess(ref expr, _) => self.get_lvalue_region(expr),
+            // What an owned_pointer points to lives as long as the
+            // owned_pointer itself. A shared_pointer keeps its value alive
+            // at least that long too.
+            Expression::Dereference(ref expr)
+                if matches!(expr.typ, Some(Pointer(PointerType::Owned, _)) |
+                                      Some(Pointer(PointerType::Shared, _))) => {
+                self.get_lvalue_region(expr)
+            }
+            // Otherwise the address of *p is whatever p holds
+            Expression::Dereference(ref expr) => self.get_region(expr),
+            Expression::Index(ref array, _) => self.get_elements_region(array),
+            _ => None,
+        }
+    }
+
+    // The scope that the elements of an array live in. An owned_array's
+    // elements live as long as it does, and a view's are wherever it
+    // was taken from.
+    fn get_elements_region(&self,
+                           array: &AstExpressionNode) -> Option<ScopeRegion> {
+        match array.typ {
+            Some(Array(ArrayType::Owned, _)) => self.get_lvalue_region(array),
+            _ => self.get_region(array),
+        }
+    }
+
+    // The local variable whose memory an assignment writes to. None if we
+    // write through a pointer, since that memory may outlive every local.
+    fn get_storage_variable(expr_node: &AstExpressionNode) -> Option<&String> {
+        match expr_node.expr {
+            Expression::Variable(ref name) => Some(name),
+            Expression::FieldAccess(ref expr, _) => {
+                TypeChecker::get_storage_variable(expr)
+            }
+            _ => None,
+        }
+    }
+
+    // Make sure that storing the value of right into left doesn't let the
+    // address of a local outlive the local itself
+    fn check_borrow_escape(&mut self, left: &AstExpressionNode,
+                           right: &AstExpressionNode) -> bool {
+        let region = if right.typ.as_ref().is_some_and(can_hold_address) {
+            self.get_region(right)
+        } else {
+            None
+        };
+
+        // A variable given a new value no longer points where it used to
+        if let Expression::Variable(ref name) = left.expr {
+            self.variable_to_region.remove(name);
+        }
+
+        let region = match region {
+            Some(region) => region,
+            None => return true,
+        };
+
+        match TypeChecker::get_storage_variable(left) {
+            Some(name) if self.get_scope_depth(name) >= region.depth => {
+                let old_region = self.variable_to_region.get(name).cloned();
+                let new_region = innermost_region(old_region, Some(region));
+                self.variable_to_region.insert(name.clone(),
+                                               new_region.unwrap());
+                true
+            }
+            Some(name) => {
+                self.errors_found.push(format!(
+                    "Reference to {} taken at line {} escapes its scope at \
+                     line {}: it is stored in {}, which outlives {}",
+                    region.variable, region.line, right.line, name,
+                    region.variable));
+                false
+            }
+            None => {
+                self.errors_found.push(format!(
+                    "Reference to {} taken at line {} escapes its scope at \
+                     line {}: it is stored through a pointer, which may \
+                     outlive {}",
+                    region.variable, region.line, right.line, region.variable));
+                false
+            }
+        }
+    }
+
+    fn get_flow_state(&self) -> FlowState {
+        FlowState {
+            regions: self.variable_to_region.clone(),
+            arithmetic_pointers: self.arithmetic_pointers.clone(),
+        }
+    }
+
+    fn set_flow_state(&mut self, state: FlowState) {
+        self.variable_to_region = state.regions;
+        self.arithmetic_pointers = state.arithmetic_pointers;
+    }
+
+    // Check the block with what's known about the variables on entry, and
+    // return what's known after it
+    fn annotate_types_branch(&mut self, block: &mut Block,
+                             state: FlowState) -> (bool, FlowState) {
+        self.set_flow_state(state);
+        let res = self.annotate_types_block(block);
+        (res, self.get_flow_state())
+    }
+
+    // A loop's condition and body may run any number of times, so they're
+    // checked again with what's known after the body until that no longer
+    // changes. Errors already found in an earlier pass aren't reported
+    // twice.
+    fn annotate_types_loop(&mut self, condition: &mut AstExpressionNode,
+                           block: &mut Block) -> bool {
+        let mut state = self.get_flow_state();
+        loop {
+            let num_errors = self.errors_found.len();
+            let num_warnings = self.warnings_found.len();
+            self.set_flow_state(state.clone());
+            let condition_ok = self.annotate_type(condition).is_some();
+            let (body_ok, after) = self.annotate_types_branch(block, state.clone());
+            let res = condition_ok && body_ok;
+            let merged = merge_flow_states(&state, &after);
+
+            let new_errors = self.errors_found.split_off(num_errors);
+            for err in new_errors {
+                if !self.errors_found.contains(&err) {
+                    self.errors_found.push(err);
+                }
+            }
+            let new_warnings = self.warnings_found.split_off(num_warnings);
+            for warning in new_warnings {
+                if !self.warnings_found.contains(&warning) {
+                    self.warnings_found.push(warning);
+                }
+            }
+
+            if merged == state {
+                self.set_flow_state(merged);
+                return res;
+            }
+            state = merged;
+        }
+    }
+
     // If name is a variable of type Pointer(Int), we return Int.
     fn get_binary_op_expr_type(&mut self,
                                op: &ast::BinaryOp, l: &mut AstExpressionNode,
@@ -179,7 +654,30 @@ impl TypeChecker {
         let l_type = l_type_opt.unwrap();
         let r_type = r_type_opt.unwrap();
 
+        if *op == ast::BinaryOp::Divide && get_constant_value(r) == Some(0) {
+            self.errors_found.push(format!("Division by zero on line {}",
+                                           r.line));
+            return None;
+        }
+
         if is_pointer_arithmetic(&l_type, &r_type, *op) {
+            if is_reference(&l_type) || is_reference(&r_type) {
+                self.errors_found.push(format!(
+                    "Cannot do pointer arithmetic on references ({:?} and {:?})",
+                    l_type, r_type));
+                return None;
+            }
+            if is_shared_pointer(&l_type) || is_shared_pointer(&r_type) {
+                self.errors_found.push(format!(
+                    "Cannot do pointer arithmetic on shared_pointers ({:?} and {:?})",
+                    l_type, r_type));
+                return None;
+            }
+            if !self.require_unsafe(&format!(
+                "Pointer arithmetic ({:?} and {:?})", l_type, r_type)) {
+                return None;
+            }
+
             if is_pointer(&l_type) {
                 return Some(l_type);
             } else {
@@ -236,7 +734,13 @@ impl TypeChecker {
             Expression::Reference(ref mut expr) => {
                 let expr_type = self.annotate_type(expr);
                 if let Some(t) = expr_type {
-                    if expression_has_address(expr) {
+                    if is_array(&t) {
+                        self.errors_found.push(format!(
+                            "Cannot take the address of an array ({:?}). \
+                             Take a view of it with [lo..hi] instead.",
+                            expr.expr));
+                        None
+                    } else if expression_has_address(expr) {
                         Some(Pointer(PointerType::Raw, Box::new(t)))
                     } else {
                         self.errors_found.push(format!(
@@ -251,7 +755,18 @@ impl TypeChecker {
             }
             Expression::Dereference(ref mut expr) => {
                 let typ_opt = self.annotate_type(expr);
-                if let Some(&VarType::Pointer(_, ref typ)) = typ_opt.as_ref() {
+                let from_arithmetic = match expr.expr {
+                    Expression::Variable(ref name) => {
+                        self.arithmetic_pointers.contains(name)
+                    }
+                    _ => false,
+                };
+
+                if from_arithmetic && !self.require_unsafe(&format!(
+                    "Dereferencing {:?}, which comes from pointer arithmetic,",
+                    expr.expr)) {
+                    None
+                } else if let Some(&VarType::Pointer(_, ref typ)) = typ_opt.as_ref() {
                     Some(*typ.clone())
                 } else {
                     self.errors_found.push(format!("Cannot dereference \
@@ -286,6 +801,13 @@ impl TypeChecker {
                     None
                 }
             }
+            Expression::Index(ref mut array, ref mut index) => {
+                self.check_array_access(array, vec![index])
+            }
+            Expression::Slice(ref mut array, ref mut start, ref mut end) => {
+                self.check_array_access(array, vec![start, end])
+                    .map(|t| Array(ArrayType::View, Box::new(t)))
+            }
         };
         expr_node.typ = typ;
         expr_node.typ.clone()
@@ -301,14 +823,30 @@ impl TypeChecker {
                     .return_type;
 
                 let mut res = true;
-                if let Some(expr_t) = expr_type {
-                    if !type_contains(ret_type, &expr_t) {
+                if self.is_from_arithmetic(expr) {
+                    // Only local variables are tracked, so the caller could
+                    // dereference it outside unsafe code
+                    self.errors_found.push(format!(
+                        "Cannot return a pointer from pointer arithmetic \
+                         at line {}", expr.line));
+                    res = false;
+                } else if let Some(expr_t) = expr_type {
+                    if !type_contains(ret_type, &expr_t) &&
+                        !can_borrow(ret_type, expr) {
                         let msg = format!("Cannot return expr {:?} \
                                            for function with ret \
                                            type {:?}", expr_t,
                                           ret_type);
                         self.errors_found.push(msg);
                         res = false;
+                    } else if can_hold_address(&expr_t) {
+                        if let Some(region) = self.get_region(expr) {
+                            self.errors_found.push(format!(
+                                "Cannot return a reference to local \
+                                 variable {} taken at line {} at line {}",
+                                region.variable, region.line, expr.line));
+                            res = false;
+                        }
                     }
                 } else {
                     res = false;
@@ -326,16 +864,29 @@ impl TypeChecker {
             }
             Statement::If(ref mut expr, ref mut stmts, ref mut else_opt) => {
                 let expr_type = self.annotate_type(expr);
-                if let &mut Some(ref mut else_stmts) = else_opt {
-                    // TODO: add else checker
-                    self.annotate_types_block(else_stmts) && self.annotate_types_block(stmts) && expr_type.is_some()
-                } else {
-                    self.annotate_types_block(stmts) && expr_type.is_some()
-                }
+                // After the if, a variable may point wherever either branch
+                // left it pointing, and hold what either gave it
+                let before = self.get_flow_state();
+                let (then_res, after_then) = self.annotate_types_branch(stmts,
+                                                                        before.clone());
+                let (else_res, after_else) = match *else_opt {
+                    Some(ref mut else_stmts) => {
+                        self.annotate_types_branch(else_stmts, before)
+                    }
+                    None => (true, before),
+                };
+                self.set_flow_state(merge_flow_states(&after_then, &after_else));
+                then_res && else_res && expr_type.is_some()
             }
             Statement::While(ref mut expr, ref mut stmts) => {
-                let expr_type = self.annotate_type(expr);
-                self.annotate_types_block(stmts) && expr_type.is_some()
+                self.annotate_types_loop(expr, stmts)
+            }
+            Statement::Unsafe(_, ref mut stmts) => {
+                let outer_unsafe = self.in_unsafe;
+                self.in_unsafe = true;
+                let res = self.annotate_types_block(stmts);
+                self.in_unsafe = outer_unsafe;
+                res
             }
             Statement::Let(ref name, ref var_type, ref mut expr_opt) => {
                 let mut res = true;
@@ -348,8 +899,11 @@ impl TypeChecker {
                 if let &mut Some(ref mut expr) = expr_opt {
                     self.annotate_type(expr);
 
-                    if !type_checker_helper::can_assign_expr_to_type(var_type,
-                                                                     expr) {
+                    if is_int_to_pointer(var_type, expr) {
+                        res = self.require_unsafe(
+                            "Converting an int to a pointer");
+                    } else if !type_checker_helper::can_assign_expr_to_type(var_type,
+                                                                            expr) {
                         self.errors_found.push(
                             format!("Cant assign expr {:?} of type ({:?}) \
                                      to var of type {:?}",
@@ -367,6 +921,20 @@ impl TypeChecker {
                         .insert(name.clone());
                     self.variable_to_type.insert(name.clone(),
                                                  var_type.clone());
+                    match *expr_opt {
+                        Some(ref expr) => self.track_arithmetic_pointer(name, expr),
+                        None => {
+                            self.arithmetic_pointers.remove(name);
+                        }
+                    }
+
+                    // A variable can't outlive anything visible at its
+                    // declaration, so we only need to remember the region
+                    let region = expr_opt.as_ref()
+                        .and_then(|expr| self.get_region(expr));
+                    if let Some(region) = region {
+                        self.variable_to_region.insert(name.clone(), region);
+                    }
                 }
 
                 res
@@ -375,13 +943,37 @@ impl TypeChecker {
                 self.annotate_type(left);
                 self.annotate_type(right);
 
-                let res = type_checker_helper::is_assignment_valid(left, right);
+                if let Expression::Variable(ref name) = left.expr {
+                    self.track_arithmetic_pointer(name, right);
+                } else if self.is_from_arithmetic(right) {
+                    // Only local variables are tracked, so it could be read
+                    // back and dereferenced outside unsafe code
+                    self.errors_found.push(format!(
+                        "Cannot store a pointer from pointer arithmetic \
+                         anywhere but a local variable at line {}",
+                        right.line));
+                    return false;
+                }
 
-                if !res {
+                let int_to_pointer = left.typ.as_ref()
+                    .is_some_and(|t| is_int_to_pointer(t, right)) &&
+                    expression_has_address(left);
+                let res = if int_to_pointer {
+                    self.require_unsafe("Converting an int to a pointer")
+                } else {
+                    type_checker_helper::is_assignment_valid(left, right)
+                };
+
+                if !res && !int_to_pointer {
                     self.errors_found.push(format!("Cannot assign {:?} to {:?}",
                                                    right, left));
+                } else if writes_through_ref(left) {
+                    self.errors_found.push(format!(
+                        "Cannot assign to {:?} through a ref. Use a mut_ref \
+                         instead.", left.expr));
+                    return false;
                 }
-                res
+                res && self.check_borrow_escape(left, right)
             }
             Statement::Call(ref mut call) => {
                 self.check_function_call(call).is_some()
@@ -392,15 +984,26 @@ impl TypeChecker {
     fn annotate_types_block(&mut self, block: &mut Block) -> bool {
         self.blocks.push(CodeBlock::new());
         let mut res = true;
+        let mut returned = false;
         for stmt in block.statements.iter_mut() {
+            if returned {
+                self.warnings_found.push(format!(
+                    "In {}: unreachable code after return at line {}",
+                    self.current_fn, get_statement_line(stmt)));
+                returned = false;
+            }
+
             if !self.annotate_types_stmt(stmt) {
                 res = false;
             }
+            returned = returned || statement_always_returns(stmt);
         }
 
         let b = self.blocks.pop().unwrap();
         for variable in b.declared_variables {
             self.variable_to_type.remove(&variable);
+            self.variable_to_region.remove(&variable);
+            self.arithmetic_pointers.remove(&variable);
         }
 
         res
@@ -422,6 +1025,20 @@ impl TypeChecker {
             self.add_structs(&program.structs);
         }
 
+        // Add all the functions to our table of functions before checking
+        // any bodies, so that functions can call ones defined after them
+        // (this includes the __drop functions the owned_pointer
+        // transformer generates)
+        for fun in program.functions.iter() {
+            if self.function_to_type.get(&fun.name).is_some_and(|t| t.is_internal) {
+                self.errors_found.push(format!("{} is used by the compiler, so \
+                                                it can't be defined", fun.name));
+                return false;
+            }
+            self.function_to_type.insert(fun.name.clone(),
+                                         fun.fn_type.clone());
+        }
+
         let mut res = true;
         for fun in program.functions.iter_mut() {
             // For now we cannot pass or return structs from functions.
@@ -436,24 +1053,32 @@ impl TypeChecker {
                 return false;
             }
 
-            // Add the current function to our table of functions
-            // (note we do this before checking the function body)
-            self.function_to_type.insert(fun.name.clone(),
-                                         fun.fn_type.clone());
             self.current_fn = fun.name.clone();
             for i in 0..fun.args.len() {
                 self.variable_to_type.insert(fun.args.get(i).unwrap().clone(),
                                              fun.fn_type.arg_types.get(i).unwrap().clone());
             }
 
+            // The whole body of an unsafe function is unsafe code
+            self.in_unsafe = fun.fn_type.is_unsafe;
             if !self.annotate_types_block(&mut fun.statements) {
                 // Don't return here, because we should type check the other
                 // functions too.
                 res = false;
             }
 
+            // Only main gets a return added at the end, any other function
+            // would run into the code after it
+            if fun.name != "main" && !block_always_returns(&fun.statements) {
+                self.errors_found.push(format!(
+                    "Function {} doesn't return on every path", fun.name));
+                res = false;
+            }
+
             for arg in &fun.args {
                 self.variable_to_type.remove(arg);
+                self.variable_to_region.remove(arg);
+                self.arithmetic_pointers.remove(arg);
             }
             assert!(self.variable_to_type.is_empty());
         }
@@ -461,7 +1086,99 @@ impl TypeChecker {
         res
     }
 
+    pub fn get_function_types(&self) -> &HashMap<String, ast::FunctionType> {
+        &self.function_to_type
+    }
+
     pub fn get_errors(&self) -> &Vec<String> {
         &self.errors_found
     }
+
+    pub fn get_warnings(&self) -> &Vec<String> {
+        &self.warnings_found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parser::parse;
+    use scanner::get_tokens;
+
+    // Names starting with _ can't be written in the source, so the calls to
+    // release are renamed to the internal function
+    fn parse_calling_release() -> Program {
+        let source = "int main (int arg)
+{
+    int x = 5;
+    release(&x);
+    return 0;
+}
+";
+        let mut prog = parse(&mut get_tokens(source));
+        for stmt in prog.functions[0].statements.statements.iter_mut() {
+            if let Statement::Call(ref mut fn_call) = *stmt {
+                fn_call.name = "__shared_release".to_string();
+            }
+        }
+        prog
+    }
+
+    #[test]
+    fn user_code_cannot_call_internal_functions() {
+        let mut prog = parse_calling_release();
+        let mut type_checker = TypeChecker::new();
+        assert!(!type_checker.annotate_types(&mut prog));
+        assert_eq!(type_checker.get_errors(),
+                   &vec!["__shared_release can only be called by code the \
+                          compiler adds".to_string()]);
+    }
+
+    #[test]
+    fn calls_the_compiler_adds_take_any_pointer() {
+        let mut prog = parse_calling_release();
+        let mut type_checker = TypeChecker::new();
+        type_checker.set_allow_internal_calls(true);
+        assert!(type_checker.annotate_types(&mut prog));
+    }
+
+    #[test]
+    fn internal_functions_cannot_be_defined() {
+        let source = "int retain (int count)
+{
+    return count;
+}
+
+int main (int arg)
+{
+    return 0;
+}
+";
+        let mut prog = parse(&mut get_tokens(source));
+        prog.functions[0].name = "__shared_retain".to_string();
+        let mut type_checker = TypeChecker::new();
+        assert!(!type_checker.annotate_types(&mut prog));
+        assert_eq!(type_checker.get_errors(),
+                   &vec!["__shared_retain is used by the compiler, so it \
+                          can't be defined".to_string()]);
+    }
+
+    #[test]
+    fn unreachable_code_is_reported_at_its_line() {
+        let source = "int main (int arg)
+{
+    int x = 5;
+    return x;
+    print x;
+    return 0;
+}
+";
+        let mut prog = parse(&mut get_tokens(source));
+        let mut type_checker = TypeChecker::new();
+        assert!(type_checker.annotate_types(&mut prog));
+        assert_eq!(type_checker.get_warnings(),
+                   &vec!["In main: unreachable code after return at line 5"
+                         .to_string()]);
+    }
 }
@@ -1,4 +1,6 @@
+use ast::ArrayType;
 use ast::BinaryOp;
+use ast::PointerType;
 use ast::VarType;
 use ast::VarType::*;
 
@@ -33,6 +35,57 @@ pub fn is_pointer_arithmetic(l: &VarType,
     return false;
 }
 
+pub fn is_shared_pointer(typ: &VarType) -> bool {
+    matches!(*typ, Pointer(PointerType::Shared, _))
+}
+
+pub fn is_array(typ: &VarType) -> bool {
+    matches!(*typ, Array(_, _))
+}
+
+// Return true if values of this type may hold the address of a local,
+// so we have to make sure they don't outlive it
+pub fn can_hold_address(typ: &VarType) -> bool {
+    is_pointer(typ) || matches!(*typ, Array(ArrayType::View, _))
+}
+
+pub fn is_reference(typ: &VarType) -> bool {
+    matches!(*typ, Pointer(PointerType::Ref, _) |
+             Pointer(PointerType::MutRef, _))
+}
+
+// Return true if right can be used as a ref/mut_ref of type left_t.
+// References are made by taking the address of something (&x), and a
+// mut_ref can be used wherever a ref can.
+pub fn can_borrow(left_t: &VarType, right: &AstExpressionNode) -> bool {
+    let (left_kind, left_target) = match *left_t {
+        Pointer(kind, ref target) if is_reference(left_t) => (kind, target),
+        _ => return false,
+    };
+
+    match (&right.expr, right.typ.as_ref()) {
+        (&Expression::Reference(_), Some(&Pointer(PointerType::Raw, ref t))) => {
+            t == left_target
+        }
+        (_, Some(&Pointer(PointerType::MutRef, ref t))) => {
+            left_kind == PointerType::Ref && t == left_target
+        }
+        _ => false,
+    }
+}
+
+// Return true if assigning to this expression writes through a ref,
+// which isn't allowed (only mut_refs can be written through)
+pub fn writes_through_ref(expr_node: &AstExpressionNode) -> bool {
+    match expr_node.expr {
+        Expression::Dereference(ref expr) => {
+            matches!(expr.typ, Some(Pointer(PointerType::Ref, _)))
+        }
+        Expression::FieldAccess(ref expr, _) => writes_through_ref(expr),
+        _ => false,
+    }
+}
+
 // Return true if the expression represents something that has an address
 // in memory (is an "lvalue")
 pub fn expression_has_address(expr_node: &AstExpressionNode) -> bool {
@@ -40,6 +93,7 @@ pub fn expression_has_address(expr_node: &AstExpressionNode) -> bool {
         Expression::Variable(_) => true,
         Expression::Dereference(_) => true,
         Expression::FieldAccess(_,_) => true,
+        Expression::Index(_, _) => true,
         _ => false
     }
 }
@@ -57,16 +111,21 @@ pub fn can_assign_expr_to_type(left_t: &VarType,
 
     // if the thing on the right is a call to malloc, we allow it
     if let Expression::Call(ref fn_call) = right.expr {
-        if fn_call.name == "allocate" && is_pointer(left_t) {
+        if (fn_call.name == "allocate" || fn_call.name == "__shared_alloc") &&
+            is_pointer(left_t) {
             return true;
         }
     }
 
+    if can_borrow(left_t, right) {
+        return true;
+    }
+
     if !type_contains(left_t, right.typ.as_ref().unwrap()) {
         // Special case: left is a pointer and right is 0
         // it is okay to assign 0 to a pointer
         let mut res = false;
-        if let &Pointer(_, _) = left_t {
+        if let &Pointer(_, _) | &Array(ArrayType::Owned, _) = left_t {
             if let &Expression::Value(val) = &right.expr {
                 res = val == 0;
             }
@@ -80,6 +139,17 @@ pub fn can_assign_expr_to_type(left_t: &VarType,
     true
 }
 
+/// Return true if this would store a (nonzero) int into a raw pointer,
+/// which can only be done in unsafe code
+pub fn is_int_to_pointer(left_t: &VarType, right: &AstExpressionNode) -> bool {
+    if let Expression::Value(0) = right.expr {
+        return false;
+    }
+
+    matches!(*left_t, Pointer(PointerType::Raw, _)) &&
+        right.typ.as_ref().is_some_and(|t| type_contains(&Int, t))
+}
+
 pub fn is_assignment_valid(left: &AstExpressionNode,
                            right: &AstExpressionNode) -> bool {
     if !left.typ.is_some() || !right.typ.is_some() {
@@ -0,0 +1,41 @@
+use ast::Block;
+use ast::Program;
+use ast::Statement;
+
+// Lists every unsafe function and unsafe block in the program, so that
+// reviewers know which code the compiler can't vouch for.
+
+fn add_unsafe_blocks(block: &Block, fn_name: &str, regions: &mut Vec<String>) {
+    for stmt in block.statements.iter() {
+        match *stmt {
+            Statement::Unsafe(line, ref unsafe_block) => {
+                regions.push(format!("line {}: unsafe block in {}",
+                                     line, fn_name));
+                add_unsafe_blocks(unsafe_block, fn_name, regions);
+            }
+            Statement::If(_, ref then_block, ref else_block_opt) => {
+                add_unsafe_blocks(then_block, fn_name, regions);
+                if let Some(ref else_block) = *else_block_opt {
+                    add_unsafe_blocks(else_block, fn_name, regions);
+                }
+            }
+            Statement::While(_, ref while_block) => {
+                add_unsafe_blocks(while_block, fn_name, regions);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Return a description of each unsafe region, in the order they appear
+pub fn get_unsafe_regions(program: &Program) -> Vec<String> {
+    let mut regions = Vec::new();
+    for fun in program.functions.iter() {
+        if let Some(line) = fun.unsafe_line {
+            regions.push(format!("line {}: unsafe function {}", line, fun.name));
+        }
+        add_unsafe_blocks(&fun.statements, &fun.name, &mut regions);
+    }
+
+    regions
+}
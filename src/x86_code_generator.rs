@@ -1,12 +1,12 @@
-use ast;
-use ast::Statement;
-use ast::Expression;
-use ast::AstExpressionNode;
-use ast::BinaryOp;
-use ast::Function;
-use ast::FunctionCall;
-use ast::VarType;
-use ast::Program;
+use ir::BlockId;
+use ir::Instr;
+use ir::IrBinOp;
+use ir::IrFunction;
+use ir::IrProgram;
+use ir::IrType;
+use ir::Terminator;
+use ir::TrapKind;
+use ir::Value;
 
 use assembly::Instruction;
 use assembly::Instruction::*;
@@ -20,591 +20,542 @@ use assembly_printer::instruction_list_to_asm;
 
 use code_generator::GeneratesCode;
 
-use code_block::CodeBlock;
+use peephole::optimize;
 
-use std::collections::HashMap;
+use register_allocator::allocate_registers;
+use register_allocator::ALLOCATABLE_REGISTERS;
+use register_allocator::CALLEE_SAVED_REGISTERS;
 
 use assembly_helper::alloc_stack;
 use assembly_helper::free_stack;
-use assembly_helper::get_mtype_size;
-use assembly_helper::register_other_than;
 use assembly_helper::move_type;
 use assembly_helper::WORD_SIZE;
 
-use representation_manager::RepresentationManager;
-
+// What ESP has to be a multiple of at every call. The System V ABI asks
+// for this, and libc functions that use SSE instructions crash without it.
+const STACK_ALIGNMENT: i32 = 16;
+
+// Code that runtime checks jump to. It prints the message with the
+// function and line of the check, and exits with the exit code. The exit
+// handlers aren't run, so the memory sanitizer doesn't go on to report
+// the memory the program didn't get to free.
+struct RuntimeTrap {
+    label: &'static str,
+    message: &'static str,
+    exit_code: i32,
+}
 
-#[derive(Clone)]
-struct LocalVariable {
-    stack_offset: i32,
-    // We need thisv for pointer dereferencing (need to know size of the thing
-    // to dereference)
-    var_type: VarType,
+const OVERFLOW_TRAP: RuntimeTrap = RuntimeTrap {
+    label: "__overflow_trap",
+    message: "Integer overflow",
+    exit_code: 70,
+};
+
+const DIVISION_BY_ZERO_TRAP: RuntimeTrap = RuntimeTrap {
+    label: "__division_by_zero_trap",
+    message: "Division by zero",
+    exit_code: 71,
+};
+
+// 72 is used by the memory sanitizer
+const BOUNDS_ERROR_TRAP: RuntimeTrap = RuntimeTrap {
+    label: "__bounds_error_trap",
+    message: "Array index out of bounds",
+    exit_code: 73,
+};
+
+fn is_memory(op: &Operand) -> bool {
+    matches!(*op, Dereference(_, _))
+}
 
-    // Used mainly for when we have to copy things and
-    // knowing which asm instruction to use
-    machine_type: MachineType,
+fn get_machine_type(typ: IrType) -> MachineType {
+    match typ {
+        IrType::I8 => MachineType::Byte,
+        IrType::I32 | IrType::Ptr => MachineType::Long,
+    }
 }
 
-impl LocalVariable {
-    pub fn new(off: i32, var_type: VarType, machine_type: MachineType) -> LocalVariable {
-        LocalVariable {
-            stack_offset: off,
-            var_type: var_type,
-            machine_type: machine_type,
-        }
+// The instruction that sets AL to the result of a comparison, after
+// cmp right, left
+fn get_set_instruction(op: IrBinOp) -> &'static str {
+    match op {
+        IrBinOp::Eq => "sete %al",
+        IrBinOp::Ne => "setne %al",
+        IrBinOp::Lt => "setl %al",
+        IrBinOp::Le => "setle %al",
+        IrBinOp::Gt => "setg %al",
+        IrBinOp::Ge => "setge %al",
+        IrBinOp::Below => "setb %al",
+        IrBinOp::BelowOrEqual => "setbe %al",
+        _ => panic!("{} is not a comparison", op),
     }
 }
 
 pub struct X86CodeGenerator {
     label_num: i32,
-
-    // keep track of where in memory variables are stored
-    identifier_to_var: HashMap<String, LocalVariable>,
-    blocks: Vec<CodeBlock>,
-    current_stack_offset: i32,
     current_function: String,
 
-    // string
-    string_to_label: HashMap<String, String>,
-    current_label_num: i32,
-
     instructions: Vec<Instruction>,
 
-    representation_mgr: RepresentationManager,
-}
+    // Where every virtual register and frame slot of the current function
+    // is, as an offset from EBP. Virtual registers kept in a register have
+    // it in vreg_registers instead.
+    vreg_offsets: Vec<i32>,
+    vreg_registers: Vec<Option<RegisterVal>>,
+    slot_offsets: Vec<i32>,
 
+    // The callee saved registers the current function uses, and where in
+    // the frame their value is kept until it returns
+    saved_registers: Vec<(RegisterVal, i32)>,
 
+    // The function and line of each runtime check, and the trap it jumps
+    // to, so the trap can say where the error happened
+    error_sites: Vec<(String, usize, &'static RuntimeTrap)>,
+
+    // Whether to keep virtual registers in registers, and run the peephole
+    // optimizer over each function (-O1)
+    optimize: bool,
+}
 
 impl X86CodeGenerator {
     pub fn new() -> X86CodeGenerator {
         X86CodeGenerator {
             label_num: 0,
-            identifier_to_var: HashMap::new(),
-            blocks: Vec::new(),
             current_function: String::new(),
 
-            current_stack_offset: 0,
-            string_to_label: HashMap::new(),
-            current_label_num: 0,
-
             instructions: Vec::new(),
 
-            representation_mgr: RepresentationManager::new(),
-        }
-    }
+            vreg_offsets: Vec::new(),
+            vreg_registers: Vec::new(),
+            slot_offsets: Vec::new(),
+            saved_registers: Vec::new(),
 
-    // Move to a register. Doesn't matter which one.
-    fn move_op_to_register(&mut self, op: Operand) -> RegisterVal {
-        if let Register(reg) = op {
-            reg
-        } else {
-            // TODO: some clever register allocation someday
-            self.instructions.push(Move(op, Register(EAX)));
-            EAX
+            error_sites: Vec::new(),
+
+            optimize: false,
         }
     }
 
-    fn move_var_to_register(&mut self,
-                            varname: &str, reg: Operand) {
-        let var = self.identifier_to_var
-            .get(varname)
-            .expect(&format!("Unkown variable {}", varname));
-
-        let from_op = Dereference(EBP, var.stack_offset);
-        let instr = move_type(from_op, reg, var.machine_type);
-        self.instructions.push(instr);
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
     }
 
-    fn move_value_to_var(&mut self, reg: Operand,
-                         varname: &str) {
-        let var = self.identifier_to_var
-            .get(varname)
-            .expect(&format!("Unkown variable {}", varname));
-
-        let to_operand = Dereference(EBP, var.stack_offset);
-        let instr = move_type(reg, to_operand, var.machine_type);
-        self.instructions.push(instr);
+    fn get_block_label(&self, block: BlockId) -> String {
+        format!(".L{}.{}", self.current_function, block)
     }
 
-    // Return a (register, offset) to write to assign to this expression
-    // Assumes the expression is "assignable," which the type checker
-    // checks.
-    // Example:
-    // x = y --> return address of x (register=EBP, off=offset)
-    // *x = y --> move x into a register, and return that
-    fn load_address_of_expr(&mut self,
-                            expr: &AstExpressionNode) -> (RegisterVal, i32) {
-        match expr.expr {
-            Expression::Variable(ref name) => {
-                let var = self.identifier_to_var.get(name).unwrap();
-                (EBP, var.stack_offset)
-            }
-            Expression::Dereference(ref expr) => {
-                let expr_op = self.evaluate_expression(expr);
+    // Every slot, and every virtual register that doesn't get a register,
+    // gets its own place in the frame, as do the callee saved registers the
+    // function uses. Arguments are already on the stack, above the return
+    // address and saved EBP. Returns the size of the frame, which is padded
+    // so ESP is aligned after the prologue.
+    fn lay_out_frame(&mut self, fun: &IrFunction) -> i32 {
+        let available: &[RegisterVal] = if self.optimize {
+            &ALLOCATABLE_REGISTERS
+        } else {
+            &[]
+        };
+        self.vreg_registers = allocate_registers(fun, available);
+        self.vreg_offsets = vec![0; fun.vreg_types.len()];
+        self.slot_offsets = vec![0; fun.slots.len()];
+        self.saved_registers = Vec::new();
 
-                let reg = self.move_op_to_register(expr_op);
-                (reg, 0)
+        for (i, &param) in fun.params.iter().enumerate() {
+            self.vreg_offsets[param] = WORD_SIZE * (2 + i as i32);
+        }
+
+        let mut frame_size = 0;
+        for (i, &size) in fun.slots.iter().enumerate() {
+            // Keep everything word aligned
+            frame_size += (size + WORD_SIZE - 1) / WORD_SIZE * WORD_SIZE;
+            self.slot_offsets[i] = -frame_size;
+        }
+        for &reg in CALLEE_SAVED_REGISTERS.iter() {
+            if self.vreg_registers.contains(&Some(reg)) {
+                frame_size += WORD_SIZE;
+                self.saved_registers.push((reg, -frame_size));
             }
-            Expression::FieldAccess(ref expr, ref field_name) => {
-                let (addr_reg,
-                     struct_off) = self.load_address_of_expr(expr);
-
-                // field_off is the offset of where the field we want is
-                let field_info = self.representation_mgr
-                    .get_field_info(expr.typ.as_ref().unwrap(),
-                                    field_name);
-                // Address of the field is at the offset of the struct,
-                // plus the offset of the field within the struct
-                let total_offset = struct_off + field_info.offset;
-                (addr_reg, total_offset)
+        }
+        for reg in 0..fun.vreg_types.len() {
+            if !fun.params.contains(&reg) && self.vreg_registers[reg].is_none() {
+                frame_size += WORD_SIZE;
+                self.vreg_offsets[reg] = -frame_size;
             }
-            _ => panic!("Cannot assign to this type of expr"),
         }
-    }
 
-    fn evaluate_block(&mut self, block: &ast::Block) {
-        self.blocks.push(CodeBlock::new());
-        for stmt in block.statements.iter() {
-            self.evaluate_statement(stmt);
+        // ESP was aligned before the call pushed the return address, and
+        // the prologue pushed EBP
+        let pushed = 2 * WORD_SIZE;
+        let unaligned = (frame_size + pushed) % STACK_ALIGNMENT;
+        if unaligned != 0 {
+            frame_size += STACK_ALIGNMENT - unaligned;
         }
+        frame_size
+    }
 
-        // Wipe out all of the variables we declared in this block, as
-        // we shouldn't able to use them again
-        let block_opt = self.blocks.pop();
-        if block_opt.is_none() {
-            panic!("Invalid state. Why is there no current block?");
-        }
-        let block = block_opt.unwrap();
-
-        // Pretty reasonable assumption
-        assert!(block.declared_variables.len() < i32::max_value() as usize);
-        let previous_offset = self.current_stack_offset;
-        for variable in block.declared_variables {
-            let var = self.identifier_to_var
-                .remove(&variable)
-                .unwrap();
-            self.current_stack_offset += get_mtype_size(var.machine_type);
+    fn get_vreg_operand(&self, reg: usize) -> Operand {
+        match self.vreg_registers[reg] {
+            Some(r) => Register(r),
+            None => Dereference(EBP, self.vreg_offsets[reg]),
         }
+    }
 
-        self.instructions.push(free_stack(self.current_stack_offset -
-                                          previous_offset));
+    fn get_operand(&self, value: &Value) -> Operand {
+        match *value {
+            Value::Reg(reg) => self.get_vreg_operand(reg),
+            Value::Const(c) => IntConstant(c),
+            Value::Global(ref label) => Variable(label.clone()),
+        }
     }
 
-    // Generate code to evaluate an expression and return the operand where
-    // the result is stored
-    fn evaluate_expression(&mut self,
-                           expr_node: &AstExpressionNode) -> Operand {
-        let expr = &expr_node.expr;
-        match *expr {
-            Expression::Call(ref fn_call) => {
-                self.evaluate_function_call(fn_call);
-                Register(EAX)
-            }
-            Expression::Value(ref v) => {
-                // FIXME: We should probably use more than just the register
-                // EAX...
-                self.instructions.push(Move(IntConstant(*v), Register(EAX)));
-                Register(EAX)
-            }
-            Expression::StringValue(ref v) => {
-                if self.string_to_label.contains_key(v) {
-                    let label = self.string_to_label.get(v).unwrap();
-                    self.instructions.push(Move(Variable(label.clone()),
-                                                Register(EAX)));
-                } else {
-                    let label = format!(".LC{}", self.current_label_num);
-                    self.current_label_num += 1;
-                    self.string_to_label.insert(v.clone(), label.clone());
-                    self.instructions.push(Move(Variable(label.clone()),
-                                                Register(EAX)));
-                }
-                Register(EAX)
+    // A register holding the address, which is ECX if it isn't in one
+    // already
+    fn get_address_register(&mut self, addr: &Value) -> RegisterVal {
+        match self.get_operand(addr) {
+            Register(r) => r,
+            _ => {
+                self.move_to_register(addr, ECX);
+                ECX
             }
-            Expression::SizeOf(ref typ) => {
-                let mtype = self.representation_mgr.get_machine_type(typ);
-                let sz = get_mtype_size(mtype);
+        }
+    }
 
-                self.instructions.push(Move(IntConstant(sz), Register(EAX)));
-                Register(EAX)
-            }
-            Expression::BinaryOp(ref op, ref l, ref r) => {
-                self.evaluate_binary_op(op, l, r)
-            }
-            Expression::Variable(ref name) => {
-                self.move_var_to_register(name, Register(EAX));
-                Register(EAX)
-            }
-            Expression::Reference(ref expr) => {
-                let (reg, offset) = self.load_address_of_expr(expr);
-                self.instructions.push(OtherTwoArg("leal",
-                                                   Dereference(reg, offset),
-                                                   Register(EAX)));
+    fn move_to_register(&mut self, value: &Value, reg: RegisterVal) {
+        let op = self.get_operand(value);
+        self.instructions.push(Move(op, Register(reg)));
+    }
 
-                Register(EAX)
-            }
-            Expression::Dereference(ref expr) => {
-                let addr_op = self.evaluate_expression(expr);
-                let addr_reg = self.move_op_to_register(addr_op);
-
-                let typ = expr.typ
-                    .as_ref()
-                    .expect("Expressions should all have types now!");
-                let instr = if let VarType::Pointer(_, ref t) = *typ {
-                    move_type(Dereference(addr_reg, 0),
-                              Register(EAX),
-                              self.representation_mgr.get_machine_type(t))
-                } else {
-                    panic!("Cannot dereference non pointer")
-                };
+    fn move_from_register(&mut self, reg: RegisterVal, dest: usize) {
+        let dest = self.get_vreg_operand(dest);
+        self.instructions.push(Move(Register(reg), dest));
+    }
 
-                // The address of the thing we want to dereference is in EAX
+    // A label for a runtime check on the given line to jump to when it
+    // fails
+    fn get_error_site_label(&mut self, line: usize,
+                            trap: &'static RuntimeTrap) -> String {
+        let label = format!("__error_site_{}", self.error_sites.len());
+        self.error_sites.push((self.current_function.clone(), line, trap));
+        label
+    }
 
-                self.instructions.push(instr);
-                Register(EAX)
-            }
-            Expression::FieldAccess(ref struct_expr, ref field_name) => {
-                // Load the address of this whole expression
-                let (register, offset) = self.load_address_of_expr(expr_node);
-                let result_reg = Register(EAX);
-                let field_info = self.representation_mgr
-                    .get_field_info(struct_expr.typ.as_ref().unwrap(),
-                                    field_name);
-
-                let instr = move_type(Dereference(register, offset),
-                                      result_reg.clone(),
-                                      field_info.machine_type);
-                self.instructions.push(instr);
-                result_reg
-            }
+    // The code the runtime checks jump to. Each one pushes the arguments
+    // for the error message, then jumps to the trap that prints it. ESP is
+    // aligned wherever a check can fail, so each call is padded to keep
+    // it that way.
+    fn get_runtime_trap_code(&self) -> String {
+        let mut code = ".section .text\n".to_string();
+        for (i, &(_, line, trap)) in self.error_sites.iter().enumerate() {
+            code.push_str(&format!("__error_site_{}:\n\
+                                    subl $4, %esp\n\
+                                    pushl ${}\n\
+                                    pushl $__error_fn_{}\n\
+                                    jmp {}\n",
+                                   i, line, i, trap.label));
         }
-    }
 
-    fn evaluate_return_statement(&mut self, value: &AstExpressionNode) {
-        let out_reg = self.evaluate_expression(value);
-        let instr = &mut self.instructions;
-        // For now everything goes into eax
-        if out_reg != Register(EAX) {
-            instr.push(Move(out_reg, Register(EAX)));
+        let used_traps: Vec<&RuntimeTrap> = [&OVERFLOW_TRAP, &DIVISION_BY_ZERO_TRAP,
+                                             &BOUNDS_ERROR_TRAP]
+            .iter()
+            .filter(|trap| self.error_sites.iter()
+                    .any(|site| site.2.label == trap.label))
+            .cloned()
+            .collect();
+        for trap in used_traps.iter() {
+            code.push_str(&format!("{}:\n\
+                                    pushl ${}_str\n\
+                                    call printf\n\
+                                    subl $12, %esp\n\
+                                    pushl $0\n\
+                                    call fflush\n\
+                                    subl $12, %esp\n\
+                                    pushl ${}\n\
+                                    call _exit\n",
+                                   trap.label, trap.label, trap.exit_code));
         }
 
-        instr.push(Move(Register(EBP), Register(ESP)));
-        instr.push(Pop(Register(EBP)));
-        // if self.current_function == "_start" {
-        //     instr.push(Move(Register(EAX), Register(EBX)));
-        //     instr.push(Move(IntConstant(1), Register(EAX)));
-        //     instr.push(Instruction::OtherStatic("int $0x80"));
-        // }
-        // else {
-        //instr.push(Instruction::OtherStatic("ret"));
-        //}
-        instr.push(Instruction::OtherStatic("ret"));
+        code.push_str(".section .data\n");
+        for trap in used_traps.iter() {
+            code.push_str(&format!("{}_str: .asciz \"{} in %s at line %d\\n\"\n",
+                                   trap.label, trap.message));
+        }
+        for (i, (fn_name, _, _)) in self.error_sites.iter().enumerate() {
+            code.push_str(&format!("__error_fn_{}: .asciz \"{}\"\n",
+                                   i, fn_name));
+        }
+        code
     }
 
-    fn evaluate_statement(&mut self,
-                          tree: &Statement) {
-        match *tree {
-            Statement::Return(ref v) => {
-                self.evaluate_return_statement(v);
-            }
-            Statement::Print(ref expr) => {
-                self.instructions.push(Comment("Evaluating print statement"
-                                               .to_string()));
-                let result_reg = self.evaluate_expression(&expr);
-
-                let instr = &mut self.instructions;
-                instr.push(Push(result_reg));
-                instr.push(Push(VariableStatic("decimal_format_str")));
-                instr.push(Instruction::Other("call printf".to_string()));
-                // pop args off the stack
-                instr.push(free_stack(WORD_SIZE*2));
-
-                // Call fflush(0)
-                instr.push(Push(IntConstant(0)));
-                instr.push(Instruction::Other("call fflush".to_string()));
-                instr.push(free_stack(WORD_SIZE));
-            }
-            Statement::If(ref expr, ref then_block, ref else_block_opt) => {
-                let reg = self.evaluate_expression(&expr);
-
-                let label = format!("L{}", self.label_num);
-                self.label_num += 1;
-                self.instructions.push(Compare(IntConstant(0), reg));
-                // Jump PAST the "then statement" if the expression is false
-                self.instructions.push(JumpIfEqual(label.to_string()));
-
-                self.instructions.push(Comment("The start of if block".to_string()));
-                self.evaluate_block(then_block);
-
-                if else_block_opt.is_some() {
-                    self.instructions.push(Comment("The end of if block, skipping the else block".to_string()));
-                    let label = format!("L{}", self.label_num);
-                    self.instructions.push(Jump(label.to_string()));
-                }
-                // print the label to jump to if the expr is false
-                self.instructions.push(Instruction::Label(label.to_string()));
+    // Compute left op right into EAX
+    // Compute left op right into the result register. Only + - * can be
+    // computed into a register other than EAX.
+    fn generate_binary_op(&mut self, op: IrBinOp, left: &Value, right: &Value,
+                          overflow_check: Option<usize>, result: RegisterVal) {
+        self.move_to_register(left, result);
+
+        // Division needs the divisor in a register, everything else can
+        // take it from anywhere
+        let right = if op == IrBinOp::Div {
+            self.move_to_register(right, ECX);
+            Register(ECX)
+        } else {
+            self.get_operand(right)
+        };
+
+        let overflow_label = match overflow_check {
+            Some(line) => self.get_error_site_label(line, &OVERFLOW_TRAP),
+            None => String::new(),
+        };
 
-                if let &Some(ref else_statements) = else_block_opt {
-                    self.instructions.push(Comment("The start of else block".to_string()));
-                    self.evaluate_block(else_statements);
-                    let label = format!("L{}", self.label_num);
+        let instr = &mut self.instructions;
+        match op {
+            IrBinOp::Add => instr.push(Add(right, Register(result))),
+            IrBinOp::Sub => instr.push(Subtract(right, Register(result))),
+            IrBinOp::Mul => instr.push(Multiply(right, Register(result))),
+            IrBinOp::Div => {
+                if overflow_check.is_some() {
+                    // INT_MIN / -1 is the one division that overflows
+                    let division_ok_label = format!("L{}", self.label_num);
                     self.label_num += 1;
-                    self.instructions.push(Instruction::Label(label.to_string()));
+                    instr.push(Compare(IntConstant(-1), Register(ECX)));
+                    instr.push(JumpIfNotEqual(division_ok_label.clone()));
+                    instr.push(Compare(IntConstant(i32::MIN), Register(EAX)));
+                    instr.push(JumpIfEqual(overflow_label.clone()));
+                    instr.push(Label(division_ok_label));
                 }
+                instr.push(Other("cltd".to_string()));
+                instr.push(Divide(Register(ECX)));
             }
-            Statement::While(ref expr, ref block) => {
-                let label1 = format!("L{}", self.label_num);
-                let label2 = format!("L{}", self.label_num+1);
-                self.label_num += 2;
-
-                self.instructions.push(Jump(label2.to_string()));
-                self.instructions.push(Label(label1.to_string()));
-                self.evaluate_block(block);
-
-                self.instructions.push(Label(label2.to_string()));
-                let reg = self.evaluate_expression(&expr);
-                self.instructions.push(Compare(IntConstant(0), reg));
-                self.instructions.push(JumpIfNotEqual(label1.to_string()));
+            _ => {
+                instr.push(Compare(right, Register(EAX)));
+                instr.push(OtherStatic(get_set_instruction(op)));
+                instr.push(OtherStatic("movzbl %al, %eax"));
             }
-            Statement::Let(ref name, ref var_type, ref expr_opt) => {
-                self.instructions.push(Comment(
-                    format!("variable declaration{}",
-                            name)));
-
-                if self.identifier_to_var.contains_key(name) {
-                    panic!("Variable {} already declared", *name);
-                }
-
-                let machine_type = self.representation_mgr.
-                    get_machine_type(var_type);
+        }
 
-                let var_size = get_mtype_size(machine_type);
-                self.current_stack_offset -= var_size;
+        if overflow_check.is_some() && op != IrBinOp::Div {
+            instr.push(Other(format!("jo {}", overflow_label)));
+        }
+    }
 
-                self.identifier_to_var.insert(name.clone(),
-                                              LocalVariable::new(
-                                                  self.current_stack_offset,
-                                                  var_type.clone(),
-                                                  machine_type));
-                {
-                    let mut current_block = self.blocks.last_mut().unwrap();
-                    current_block.declared_variables.insert(name.clone());
+    fn generate_instr(&mut self, instr: &Instr) {
+        match *instr {
+            Instr::Copy { dest, ref src } => {
+                let src = self.get_operand(src);
+                let dest = self.get_vreg_operand(dest);
+                if is_memory(&src) && is_memory(&dest) {
+                    self.instructions.push(Move(src, Register(EAX)));
+                    self.instructions.push(Move(Register(EAX), dest));
+                } else {
+                    self.instructions.push(Move(src, dest));
                 }
-
-                // TODO: Allocate all stack space in advance
-                self.instructions.push(alloc_stack(var_size));
-                if let &Some(ref expr) = expr_opt {
-                    let reg = self.evaluate_expression(expr);
-                    self.move_value_to_var(reg, name);
+            }
+            Instr::Trunc { dest, ref src } => {
+                self.move_to_register(src, EAX);
+                self.instructions.push(OtherStatic("movzbl %al, %eax"));
+                self.move_from_register(EAX, dest);
+            }
+            Instr::BinOp { dest, op, ref left, ref right, overflow_check } => {
+                // + - * can be done right in the destination, if it's a
+                // register the right operand isn't in
+                let is_simple = op == IrBinOp::Add || op == IrBinOp::Sub ||
+                    op == IrBinOp::Mul;
+                let result = match self.get_vreg_operand(dest) {
+                    Register(r) if is_simple &&
+                        self.get_operand(right) != Register(r) => r,
+                    _ => EAX,
+                };
+                self.generate_binary_op(op, left, right, overflow_check, result);
+                if result == EAX {
+                    self.move_from_register(EAX, dest);
                 }
             }
-            Statement::Assign(ref left_expr, ref right_expr) => {
-                // Figure out where we're going to store this
-                let (mut addr_reg, off) = self.load_address_of_expr(left_expr);
-
-                if addr_reg != EBP {
-                    self.instructions.push(Push(Register(addr_reg)));
+            Instr::Load { dest, typ, ref addr, offset } => {
+                let base = self.get_address_register(addr);
+                let result = match self.get_vreg_operand(dest) {
+                    Register(r) => r,
+                    _ => EAX,
+                };
+                self.instructions.push(move_type(Dereference(base, offset),
+                                                 Register(result),
+                                                 get_machine_type(typ)));
+                if result == EAX {
+                    self.move_from_register(EAX, dest);
                 }
-
-                // Evaluate the right hand expression. This means
-                // addr_reg now contains junk if its not EBP
-                let value_op = self.evaluate_expression(right_expr);
-
-                // Put the address we may have saved back into a register
-                // (We can put it in any register besides the register
-                // storing the expression's value)
-                if addr_reg != EBP {
-                    if let Register(reg) = value_op {
-                        addr_reg = register_other_than(&reg);
-                    }
-                    self.instructions.push(Pop(Register(addr_reg)));
+            }
+            Instr::Store { typ, ref value, ref addr, offset } => {
+                // Only EAX and ECX have a low byte we use for chars
+                let mut value = self.get_operand(value);
+                if is_memory(&value) || (typ == IrType::I8 &&
+                                         !matches!(value, IntConstant(_))) {
+                    self.instructions.push(Move(value, Register(EAX)));
+                    value = Register(EAX);
                 }
-
-                let l_type = left_expr.typ.as_ref().unwrap();
-                let machine_type = self.representation_mgr
-                    .get_machine_type(l_type);
-                let instr = move_type(value_op,
-                                      Dereference(addr_reg, off),
-                                      machine_type);
-                self.instructions.push(instr);
+                let base = self.get_address_register(addr);
+                self.instructions.push(move_type(value, Dereference(base, offset),
+                                                 get_machine_type(typ)));
+            }
+            Instr::FrameAddr { dest, slot } => {
+                let offset = self.slot_offsets[slot];
+                self.instructions.push(OtherTwoArg("leal",
+                                                   Dereference(EBP, offset),
+                                                   Register(EAX)));
+                self.move_from_register(EAX, dest);
             }
-            Statement::Call(ref fn_call) => {
-                self.evaluate_function_call(fn_call);
+            Instr::Call { ref dests, ref name, ref args, .. } => {
+                // Pad the arguments so ESP is still aligned at the call
+                let args_size = WORD_SIZE * args.len() as i32;
+                let padding = (STACK_ALIGNMENT - args_size % STACK_ALIGNMENT) %
+                    STACK_ALIGNMENT;
+                self.instructions.push(alloc_stack(padding));
+                for arg in args.iter().rev() {
+                    let op = self.get_operand(arg);
+                    self.instructions.push(Push(op));
+                }
+                self.instructions.push(Call(name.clone()));
+                self.instructions.push(free_stack(args_size + padding));
+
+                // Arrays are returned with the address of the first element
+                // in eax and the length in ecx
+                for (&dest, &reg) in dests.iter().zip([EAX, ECX].iter()) {
+                    self.move_from_register(reg, dest);
+                }
             }
         }
     }
 
-    fn evaluate_binary_op(&mut self,
-                          op: &BinaryOp,
-                          l_node: &AstExpressionNode,
-                          r_node: &AstExpressionNode) -> Operand {
-        self.instructions.push(Comment("Evaluating binary operation"
-                                       .to_string()));
-
-        let left_register = self.evaluate_expression(l_node);
-        // Save the value that we computed in case evaluating
-        // the right side overwrites this register
-        self.instructions.push(Push(left_register));
-
-        let right_register = self.evaluate_expression(r_node);
-
-        // For now we use Register(EAX) for everything
-        if right_register != Register(EAX) {
-            self.instructions.push(Move(right_register, Register(EAX)));
+    // Undo the prologue, leaving the return address on top of the stack
+    fn generate_epilogue(&mut self) {
+        for &(reg, offset) in self.saved_registers.iter() {
+            self.instructions.push(Move(Dereference(EBP, offset),
+                                        Register(reg)));
         }
+        self.instructions.push(Move(Register(EBP), Register(ESP)));
+        self.instructions.push(Pop(Register(EBP)));
+    }
 
-        // put the value of the left expression into Register(EBX)
-        self.instructions.push(Pop(Register(EBX)));
-
-        let instr = &mut self.instructions;
-
-        match *op {
-            BinaryOp::Plus => instr.push(Add(Register(EBX), Register(EAX))),
-            BinaryOp::Multiply => instr.push(Multiply(Register(EBX),
-                                                      Register(EAX))),
-            BinaryOp::Minus => {
-                instr.push(Subtract(Register(EAX), Register(EBX)));
-                instr.push(Move(Register(EBX), Register(EAX)));
-            }
-            BinaryOp::Divide => {
-                instr.push(Move(Register(EAX), Register(ECX)));
-                instr.push(Move(Register(EBX), Register(EAX)));
-                instr.push(Other("cltd".to_string()));
-                instr.push(Divide(Register(ECX)));
-            }
-            BinaryOp::CompareEqual => {
-                instr.push(Compare(Register(EAX), Register(EBX)));
-                // FIXME: weird
-                instr.push(Other("sete %al".to_string()));
-                instr.push(Other("movzbl %al, %eax".to_string()));
-            }
-            BinaryOp::CompareGreater => {
-                instr.push(Compare(Register(EAX), Register(EBX)));
-                instr.push(Other("setg %al".to_string()));
-                instr.push(Other("movzbl %al, %eax".to_string()));
+    fn generate_terminator(&mut self, terminator: &Terminator,
+                           next_block: Option<BlockId>) {
+        match *terminator {
+            Terminator::Jump(block) => {
+                if next_block != Some(block) {
+                    let label = self.get_block_label(block);
+                    self.instructions.push(Jump(label));
+                }
             }
-            BinaryOp::CompareLess => {
-                instr.push(Compare(Register(EAX), Register(EBX)));
-                instr.push(Other("setl %al".to_string()));
-                instr.push(Other("movzbl %al, %eax".to_string()));
+            Terminator::Branch { ref cond, then_block, else_block } => {
+                self.move_to_register(cond, EAX);
+                self.instructions.push(Compare(IntConstant(0), Register(EAX)));
+
+                let then_label = self.get_block_label(then_block);
+                let else_label = self.get_block_label(else_block);
+                if next_block == Some(then_block) {
+                    self.instructions.push(JumpIfEqual(else_label));
+                } else {
+                    self.instructions.push(JumpIfNotEqual(then_label));
+                    if next_block != Some(else_block) {
+                        self.instructions.push(Jump(else_label));
+                    }
+                }
             }
-            BinaryOp::CompareGreaterOrEqual => {
-                instr.push(Compare(Register(EAX), Register(EBX)));
-                instr.push(Other("setge %al".to_string()));
-                instr.push(Other("movzbl %al, %eax".to_string()));
+            Terminator::Return(ref values) => {
+                for (value, &reg) in values.iter().zip([EAX, ECX].iter()) {
+                    self.move_to_register(value, reg);
+                }
+                self.generate_epilogue();
+                self.instructions.push(OtherStatic("ret"));
             }
-            BinaryOp::CompareLessOrEqual => {
-                instr.push(Compare(Register(EAX), Register(EBX)));
-                instr.push(Other("setle %al".to_string()));
-                instr.push(Other("movzbl %al, %eax".to_string()));
+            Terminator::TailCall { ref name, ref args, .. } => {
+                // The arguments can read ours, so they're all pushed before
+                // any of ours are overwritten
+                for arg in args.iter().rev() {
+                    let op = self.get_operand(arg);
+                    self.instructions.push(Push(op));
+                }
+                for i in 0..args.len() {
+                    let offset = WORD_SIZE * (2 + i as i32);
+                    self.instructions.push(Pop(Dereference(EBP, offset)));
+                }
 
+                // The function gets our return address, so it returns
+                // straight to our caller
+                self.generate_epilogue();
+                self.instructions.push(Jump(name.clone()));
             }
-            BinaryOp::CompareNotEqual => {
-                instr.push(Compare(Register(EAX), Register(EBX)));
-                instr.push(Other("setne %al".to_string()));
-                instr.push(Other("movzbl %al, %eax".to_string()));
+            Terminator::Trap(TrapKind::BoundsError, line) => {
+                let label = self.get_error_site_label(line, &BOUNDS_ERROR_TRAP);
+                self.instructions.push(Jump(label));
             }
-
-        }
-        Register(EAX)
-    }
-
-    fn evaluate_function_call(&mut self, fn_call: &FunctionCall) {
-        for arg_expr in fn_call.args_exprs.iter().rev() {
-            let reg = self.evaluate_expression(arg_expr);
-            self.instructions.push(Push(reg));
-        }
-
-        let fn_name = match &fn_call.name[..] {
-            "alloc_int" | "alloc_owned_int" => "malloc".to_string(),
-            "allocate" => "malloc".to_string(), // allocate exact number of bytes given
-            "free" => "free".to_string(),
-            "free_int" => "free".to_string(),
-            _ => fn_call.name.clone(),
-        };
-
-        // FIXME: This does not belong here. It should be done at an earlier stage.
-        if &fn_call.name == "alloc_int" || &fn_call.name == "alloc_owned_int" {
-            // Multiply argument by four
-            self.instructions.push(Pop(Register(EAX)));
-            self.instructions.push(Multiply(IntConstant(WORD_SIZE), Register(EAX)));
-            self.instructions.push(Push(Register(EAX)));
+            Terminator::Trap(TrapKind::DivisionByZero, line) => {
+                let label = self.get_error_site_label(line,
+                                                      &DIVISION_BY_ZERO_TRAP);
+                self.instructions.push(Jump(label));
+            }
+            Terminator::Unreachable => {}
         }
-
-        self.instructions.push(Call(fn_name));
-        self.instructions.push(free_stack(WORD_SIZE * fn_call.args_exprs.len() as i32));
     }
 
     /// Generate the assembly for a function
-    fn generate_code_for_function(&mut self, fun: &Function) -> String {
-        assert!(self.identifier_to_var.is_empty());
-        assert!(self.blocks.is_empty());
-
-        // let name = if &fun.name == "main" {
-        //     "_start".to_string()
-        // } else {
-        //     fun.name.clone()
-        // };
-        let name = fun.name.clone();
-
-        self.current_function = name.clone();
-
-        // Add the function's parameters as local variables
-        for i in 0..fun.fn_type.arg_types.len() {
-            let arg_type = fun.fn_type.arg_types.get(i).expect("function parameter with no type");
-            let machine_type = self.representation_mgr.get_machine_type(arg_type);
-            let var = LocalVariable::new(WORD_SIZE * (2 + i as i32), arg_type.clone(), machine_type);
-            self.identifier_to_var.insert(fun.args.get(i).unwrap().clone(), var);
-        }
-
-        let mut code = String::new();
+    fn generate_code_for_function(&mut self, fun: &IrFunction) -> String {
+        self.current_function = fun.name.clone();
         self.instructions = Vec::new();
-        {
-            let instr = &mut self.instructions;
-            instr.push(Label(name.clone()));
-            instr.push(Push(Register(EBP)));
-            instr.push(Move(Register(ESP), Register(EBP)));
+
+        let frame_size = self.lay_out_frame(fun);
+        self.instructions.push(Label(fun.name.clone()));
+        self.instructions.push(Push(Register(EBP)));
+        self.instructions.push(Move(Register(ESP), Register(EBP)));
+        self.instructions.push(alloc_stack(frame_size));
+        for &(reg, offset) in self.saved_registers.iter() {
+            self.instructions.push(Move(Register(reg), Dereference(EBP, offset)));
+        }
+        for (i, &param) in fun.params.iter().enumerate() {
+            if let Some(reg) = self.vreg_registers[param] {
+                let offset = WORD_SIZE * (2 + i as i32);
+                self.instructions.push(Move(Dereference(EBP, offset),
+                                            Register(reg)));
+            }
         }
 
-        self.evaluate_block(&fun.statements);
+        for (i, block) in fun.blocks.iter().enumerate() {
+            let label = self.get_block_label(i);
+            self.instructions.push(Label(label));
+            for instr in block.instrs.iter() {
+                self.instructions.push(Comment(instr.to_string()));
+                self.generate_instr(instr);
+            }
 
-        if name == "main" {
-            //If the function is main, then returns 0 at the end
-            let expr = AstExpressionNode::new(Expression::Value(0));
-            let ret_stmt = Statement::Return(expr);
-            self.evaluate_statement(&ret_stmt);
+            let next_block = if i + 1 < fun.blocks.len() { Some(i + 1) } else { None };
+            self.generate_terminator(&block.terminator, next_block);
         }
 
-        // Remove arguments from active identifiers
-        for arg in &fun.args {
-            self.identifier_to_var.remove(arg);
+        let instructions = std::mem::take(&mut self.instructions);
+        if self.optimize {
+            instruction_list_to_asm(&optimize(instructions))
+        } else {
+            instruction_list_to_asm(&instructions)
         }
-
-        code.push_str(&instruction_list_to_asm(&mut self.instructions));
-        code
     }
 }
 
 impl GeneratesCode for X86CodeGenerator {
 
-    fn generate_code(&mut self, prog: &Program) -> String {
-        self.representation_mgr.init(&prog.structs);
-
-        let functions = &prog.functions;
-        let asm_header = ".section .data\n\
-                          decimal_format_str: .asciz \"%d\\n\"\n\
-                          .section .text\n\
+    fn generate_code(&mut self, prog: &IrProgram) -> String {
+        let asm_header = ".section .text\n\
                           .globl main\n";
         let mut code = asm_header.to_string();
-        for function in functions {
+        for function in prog.functions.iter() {
             code.push_str(&self.generate_code_for_function(function));
         }
 
+        if !self.error_sites.is_empty() {
+            code.push_str(&self.get_runtime_trap_code());
+        }
+
         let mut complete_code = String::new();
         complete_code.push_str(".section .rodata\n");
-        for (st, label) in self.string_to_label.iter() {
+        for (label, string) in prog.strings.iter() {
             complete_code.push_str(&format!("{}:\n\
-                                            .string {}\n", label, st));
+                                            .string {}\n", label, string));
         }
         complete_code.push_str(&code);
 
@@ -20,12 +20,15 @@ for d in subdirs:
         path = os.path.join(test_dir, d, t)
         fd = open(path, "r")
         ln = fd.readline()
+        # An optional second line gives options for the compiler
+        flags = fd.readline().strip()
         fd.close()
+        flags = flags[len("// FLAGS:"):] if flags.startswith("// FLAGS:") else ""
 
         ln = ln.replace("// ", "").replace(";", "")
         expected_output = ln.strip()
 
-        compiler = "cargo run {0}".format(path)
+        compiler = "cargo run {0} {1}".format(path, flags)
 
         compiler_output = get_program_output(compiler)
 
@@ -57,6 +60,18 @@ for d in subdirs:
                 continue
 
             os.system("./build.sh")
+
+            # The program should stop with the given exit code
+            if expected_output.startswith("EXIT"):
+                expected_code = int(expected_output.split(' ')[1])
+                code = subprocess.call("./a.out", shell=True)
+                if code != expected_code:
+                    errs.append("ERROR at {0}: Expected exit code {1}. Got {2}"
+                                .format(path, expected_code, code))
+                else:
+                    tests_passed += 1
+                continue
+
             # Run it with no buffering on stdout (so we get whatever it prints)
             output = get_program_output("./a.out")
 
@@ -0,0 +1,25 @@
+// 13
+// FLAGS: --dump-cfg
+int count_up (int limit)
+{
+    int x = 0;
+    int y = 0;
+    int* p = &y;
+    while (x < limit) {
+        x = x + 1;
+        if (x == 100) {
+            return 0;
+        } else {
+            *p = y + x;
+        }
+    }
+    unsafe {
+        y = y + 3;
+    }
+    return y;
+}
+
+int main (int arg)
+{
+    print count_up(4);
+}
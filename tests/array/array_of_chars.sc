@@ -0,0 +1,15 @@
+// 3
+owned_array(char) make_word(int arg) {
+    owned_array(char) word = new_array(char, 3);
+    word[0] = 'a';
+    word[1] = 'b';
+    word[2] = 'c';
+    return word;
+}
+
+int main(int arg) {
+    owned_array(char) word = make_word(0);
+    owned_array(char) moved = word;
+    print len(moved);
+    return 0;
+}
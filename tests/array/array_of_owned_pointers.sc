@@ -0,0 +1,5 @@
+// ERROR ownership
+int main(int arg) {
+    owned_array(owned_pointer(int)) a = new_array(owned_pointer(int), 3);
+    return 0;
+}
@@ -0,0 +1,7 @@
+// EXIT 73
+int main(int arg) {
+    // Too big for calloc, which returns NULL
+    owned_array(int) a = new_array(int, 1000000000);
+    a[3] = 1;
+    return 0;
+}
@@ -0,0 +1,6 @@
+// ERROR typechecker
+int main(int arg) {
+    int* p = allocate(12);
+    print p[0];
+    return 0;
+}
@@ -0,0 +1,11 @@
+// EXIT 73
+int main(int arg) {
+    owned_array(int) a = new_array(int, 3);
+    int i = 0;
+    while i <= 3 {
+        a[i] = i;
+        i = i + 1;
+    }
+    print a[0];
+    return 0;
+}
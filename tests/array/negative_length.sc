@@ -0,0 +1,8 @@
+// EXIT 73
+int main(int arg) {
+    // There's no unary minus, so make a negative length at runtime
+    int n = arg - 5;
+    owned_array(int) a = new_array(int, n);
+    a[3] = 1;
+    return 0;
+}
@@ -0,0 +1,18 @@
+// 45
+int main(int arg) {
+    owned_array(int) a = new_array(int, 10);
+    int i = 0;
+    while i < len(a) {
+        a[i] = i;
+        i = i + 1;
+    }
+
+    int sum = 0;
+    i = 0;
+    while i < len(a) {
+        sum = sum + a[i];
+        i = i + 1;
+    }
+    print sum;
+    return 0;
+}
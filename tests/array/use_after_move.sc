@@ -0,0 +1,7 @@
+// ERROR ownership
+int main(int arg) {
+    owned_array(int) a = new_array(int, 3);
+    owned_array(int) b = a;
+    print a[0];
+    return 0;
+}
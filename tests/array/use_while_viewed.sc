@@ -0,0 +1,8 @@
+// ERROR ownership
+int main(int arg) {
+    owned_array(int) a = new_array(int, 3);
+    array_view(int) v = a[0..3];
+    owned_array(int) b = a;
+    print v[0];
+    return 0;
+}
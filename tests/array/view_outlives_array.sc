@@ -0,0 +1,10 @@
+// ERROR typechecker
+array_view(int) first_two(int arg) {
+    owned_array(int) a = new_array(int, 3);
+    return a[0..2];
+}
+
+int main(int arg) {
+    print len(first_two(0));
+    return 0;
+}
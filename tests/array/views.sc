@@ -0,0 +1,23 @@
+// 12
+int sum(array_view(int) v) {
+    int total = 0;
+    int i = 0;
+    while i < len(v) {
+        total = total + v[i];
+        i = i + 1;
+    }
+    return total;
+}
+
+int main(int arg) {
+    owned_array(int) a = new_array(int, 6);
+    int i = 0;
+    while i < 6 {
+        a[i] = i;
+        i = i + 1;
+    }
+
+    // 2 + 3 + 4, then 3
+    print sum(a[2..5]) + sum(a[2..5][1..2]);
+    return 0;
+}
@@ -0,0 +1,6 @@
+// 7
+int main(int arg) {
+    int x = (arg + 20) / 3;
+    print x;
+    return 0;
+}
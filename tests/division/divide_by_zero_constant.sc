@@ -0,0 +1,6 @@
+// ERROR typechecker
+int main(int arg) {
+    int x = 10 / 0;
+    print x;
+    return 0;
+}
@@ -0,0 +1,6 @@
+// ERROR typechecker
+int main(int arg) {
+    int x = arg / (2 - 2);
+    print x;
+    return 0;
+}
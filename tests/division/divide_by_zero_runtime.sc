@@ -0,0 +1,9 @@
+// EXIT 71
+int divide(int a, int b) {
+    return a / b;
+}
+
+int main(int arg) {
+    print divide(10, arg - 1);
+    return 0;
+}
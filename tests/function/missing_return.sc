@@ -0,0 +1,9 @@
+// ERROR typechecker
+int double(int x) {
+    int y = x * 2;
+}
+
+int main(int arg) {
+    print double(3);
+    return 0;
+}
@@ -0,0 +1,15 @@
+// ERROR typechecker
+int sign(int x) {
+    if x > 0 {
+        return 1;
+    } else {
+        if x < 0 {
+            return 0 - 1;
+        }
+    }
+}
+
+int main(int arg) {
+    print sign(3);
+    return 0;
+}
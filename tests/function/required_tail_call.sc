@@ -0,0 +1,14 @@
+// 55
+// FLAGS: --require-tail-call
+
+int sum(int n, int total) {
+    if n == 0 {
+        return total;
+    }
+    return sum(n - 1, total + n);
+}
+
+int main(int arg) {
+    print sum(10, 0);
+    return 0;
+}
@@ -0,0 +1,16 @@
+// ERROR tail_call
+// FLAGS: --require-tail-call
+
+// The owned_pointer has to be freed after the call returns
+int count(int n) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    if n == 0 {
+        return 0;
+    }
+    return count(n - 1);
+}
+
+int main(int arg) {
+    print count(10);
+    return 0;
+}
@@ -0,0 +1,17 @@
+// 1
+int sign(int x) {
+    if x > 0 {
+        return 1;
+    } else {
+        if x < 0 {
+            return 0 - 1;
+        } else {
+            return 0;
+        }
+    }
+}
+
+int main(int arg) {
+    print sign(3);
+    return 0;
+}
@@ -0,0 +1,11 @@
+// 5
+int five(int x) {
+    return 5;
+    x = x + 1;
+    print x;
+}
+
+int main(int arg) {
+    print five(1);
+    return 0;
+}
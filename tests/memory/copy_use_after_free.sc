@@ -0,0 +1,13 @@
+// ERROR free_checker
+struct A {
+    int x;
+}
+
+int main(int arg) {
+    A* a = allocate(4);
+    A* b = a;
+    free(a);
+
+    (*b).x = 3;
+    return 0;
+}
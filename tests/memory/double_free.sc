@@ -0,0 +1,10 @@
+// ERROR free_checker
+int main(int arg) {
+    int* p = allocate(4);
+    if arg > 0 {
+        *p = 1;
+    }
+    free(p);
+    free(p);
+    return 0;
+}
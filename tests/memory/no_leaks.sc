@@ -0,0 +1,26 @@
+// 10 20
+// FLAGS: --warn-leaks
+struct Node {
+    int val;
+    Node* next;
+}
+
+Node* push(Node* head, int val) {
+    Node* n = allocate(8);
+    (*n).val = val;
+    (*n).next = head;
+    return n;
+}
+
+int main(int arg) {
+    Node* Null = 0;
+    Node* head = push(Null, 10);
+    head = push(head, 20);
+
+    Node* second = (*head).next;
+    printf("%d %d\n", (*second).val, (*head).val);
+
+    free(second);
+    free(head);
+    return 0;
+}
@@ -4,14 +4,18 @@ int main(int arg) {
     
     int i = 0;
     while i < 10 {
-        *(x + i) = i;
+        unsafe {
+            *(x + i) = i;
+        }
         i = i + 1;
     }
 
     i = 0;
     int sum = 0;
     while i < 10 {
-        sum = sum + *(x + i);
+        unsafe {
+            sum = sum + *(x + i);
+        }
         i = i + 1;
     }
 
@@ -4,11 +4,13 @@ int main(int arg) {
     int* x = allocate(4 * sz);
 
     *x = 123;
-    *(x + 1) = 456;
+    unsafe {
+        *(x + 1) = 456;
 
-    int* y = x + 1;
+        int* y = x + 1;
 
-    printf("%d\n", *(y - 1));
+        printf("%d\n", *(y - 1));
+    }
 
     free(x);
 
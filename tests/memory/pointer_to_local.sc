@@ -0,0 +1,25 @@
+// 16
+struct Holder {
+    int* p;
+}
+
+int main(int arg) {
+    int x = 5;
+    int* p;
+    Holder h;
+
+    if 1 == 1 {
+        // Pointing at something which lives longer than us is fine
+        int* q = &x;
+        p = q;
+        h.p = &x;
+
+        int y = 6;
+        int* r = &y;
+        *r = *r + *q;
+        x = y;
+    }
+
+    print x + *p - *(h.p) + 5;
+    return 0;
+}
@@ -0,0 +1,20 @@
+// ERROR typechecker
+// t only points at y from the third iteration on
+int main(int arg) {
+    int x = 1;
+    int* r = &x;
+    if 1 == 1 {
+        int y = 2;
+        int* s = &x;
+        int* t = &x;
+        int i = 0;
+        while i < 3 {
+            r = t;
+            t = s;
+            s = &y;
+            i = i + 1;
+        }
+    }
+    print *r;
+    return 0;
+}
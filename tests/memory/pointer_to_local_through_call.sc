@@ -0,0 +1,14 @@
+// ERROR typechecker
+int* id(int* p) {
+    return p;
+}
+
+int main(int arg) {
+    int* q;
+    if 1 == 1 {
+        int y = 6;
+        q = id(&y);
+    }
+    print *q;
+    return 0;
+}
@@ -0,0 +1,19 @@
+// 9
+int main(int arg) {
+    int* p = allocate(4);
+    *p = 7;
+    print *p;
+    free(p);
+
+    p = allocate(4);
+    *p = 9;
+    print *p;
+
+    int* q = p;
+    if arg > 100 {
+        free(q);
+        return 1;
+    }
+    free(p);
+    return 0;
+}
@@ -0,0 +1,17 @@
+// ERROR typechecker
+// s may still point at y after the if
+int main(int arg) {
+    int x = 5;
+    int* q = &x;
+    int* r;
+    if 1 == 1 {
+        int y = 6;
+        int* s = &y;
+        if arg > 1 {
+            s = q;
+        }
+        r = s;
+    }
+    print *r;
+    return 0;
+}
@@ -0,0 +1,16 @@
+// 11
+// s no longer points at y once it's given q, so r can have it
+int main(int arg) {
+    int x = 5;
+    int* q = &x;
+    int* r;
+    if 1 == 1 {
+        int y = 6;
+        int* s = &y;
+        x = x + *s;
+        s = q;
+        r = s;
+    }
+    print *r;
+    return 0;
+}
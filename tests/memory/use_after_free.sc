@@ -0,0 +1,9 @@
+// ERROR free_checker
+int main(int arg) {
+    int* p = allocate(4);
+    *p = 5;
+    free(p);
+
+    print *p;
+    return 0;
+}
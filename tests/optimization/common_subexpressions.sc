@@ -0,0 +1,55 @@
+// 40 12 9 21
+// FLAGS: -O1
+struct Node {
+    int v;
+    Node* next;
+}
+
+// Nothing stores between the two loads of the field
+int twice_next(Node* p) {
+    Node* a = (*p).next;
+    Node* b = (*p).next;
+    return (*a).v + (*b).v;
+}
+
+// The store through q may change what p points to, so it's loaded again
+int store_between(int* p, int* q) {
+    int before = *p;
+    *q = 5;
+    return before + *p;
+}
+
+int increment(int* p) {
+    *p = *p + 1;
+    return 0;
+}
+
+// So may the call
+int call_between(int* p) {
+    int before = *p;
+    increment(p);
+    return before + *p;
+}
+
+// a * b is computed again after a changes
+int reassigned(int a, int b) {
+    int x = a * b;
+    a = a + 1;
+    int y = a * b;
+    return x + y + a * b - 3;
+}
+
+int main(int arg) {
+    Node last;
+    last.v = 20;
+    last.next = 0;
+    Node first;
+    first.v = 1;
+    first.next = &last;
+
+    int x = 7;
+    int y = 4;
+    printf("%d %d %d %d\n", twice_next(&first), store_between(&x, &x),
+           call_between(&y), reassigned(2, 3));
+    return 0;
+}
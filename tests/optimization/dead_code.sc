@@ -0,0 +1,30 @@
+// 12
+// FLAGS: -O1
+
+int answer(int x) {
+    return x * 2;
+    print 99;
+    x = x + 1;
+}
+
+int main(int arg) {
+    int unused = 5 * arg;
+    int kept = 3;
+    int* p = &kept;
+    if 0 {
+        print 100;
+    }
+    if 1 {
+        unused = 7;
+    } else {
+        print 200;
+    }
+    while 0 {
+        print 300;
+    }
+    // Stores through pointers and calls are kept even if unused
+    *p = 6;
+    int ignored = answer(1);
+    print answer(kept);
+    return 0;
+}
@@ -0,0 +1,7 @@
+// EXIT 70
+// FLAGS: -O1 --overflow-checks
+int main (int arg)
+{
+    int big = 2147483647;
+    print big + 1;
+}
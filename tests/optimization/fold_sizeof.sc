@@ -0,0 +1,18 @@
+// 12
+// FLAGS: -O1
+struct Pair {
+    int first;
+    int second;
+}
+
+int main(int arg) {
+    Pair* p = allocate(8 * 3);
+    int index = 2;
+    unsafe {
+        Pair* q = p + index;
+        (*q).second = 12;
+        print (*(p + 2)).second;
+    }
+    free(p);
+    return 0;
+}
@@ -0,0 +1,8 @@
+// -2147483648
+// FLAGS: -O1
+int main (int arg)
+{
+    int big = 2147483647;
+    int x = big + 1;
+    print x;
+}
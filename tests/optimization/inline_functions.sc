@@ -0,0 +1,35 @@
+// 7 3 10 -1 20
+// FLAGS: -O1
+
+// Returns from the middle, which becomes a jump past the inlined body
+int clamp(int x, int low, int high) {
+    if x < low {
+        return low;
+    }
+    if x > high {
+        return high;
+    }
+    return x;
+}
+
+// Too big to be inlined without being marked inline
+inline int sign_sum(int x, int y) {
+    int result = 0;
+    if x > 0 { result = result + 1; }
+    if x < 0 { result = result - 1; }
+    if y > 0 { result = result + 1; }
+    if y < 0 { result = result - 1; }
+    while result > 1 { result = result - 1; }
+    while result < 0 - 1 { result = result + 1; }
+    return result;
+}
+
+int main(int arg) {
+    // The parameters have the same names as these, but mustn't change them
+    int x = 7;
+    int low = 3;
+    int high = 10;
+    int total = clamp(x, low, high) + clamp(0, low, high) + clamp(99, low, high);
+    printf("%d %d %d %d %d\n", x, low, high, sign_sum(0 - x, 0), total);
+    return 0;
+}
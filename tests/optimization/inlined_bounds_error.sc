@@ -0,0 +1,13 @@
+// Array index out of bounds in get at line 6
+// FLAGS: -O1
+// get is too small to be worth calling, but inlining it would make its
+// bounds error name main instead
+int get(array_view(int) a, int i) {
+    return a[i];
+}
+
+int main(int arg) {
+    owned_array(int) a = new_array(int, 4);
+    print get(a[0..len(a)], 4);
+    return 0;
+}
@@ -0,0 +1,86 @@
+// 12 5 7 0 36 0
+// FLAGS: -O1
+struct Pair {
+    int a;
+    int b;
+}
+
+// The limit is loaded and computed in the condition, and can be moved out
+// of the loop
+int count_to(Pair* p, int n) {
+    int i = 0;
+    while i < (*p).a + n {
+        i = i + 1;
+    }
+    return i;
+}
+
+// The store changes what the condition loads, so it has to stay in it
+int store_in_loop(Pair* p) {
+    int i = 0;
+    while i < (*p).a {
+        (*p).a = (*p).a - 1;
+        i = i + 1;
+    }
+    return i;
+}
+
+// The first iteration reads the value from before the loop
+int read_before_assigned(int n) {
+    int x = 7;
+    int last = 0;
+    int i = 0;
+    while i < n {
+        last = x;
+        x = n * 2;
+        i = i + 1;
+    }
+    return last;
+}
+
+// If the loop doesn't run, the value from before it is kept
+int not_run(int n, int k) {
+    int x = 0;
+    int i = 0;
+    while i < n {
+        x = k * 3;
+        i = i + 1;
+    }
+    return x;
+}
+
+int nested(int n, int m) {
+    int total = 0;
+    int i = 0;
+    while i < n {
+        int j = 0;
+        while j < m {
+            total = total + n * m;
+            j = j + 1;
+        }
+        i = i + 1;
+    }
+    return total;
+}
+
+// The division mustn't happen if the loop doesn't run
+int divide_in_loop(int x, int y, int n) {
+    int q = 0;
+    int i = 0;
+    while i < n {
+        q = x / y;
+        i = i + 1;
+    }
+    return q;
+}
+
+int main(int arg) {
+    Pair p;
+    p.a = 10;
+    p.b = 0;
+    int counted = count_to(&p, 2);
+    int stored = store_in_loop(&p);
+    printf("%d %d %d %d %d %d\n", counted, stored, read_before_assigned(1),
+           not_run(0, 5), nested(2, 3), divide_in_loop(1, 0, 0));
+    return 0;
+}
@@ -0,0 +1,17 @@
+// 27
+// FLAGS: -O1
+int main (int arg)
+{
+    int a = 3;
+    int b = 4;
+    int i = 0;
+    if (arg > 100) {
+        b = 5;
+    }
+    while (i < 2) {
+        a = a + b;
+        i = i + 1;
+    }
+    int c = a * 3 - 6;
+    print c;
+}
@@ -0,0 +1,7 @@
+// EXIT 71
+// FLAGS: -O1
+int main (int arg)
+{
+    int zero = 0;
+    print 10 / zero;
+}
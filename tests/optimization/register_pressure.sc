@@ -0,0 +1,30 @@
+// 1 2 3 4 5 6 7 28 25
+// FLAGS: -O1
+int sum(int a, int b, int c, int d, int e, int f, int g) {
+    return a + b + c + d + e + f + g;
+}
+
+int main(int arg) {
+    int a = 0;
+    int b = 0;
+    int c = 0;
+    int d = 0;
+    int e = 0;
+    int f = 0;
+    int g = 0;
+    int i = 0;
+    while i < 7 {
+        i = i + 1;
+        if i == 1 { a = i; }
+        if i == 2 { b = i; }
+        if i == 3 { c = i; }
+        if i == 4 { d = i; }
+        if i == 5 { e = i; }
+        if i == 6 { f = i; }
+        if i == 7 { g = i; }
+    }
+    int total = sum(a, b, c, d, e, f, g);
+    int last = sum(g, g, 1, 1, 1, 1, 1) + a + b + c;
+    printf("%d %d %d %d %d %d %d %d %d\n", a, b, c, d, e, f, g, total, last);
+    return 0;
+}
@@ -0,0 +1,35 @@
+// 42 42
+// FLAGS: -O1
+
+// The callee's frame would replace the caller's, and with it x. The
+// callers are recursive so they aren't inlined.
+int read(int* p, int n) {
+    int a = 1001;
+    int* q = &a;
+    if n > 0 {
+        return read(p, n - 1);
+    }
+    return *p + *q - 1001;
+}
+
+int pass_address(int n, int m) {
+    if n > 0 {
+        return pass_address(n - 1, m);
+    }
+    int x = 42;
+    return read(&x, 0);
+}
+
+int pass_pointer(int n, int m) {
+    if n > 0 {
+        return pass_pointer(n - 1, m);
+    }
+    int x = 42;
+    int* q = &x;
+    return read(q, 0);
+}
+
+int main(int arg) {
+    printf("%d %d\n", pass_address(1, 2), pass_pointer(1, 2));
+    return 0;
+}
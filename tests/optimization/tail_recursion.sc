@@ -0,0 +1,30 @@
+// 10000000 1
+// FLAGS: -O1
+
+// Would need ten million frames without tail calls
+int count(int n, int total) {
+    if n == 0 {
+        return total;
+    }
+    return count(n - 1, total + 1);
+}
+
+// Siblings taking their arguments in a different order
+int is_even(int n, int unused) {
+    if n == 0 {
+        return 1;
+    }
+    return is_odd(unused, n - 1);
+}
+
+int is_odd(int unused, int n) {
+    if n == 0 {
+        return 0;
+    }
+    return is_even(n - 1, unused);
+}
+
+int main(int arg) {
+    printf("%d %d\n", count(10000000, 0), is_even(1000000, 0));
+    return 0;
+}
@@ -0,0 +1,8 @@
+// EXIT 70
+// FLAGS: --overflow-checks
+int main(int arg) {
+    int big = 2147483647;
+    int x = big + arg;
+    print x;
+    return 0;
+}
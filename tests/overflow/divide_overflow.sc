@@ -0,0 +1,7 @@
+// EXIT 70
+// FLAGS: --overflow-checks
+int main(int arg) {
+    int min = 0 - 2147483647 - 1;
+    print min / (0 - arg);
+    return 0;
+}
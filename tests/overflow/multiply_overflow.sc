@@ -0,0 +1,10 @@
+// EXIT 70
+// FLAGS: --overflow-checks
+int square(int x) {
+    return x * x;
+}
+
+int main(int arg) {
+    print square(65536);
+    return 0;
+}
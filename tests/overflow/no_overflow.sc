@@ -0,0 +1,8 @@
+// 2147483647
+// FLAGS: --overflow-checks
+int main(int arg) {
+    int x = 2147483646 + arg;
+    int y = (x - 7) * 1 / 1 + 7;
+    print y;
+    return 0;
+}
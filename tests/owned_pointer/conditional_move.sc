@@ -0,0 +1,18 @@
+// 3
+int main(int arg) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    *p = 3;
+
+    int result = 0;
+    if arg == 1 {
+        // p is moved only in this branch, so it's freed at the end of main
+        // only if it wasn't moved
+        owned_pointer(int) q = p;
+        result = *q;
+    } else {
+        result = *p;
+    }
+
+    print result;
+    return 0;
+}
@@ -0,0 +1,15 @@
+// 30
+int main(int arg) {
+    int i = 0;
+    int sum = 0;
+    while i < 3 {
+        // p is freed at the end of every iteration
+        owned_pointer(int) p = alloc_owned_int(1);
+        *p = 10;
+        sum = sum + *p;
+        i = i + 1;
+    }
+
+    print sum;
+    return 0;
+}
@@ -0,0 +1,11 @@
+// ERROR ownership
+int main(int arg) {
+    owned_pointer(int) p = alloc_owned_int(1);
+
+    free(p);
+
+    // Double free
+    free(p);
+
+    return 0;
+}
@@ -0,0 +1,20 @@
+// 12
+int main(int arg) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    *p = 5;
+
+    // q now owns the memory. Only q will get freed.
+    owned_pointer(int) q = p;
+    *q = *q + 7;
+
+    // Overwriting p frees nothing, since it was moved from
+    p = alloc_owned_int(1);
+    *p = *q;
+
+    // Overwriting q frees its old value first
+    q = alloc_owned_int(1);
+    *q = 0;
+
+    print *p + *q;
+    return 0;
+}
@@ -0,0 +1,13 @@
+// ERROR ownership
+int main(int arg) {
+    owned_pointer(int) p = alloc_owned_int(1);
+
+    int i = 0;
+    while i < 2 {
+        // The second iteration would move p again
+        owned_pointer(int) q = p;
+        i = i + 1;
+    }
+
+    return 0;
+}
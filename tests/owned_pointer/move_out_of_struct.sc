@@ -0,0 +1,14 @@
+// ERROR ownership
+struct Box {
+    owned_pointer(int) value;
+}
+
+int main(int arg) {
+    Box b;
+    b.value = alloc_owned_int(1);
+
+    // b.value would be left dangling
+    owned_pointer(int) p = b.value;
+
+    return 0;
+}
@@ -0,0 +1,21 @@
+// 9
+struct Box {
+    owned_pointer(int) value;
+}
+
+owned_pointer(int) make_int(int x) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    *p = x;
+    return p;
+}
+
+int main(int arg) {
+    Box b;
+    b.value = make_int(4);
+
+    // The 4 gets freed before we store the 9
+    b.value = make_int(*(b.value) + 5);
+
+    print *(b.value);
+    return 0;
+}
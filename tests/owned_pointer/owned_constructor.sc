@@ -0,0 +1,32 @@
+// 5 10 15
+struct A {
+    int x;
+    int y;
+}
+
+// The caller gets ownership of the A we allocate here
+owned_pointer(A) make_a(int x) {
+    owned_pointer(A) a = allocate(8);
+    (*a).x = x;
+    (*a).y = x * 2;
+    return a;
+}
+
+// We take ownership of a, so it's freed when we return
+int sum_a(owned_pointer(A) a) {
+    return (*a).x + (*a).y;
+}
+
+int main(int arg) {
+    owned_pointer(A) a = make_a(5);
+    int x = (*a).x;
+    int y = (*a).y;
+
+    // Nobody uses this one, so it's freed right away
+    make_a(100);
+
+    int sum = sum_a(a);
+
+    printf("%d %d %d\n", x, y, sum);
+    return 0;
+}
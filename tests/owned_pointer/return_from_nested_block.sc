@@ -0,0 +1,19 @@
+// 7
+int get_value(int x) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    *p = x;
+
+    if x > 5 {
+        owned_pointer(int) q = alloc_owned_int(1);
+        *q = *p;
+        // Both p and q have to be freed here
+        return *q;
+    }
+
+    return 0;
+}
+
+int main(int arg) {
+    print get_value(7);
+    return 0;
+}
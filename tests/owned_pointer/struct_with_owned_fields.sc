@@ -0,0 +1,48 @@
+// 6
+struct Node {
+    int val;
+    owned_pointer(Node) next;
+}
+
+struct List {
+    owned_pointer(Node) head;
+    int size;
+}
+
+// Make a new node which owns the rest of the list
+owned_pointer(Node) make_node(int val, owned_pointer(Node) next) {
+    owned_pointer(Node) node = allocate(8);
+    (*node).val = val;
+    (*node).next = next;
+    return node;
+}
+
+int push_front(List* list, int val) {
+    // The old head is moved out of the list, but it's replaced in the same
+    // statement
+    (*list).head = make_node(val, (*list).head);
+    (*list).size = (*list).size + 1;
+    return 0;
+}
+
+int main(int arg) {
+    // All the nodes are freed by the generated __drop_List when list goes
+    // out of scope
+    List list;
+    list.size = 0;
+
+    push_front(&list, 1);
+    push_front(&list, 2);
+    push_front(&list, 3);
+
+    int sum = 0;
+    Node* Null = 0;
+    Node* iter = &*(list.head);
+    while iter != Null {
+        sum = sum + (*iter).val;
+        iter = &*((*iter).next);
+    }
+
+    print sum;
+    return 0;
+}
@@ -0,0 +1,13 @@
+// ERROR ownership
+owned_pointer(int) make_int(int x) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    *p = x;
+    return p;
+}
+
+int main(int arg) {
+    // Nothing owns the result of make_int, so we could never free it
+    print *(make_int(3));
+
+    return 0;
+}
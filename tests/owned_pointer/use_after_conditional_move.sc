@@ -0,0 +1,13 @@
+// ERROR ownership
+int main(int arg) {
+    owned_pointer(int) p = alloc_owned_int(1);
+
+    if arg == 2 {
+        owned_pointer(int) q = p;
+    }
+
+    // p may have been moved
+    print *p;
+
+    return 0;
+}
@@ -0,0 +1,10 @@
+// ERROR ownership
+int main(int arg) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    owned_pointer(int) q = p;
+
+    // p doesn't own anything anymore
+    *p = 5;
+
+    return 0;
+}
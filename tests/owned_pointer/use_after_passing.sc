@@ -0,0 +1,16 @@
+// ERROR ownership
+int consume(owned_pointer(int) p) {
+    return *p;
+}
+
+int main(int arg) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    *p = 3;
+
+    // p is moved into consume
+    consume(p);
+
+    print *p;
+
+    return 0;
+}
@@ -0,0 +1,11 @@
+// ERROR ownership
+int main(int arg) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    ref(int) r = &*p;
+
+    // The old value of p, which r points to, is freed
+    p = alloc_owned_int(2);
+    print *r;
+
+    return 0;
+}
@@ -0,0 +1,28 @@
+// 15
+struct Point {
+    int x;
+    int y;
+}
+
+int sum(ref(Point) p) {
+    return (*p).x + (*p).y;
+}
+
+int scale(mut_ref(Point) p, int factor) {
+    (*p).x = (*p).x * factor;
+    (*p).y = (*p).y * factor;
+    return 0;
+}
+
+int main(int arg) {
+    Point p;
+    p.x = 2;
+    p.y = 3;
+
+    scale(&p, 3);
+
+    // A mut_ref can be used as a ref
+    mut_ref(Point) r = &p;
+    print sum(r);
+    return 0;
+}
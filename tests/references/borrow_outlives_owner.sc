@@ -0,0 +1,13 @@
+// ERROR typechecker
+int main(int arg) {
+    ref(int) r;
+
+    if arg == 1 {
+        owned_pointer(int) p = alloc_owned_int(1);
+        *p = 3;
+        r = &*p;
+    }
+
+    print *r;
+    return 0;
+}
@@ -0,0 +1,20 @@
+// 42
+int add_to(mut_ref(int) target, ref(int) amount) {
+    *target = *target + *amount;
+    return 0;
+}
+
+int main(int arg) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    *p = 40;
+    int two = 2;
+
+    if 1 == 1 {
+        mut_ref(int) r = &*p;
+        add_to(r, &two);
+    }
+
+    // r is gone, so p can be used again
+    print *p;
+    return 0;
+}
@@ -0,0 +1,10 @@
+// ERROR ownership
+int main(int arg) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    ref(int) r = &*p;
+
+    free(p);
+    print *r;
+
+    return 0;
+}
@@ -0,0 +1,15 @@
+// ERROR ownership
+int consume(owned_pointer(int) p) {
+    return *p;
+}
+
+int main(int arg) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    ref(int) r = &*p;
+
+    // consume frees what r points to
+    consume(p);
+    print *r;
+
+    return 0;
+}
@@ -0,0 +1,9 @@
+// ERROR typechecker
+struct Holder {
+    // The struct could outlive what this is borrowed from
+    ref(int) r;
+}
+
+int main(int arg) {
+    return 0;
+}
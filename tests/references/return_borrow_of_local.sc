@@ -0,0 +1,13 @@
+// ERROR typechecker
+ref(int) get(int x) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    *p = x;
+
+    // p is freed when we return
+    return &*p;
+}
+
+int main(int arg) {
+    print *(get(3));
+    return 0;
+}
@@ -0,0 +1,15 @@
+// 7
+ref(int) larger(ref(int) a, ref(int) b) {
+    if *a > *b {
+        return a;
+    }
+    return b;
+}
+
+int main(int arg) {
+    int x = 7;
+    int y = 3;
+    ref(int) r = larger(&x, &y);
+    print *r;
+    return 0;
+}
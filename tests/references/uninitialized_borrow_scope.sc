@@ -0,0 +1,25 @@
+// 7
+// A mut_ref declared without a value stops borrowing when it goes out of
+// scope, and the borrow doesn't carry over into the next function
+int first(int arg) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    if arg > 0 {
+        mut_ref(int) r;
+        r = &*p;
+        *r = 5;
+    }
+    *p = *p + 2;
+    return *p;
+}
+
+int second(int arg) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    *p = arg;
+    return *p;
+}
+
+int main(int arg) {
+    print first(1);
+    print second(7);
+    return 0;
+}
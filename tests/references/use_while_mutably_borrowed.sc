@@ -0,0 +1,11 @@
+// ERROR ownership
+int main(int arg) {
+    owned_pointer(int) p = alloc_owned_int(1);
+    mut_ref(int) r = &*p;
+
+    // r is still around
+    *p = 4;
+    *r = 5;
+
+    return 0;
+}
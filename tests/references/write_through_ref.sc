@@ -0,0 +1,12 @@
+// ERROR typechecker
+int set(ref(int) r) {
+    // Only mut_refs can be written through
+    *r = 5;
+    return 0;
+}
+
+int main(int arg) {
+    int x = 1;
+    set(&x);
+    return 0;
+}
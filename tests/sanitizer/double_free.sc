@@ -0,0 +1,13 @@
+// EXIT 72
+// FLAGS: --sanitize=memory
+int release(int* p) {
+    free(p);
+    return 0;
+}
+
+int main(int arg) {
+    int* x = allocate(4);
+    release(x);
+    free(x);
+    return 0;
+}
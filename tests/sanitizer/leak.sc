@@ -0,0 +1,8 @@
+// EXIT 72
+// FLAGS: --sanitize=memory
+int main(int arg) {
+    int* x = allocate(4);
+    *x = 3;
+    print *x;
+    return 0;
+}
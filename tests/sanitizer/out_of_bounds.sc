@@ -0,0 +1,10 @@
+// EXIT 72
+// FLAGS: --sanitize=memory
+int main(int arg) {
+    int* x = allocate(4 * 2);
+    unsafe {
+        *(x + 2) = 5;
+    }
+    free(x);
+    return 0;
+}
@@ -0,0 +1,24 @@
+// 7
+// FLAGS: --sanitize=memory
+int main(int arg) {
+    int* x = allocate(4 * 2);
+    *x = 3;
+    unsafe {
+        *(x + 1) = 7;
+    }
+    print *x;
+
+    // Freed automatically, through the sanitizer too
+    owned_pointer(int) p = alloc_owned_int(1);
+    *p = 10;
+    print *p;
+
+    int local = 0;
+    int* l = &local;
+    unsafe {
+        *l = *(x + 1);
+    }
+    print local;
+    free(x);
+    return 0;
+}
@@ -0,0 +1,13 @@
+// 7
+int main(int arg) {
+    shared_pointer(int) a = allocate(4);
+    *a = 3;
+
+    // Both copies point to the same int, which is freed once both of them
+    // are gone
+    shared_pointer(int) b = a;
+    *b = 7;
+
+    print *a;
+    return 0;
+}
@@ -0,0 +1,6 @@
+// ERROR ownership
+int main(int arg) {
+    shared_pointer(int) a = allocate(4);
+    free(a);
+    return 0;
+}
@@ -0,0 +1,6 @@
+// ERROR typechecker
+int main(int arg) {
+    shared_pointer(int) a = allocate(8);
+    shared_pointer(int) b = a + 1;
+    return 0;
+}
@@ -0,0 +1,19 @@
+// 12
+struct Holder {
+    owned_pointer(int) value;
+}
+
+int main(int arg) {
+    shared_pointer(Holder) a = allocate(4);
+    (*a).value = alloc_owned_int(1);
+    *((*a).value) = 12;
+
+    shared_pointer(Holder) b = a;
+    if 1 {
+        shared_pointer(Holder) c = b;
+    }
+
+    // The int is freed by __drop_Holder when the last copy goes away
+    print *((*b).value);
+    return 0;
+}
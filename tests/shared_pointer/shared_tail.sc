@@ -0,0 +1,37 @@
+// 9
+struct Node {
+    int val;
+    shared_pointer(Node) next;
+}
+
+shared_pointer(Node) make_node(int val, shared_pointer(Node) next) {
+    shared_pointer(Node) node = allocate(8);
+    (*node).val = val;
+    (*node).next = next;
+    return node;
+}
+
+int sum(shared_pointer(Node) list) {
+    int total = 0;
+    Node* Null = 0;
+    Node* iter = &*list;
+    while iter != Null {
+        total = total + (*iter).val;
+        iter = &*((*iter).next);
+    }
+    return total;
+}
+
+int main(int arg) {
+    shared_pointer(Node) empty;
+    shared_pointer(Node) tail = make_node(3, empty);
+
+    // Both lists share the same tail, which is only freed after both of
+    // them are
+    shared_pointer(Node) first = make_node(1, tail);
+    shared_pointer(Node) second = make_node(2, tail);
+    tail = empty;
+
+    print sum(first) + sum(second);
+    return 0;
+}
@@ -0,0 +1,6 @@
+// 007 ab% 2a
+int main(int arg) {
+    char* s = "abc";
+    printf("%03d %.2s%% %x\n", 7, s, 42);
+    return 0;
+}
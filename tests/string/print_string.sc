@@ -2,10 +2,13 @@
 // last character is a \n (which is 10 in ascii)
 int main(int arg) {
    char* p = "hallo thar\n";
-   
-   while *p != 0 {
+
+   // p comes from pointer arithmetic after the first iteration
+   unsafe {
+      while *p != 0 {
          print *p;
          p = p + 1;
+      }
    }
 
    return 0;
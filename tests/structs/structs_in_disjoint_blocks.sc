@@ -0,0 +1,31 @@
+// 3 30 7 70
+struct Point {
+    int x;
+    int y;
+}
+
+int main(int arg) {
+    int first = 0;
+    int second = 0;
+    if arg > 0 {
+        Point p;
+        p.x = 3;
+        p.y = 30;
+        first = p.x;
+        second = p.y;
+    }
+    int third = 0;
+    int fourth = 0;
+    if arg > 0 {
+        // Gets the same place in the frame as p
+        Point q;
+        q.x = 7;
+        q.y = q.x * 10;
+        int big = 1000;
+        int* r = &big;
+        third = q.x;
+        fourth = q.y + *r - big;
+    }
+    printf("%d %d %d %d\n", first, second, third, fourth);
+    return 0;
+}
@@ -0,0 +1,20 @@
+// ERROR typechecker
+int main(int arg) {
+    // Something you should not be allowed
+    // to do:
+
+    int* p;
+
+    if 3 == 3 {
+        int y = 5;
+
+        // Should not be allowed to do this
+        p = &y;
+    }
+
+    int z = 3;
+
+    print *p;
+
+    return 0;
+}
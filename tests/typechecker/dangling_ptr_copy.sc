@@ -0,0 +1,17 @@
+// ERROR typechecker
+int main(int arg) {
+    int* p;
+
+    while arg == 1 {
+        int y = 5;
+        int* q = &y;
+
+        // q points at y, so p can't hold onto it either
+        p = q + 1;
+        arg = 0;
+    }
+
+    print *p;
+
+    return 0;
+}
@@ -0,0 +1,17 @@
+// ERROR typechecker
+struct Holder {
+    int* p;
+}
+
+int main(int arg) {
+    Holder h;
+
+    if 1 == 1 {
+        int y = 5;
+        h.p = &y;
+    }
+
+    print *(h.p);
+
+    return 0;
+}
@@ -0,0 +1,5 @@
+// ERROR typechecker
+int main(int arg) {
+    printf("%d %d\n", 1);
+    return 0;
+}
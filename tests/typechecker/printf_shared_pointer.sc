@@ -0,0 +1,6 @@
+// ERROR typechecker
+int main(int arg) {
+    shared_pointer(int) p = allocate(4);
+    printf("%p\n", p);
+    return 0;
+}
@@ -0,0 +1,5 @@
+// ERROR typechecker
+int main(int arg) {
+    printf("%f\n", 1);
+    return 0;
+}
@@ -0,0 +1,6 @@
+// ERROR typechecker
+int main(int arg) {
+    char* s = "hello";
+    printf("%d\n", s);
+    return 0;
+}
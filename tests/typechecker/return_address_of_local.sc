@@ -0,0 +1,13 @@
+// ERROR typechecker
+int* get_pointer(int arg) {
+    int x = arg;
+
+    // x is gone once we return
+    return &x;
+}
+
+int main(int arg) {
+    int* p = get_pointer(5);
+    print *p;
+    return 0;
+}
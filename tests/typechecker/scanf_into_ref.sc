@@ -0,0 +1,8 @@
+// ERROR typechecker
+// scanf writes through the pointer, which a ref can't do
+int main(int arg) {
+    int x = 0;
+    ref(int) r = &x;
+    scanf("%d", r);
+    return 0;
+}
@@ -0,0 +1,6 @@
+// ERROR typechecker
+int main(int arg) {
+    int x;
+    scanf("%d", x);
+    return 0;
+}
@@ -0,0 +1,8 @@
+// ERROR typechecker
+// Reading an address into a ref would skip the borrow checks
+int main(int arg) {
+    int x = 0;
+    ref(int) r = &x;
+    scanf("%p", &r);
+    return 0;
+}
@@ -0,0 +1,7 @@
+// ERROR typechecker
+// The shared_pointer has to be passed as &*p
+int main(int arg) {
+    shared_pointer(int) p = allocate(4);
+    scanf("%d", p);
+    return 0;
+}
@@ -0,0 +1,6 @@
+// ERROR typechecker
+int main(int arg) {
+    char c;
+    scanf("%d", &c);
+    return 0;
+}
@@ -0,0 +1,7 @@
+// ERROR typechecker
+int main(int arg) {
+    int* x = allocate(8);
+    *(x + 1) = 5;
+    free(x);
+    return 0;
+}
@@ -0,0 +1,17 @@
+// ERROR typechecker
+struct Holder {
+    int* p;
+}
+
+int main(int arg) {
+    int* x = allocate(8);
+    Holder h;
+    unsafe {
+        h.p = x + 1;
+    }
+
+    // Fields aren't tracked, so this would be dereferenced outside unsafe
+    *(h.p) = 5;
+    free(x);
+    return 0;
+}
@@ -0,0 +1,18 @@
+// 7
+int main(int arg) {
+    int* x = allocate(8);
+    int* y = x;
+    if arg < 100 {
+        unsafe {
+            y = x + 1;
+            *y = 3;
+        }
+        y = x;
+    }
+
+    // Every path gives y a value that isn't from pointer arithmetic
+    *y = 7;
+    print *x;
+    free(x);
+    return 0;
+}
@@ -0,0 +1,11 @@
+// ERROR typechecker
+unsafe int second(int* arr) {
+    return *(arr + 1);
+}
+
+int main(int arg) {
+    int* x = allocate(8);
+    print second(x);
+    free(x);
+    return 0;
+}
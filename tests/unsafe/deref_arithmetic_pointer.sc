@@ -0,0 +1,13 @@
+// ERROR typechecker
+int main(int arg) {
+    int* x = allocate(8);
+    int* y = x;
+    unsafe {
+        y = x + 1;
+    }
+
+    // y came from pointer arithmetic, so this has to be in unsafe too
+    *y = 5;
+    free(x);
+    return 0;
+}
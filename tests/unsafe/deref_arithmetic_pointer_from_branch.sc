@@ -0,0 +1,17 @@
+// ERROR typechecker
+int main(int arg) {
+    int* x = allocate(8);
+    int* y = x;
+    if arg > 100 {
+        unsafe {
+            y = x + 1;
+        }
+    } else {
+        y = x;
+    }
+
+    // y may have come from pointer arithmetic
+    *y = 5;
+    free(x);
+    return 0;
+}
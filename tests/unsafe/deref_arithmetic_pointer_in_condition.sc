@@ -0,0 +1,15 @@
+// ERROR typechecker
+int main(int arg) {
+    char* p = "abc";
+    int n = 0;
+
+    // The condition is checked again after p = p + 1
+    while *p != 0 {
+        unsafe {
+            p = p + 1;
+        }
+        n = n + 1;
+    }
+    print n;
+    return 0;
+}
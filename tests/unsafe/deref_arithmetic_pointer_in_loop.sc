@@ -0,0 +1,16 @@
+// ERROR typechecker
+int main(int arg) {
+    int* x = allocate(8);
+    int* y = x;
+    int i = 0;
+    while i < 2 {
+        // On the second iteration y comes from pointer arithmetic
+        *y = 5;
+        unsafe {
+            y = x + 1;
+        }
+        i = i + 1;
+    }
+    free(x);
+    return 0;
+}
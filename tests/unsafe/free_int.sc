@@ -0,0 +1,10 @@
+// ERROR typechecker
+// Freeing an int would turn it into a pointer, which isn't allowed even in
+// unsafe code
+int main(int arg) {
+    int address = 1234;
+    unsafe {
+        free(address);
+    }
+    return 0;
+}
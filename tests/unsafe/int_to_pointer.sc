@@ -0,0 +1,6 @@
+// ERROR typechecker
+int main(int arg) {
+    int address = 1234;
+    int* p = address;
+    return 0;
+}
@@ -0,0 +1,12 @@
+// 1
+int main(int arg) {
+    int zero = arg - arg;
+    int* p;
+    unsafe {
+        p = zero;
+    }
+
+    int* Null = 0;
+    print p == Null;
+    return 0;
+}
@@ -0,0 +1,17 @@
+// ERROR typechecker
+unsafe int* second(int* x) {
+    return x + 1;
+}
+
+int main(int arg) {
+    int* x = allocate(8);
+    int* p;
+    unsafe {
+        p = second(x);
+    }
+
+    // The caller can't tell p comes from pointer arithmetic
+    *p = 5;
+    free(x);
+    return 0;
+}
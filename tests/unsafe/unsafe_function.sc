@@ -0,0 +1,25 @@
+// 30
+unsafe int sum(int* arr, int n) {
+    int i = 0;
+    int total = 0;
+    while i < n {
+        total = total + *(arr + i);
+        i = i + 1;
+    }
+    return total;
+}
+
+int main(int arg) {
+    int* x = allocate(4 * 3);
+    int total;
+    unsafe {
+        *x = 5;
+        *(x + 1) = 10;
+        *(x + 2) = 15;
+        total = sum(x, 3);
+    }
+
+    print total;
+    free(x);
+    return 0;
+}